@@ -0,0 +1,236 @@
+use crate::matrix::Matrix;
+use crate::quaternion::Quaternion;
+use crate::tuple::Tuple;
+
+// A transform split into translation * rotation * scale. Shear is not represented, so
+// decomposing a sheared matrix loses it.
+#[derive(Debug, Copy, Clone)]
+pub struct Decomposed {
+    pub translation: Tuple,
+    pub rotation: Quaternion,
+    pub scale: Tuple,
+}
+
+impl Decomposed {
+    pub fn from_matrix(m: &Matrix) -> Self {
+        let translation =
+            Tuple::new_vector(m.get_value(0, 3), m.get_value(1, 3), m.get_value(2, 3));
+
+        let column =
+            |c: usize| Tuple::new_vector(m.get_value(0, c), m.get_value(1, c), m.get_value(2, c));
+        let mut sx = column(0).magnitude();
+        let sy = column(1).magnitude();
+        let sz = column(2).magnitude();
+        // a mirror shows up as a negative determinant; fold it into one axis
+        if column(0).dot(column(1).cross(column(2))) < 0.0 {
+            sx = -sx;
+        }
+
+        // A flattened axis has no direction of its own: the frame is completed from the
+        // other two, or left unrotated when more than one is gone.
+        let axis = |c: usize, s: f64| (s.abs() > f64::EPSILON).then(|| column(c).divide(s));
+        let axes = match (axis(0, sx), axis(1, sy), axis(2, sz)) {
+            (Some(x), Some(y), Some(z)) => [x, y, z],
+            (None, Some(y), Some(z)) => [y.cross(z), y, z],
+            (Some(x), None, Some(z)) => [x, z.cross(x), z],
+            (Some(x), Some(y), None) => [x, y, x.cross(y)],
+            _ => [
+                Tuple::new_vector(1.0, 0.0, 0.0),
+                Tuple::new_vector(0.0, 1.0, 0.0),
+                Tuple::new_vector(0.0, 0.0, 1.0),
+            ],
+        };
+        let mut rotation = Matrix::identity();
+        for (c, a) in axes.iter().enumerate() {
+            rotation.values[0][c] = a.x;
+            rotation.values[1][c] = a.y;
+            rotation.values[2][c] = a.z;
+        }
+
+        Decomposed {
+            translation,
+            rotation: Quaternion::from_matrix(&rotation),
+            scale: Tuple::new_vector(sx, sy, sz),
+        }
+    }
+
+    pub fn to_matrix(self) -> Matrix {
+        Matrix::translation(self.translation.x, self.translation.y, self.translation.z)
+            .multiply_matrix(&self.rotation.to_matrix())
+            .multiply_matrix(&Matrix::scaling(self.scale.x, self.scale.y, self.scale.z))
+    }
+
+    // translation and scale are interpolated linearly, rotation with slerp
+    pub fn interpolate(&self, other: &Self, t: f64) -> Self {
+        Decomposed {
+            translation: self
+                .translation
+                .add(other.translation.minus(self.translation).multiply(t)),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.add(other.scale.minus(self.scale).multiply(t)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    // element-wise blend of the two matrices; cheap, but rotations shrink halfway
+    Matrix,
+    // translation/rotation/scale blended separately, rotation with quaternion slerp
+    Decomposed,
+}
+
+// A transform keyframed at the start and end of a time interval, e.g. the shutter
+// interval of a camera. Outside the interval the nearest keyframe is held. Both
+// keyframes have to be invertible, since rays are taken into object space with them.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    pub start: Matrix,
+    pub end: Matrix,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub interpolation: Interpolation,
+    start_parts: Decomposed,
+    end_parts: Decomposed,
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Matrix, end: Matrix, start_time: f64, end_time: f64) -> Self {
+        if !start.is_invertible() || !end.is_invertible() {
+            panic!("Animation error. Keyframes must be invertible, so no axis may be scaled to 0!");
+        }
+        let start_parts = Decomposed::from_matrix(&start);
+        let end_parts = Decomposed::from_matrix(&end);
+        AnimatedTransform {
+            start,
+            end,
+            start_time,
+            end_time,
+            interpolation: Interpolation::Decomposed,
+            start_parts,
+            end_parts,
+        }
+    }
+
+    // a transform that doesn't move
+    pub fn fixed(m: Matrix) -> Self {
+        AnimatedTransform::new(m.clone(), m, 0.0, 0.0)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        !self.start.equals(&self.end)
+    }
+
+    pub fn at(&self, time: f64) -> Matrix {
+        if !self.is_animated() || self.end_time <= self.start_time {
+            return self.start.clone();
+        }
+        let t = ((time - self.start_time) / (self.end_time - self.start_time)).clamp(0.0, 1.0);
+        match self.interpolation {
+            Interpolation::Matrix => {
+                let mut m = Matrix::new(4, 4);
+                for r in 0..4 {
+                    for c in 0..4 {
+                        let a = self.start.get_value(r, c);
+                        let b = self.end.get_value(r, c);
+                        m.values[r][c] = a + (b - a) * t;
+                    }
+                }
+                m
+            }
+            Interpolation::Decomposed => {
+                self.start_parts.interpolate(&self.end_parts, t).to_matrix()
+            }
+        }
+    }
+
+    pub fn inverse_at(&self, time: f64) -> Matrix {
+        self.at(time).inverse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn decompose_round_trip() {
+        let m = Matrix::translation(1.0, -2.0, 3.0)
+            .multiply_matrix(&Matrix::rotation_y(0.7))
+            .multiply_matrix(&Matrix::rotation_x(-0.2))
+            .multiply_matrix(&Matrix::scaling(2.0, 0.5, 3.0));
+        let d = Decomposed::from_matrix(&m);
+        assert!(d.translation.equals(&Tuple::new_vector(1.0, -2.0, 3.0)));
+        assert!(d.scale.equals(&Tuple::new_vector(2.0, 0.5, 3.0)));
+        assert!(d.to_matrix().equals(&m));
+
+        let mirrored = Matrix::scaling(-1.0, 1.0, 1.0);
+        assert!(Decomposed::from_matrix(&mirrored)
+            .to_matrix()
+            .equals(&mirrored));
+    }
+
+    #[test]
+    fn decomposing_a_flattened_axis() {
+        let m = Matrix::translation(1.0, 0.0, 0.0)
+            .multiply_matrix(&Matrix::rotation_y(0.7))
+            .multiply_matrix(&Matrix::scaling(0.0, 2.0, 3.0));
+        let d = Decomposed::from_matrix(&m);
+        assert!(d.scale.equals(&Tuple::new_vector(0.0, 2.0, 3.0)));
+        assert!(d.to_matrix().equals(&m));
+        // halfway to a proper keyframe everything stays finite
+        let other = Decomposed::from_matrix(&Matrix::rotation_y(0.7));
+        let halfway = d.interpolate(&other, 0.5).to_matrix();
+        let expected = Matrix::translation(0.5, 0.0, 0.0)
+            .multiply_matrix(&Matrix::rotation_y(0.7))
+            .multiply_matrix(&Matrix::scaling(0.5, 1.5, 2.0));
+        assert!(halfway.equals(&expected));
+
+        let flat = Decomposed::from_matrix(&Matrix::scaling(0.0, 0.0, 1.0));
+        assert!(flat.to_matrix().equals(&Matrix::scaling(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Animation error")]
+    fn keyframes_need_to_be_invertible() {
+        AnimatedTransform::new(Matrix::identity(), Matrix::scaling(1.0, 0.0, 1.0), 0.0, 1.0);
+    }
+
+    #[test]
+    fn translation_moves_linearly() {
+        let a = AnimatedTransform::new(
+            Matrix::translation(0.0, 0.0, 0.0),
+            Matrix::translation(10.0, 0.0, 0.0),
+            0.0,
+            1.0,
+        );
+        assert!(a.at(0.25).equals(&Matrix::translation(2.5, 0.0, 0.0)));
+        // held outside the interval
+        assert!(a.at(-1.0).equals(&Matrix::translation(0.0, 0.0, 0.0)));
+        assert!(a.at(2.0).equals(&Matrix::translation(10.0, 0.0, 0.0)));
+        assert!(a
+            .inverse_at(0.5)
+            .equals(&Matrix::translation(-5.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn rotation_uses_slerp() {
+        let mut a =
+            AnimatedTransform::new(Matrix::identity(), Matrix::rotation_z(PI / 2.0), 0.0, 1.0);
+        assert!(a.at(0.5).equals(&Matrix::rotation_z(PI / 4.0)));
+
+        // plain matrix blending shrinks the object halfway through the turn
+        a.interpolation = Interpolation::Matrix;
+        let p = a.at(0.5).multiply_tuple(&Tuple::new_point(1.0, 0.0, 0.0));
+        assert!(Tuple::new_vector(p.x, p.y, p.z).magnitude() < 0.99);
+    }
+
+    #[test]
+    fn fixed_transforms() {
+        let a = AnimatedTransform::fixed(Matrix::scaling(2.0, 2.0, 2.0));
+        assert!(!a.is_animated());
+        assert!(a.at(123.0).equals(&Matrix::scaling(2.0, 2.0, 2.0)));
+    }
+}
@@ -0,0 +1,389 @@
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
+use crate::canvas::Canvas;
+use crate::matrix::Matrix;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::sampling::{self, Supersampler};
+use crate::tuple::{Color, Tuple};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    // the book's pinhole camera, optionally with a thin lens
+    Perspective,
+    // parallel rays, for technical drawings; `view_width` is in world units
+    Orthographic { view_width: f64 },
+    // equidistant fisheye: the angle off the view axis grows linearly with the distance
+    // from the image centre, reaching field_of_view / 2 at the edge of the shorter side
+    Fisheye,
+    // full 360 x 180 degree panorama, meant for a 2:1 canvas
+    Equirectangular,
+}
+
+pub struct Camera {
+    pub projection: Projection,
+    pub hsize: usize,
+    pub vsize: usize,
+    pub field_of_view: f64,
+    transform: Matrix,
+    inverse_transform: Matrix,
+    half_width: f64,
+    half_height: f64,
+    pub pixel_size: f64,
+    // Thin lens. An aperture of 0 is a pinhole: everything is in focus.
+    pub aperture: f64,
+    // distance from the eye to the plane that is in perfect focus
+    pub focal_distance: f64,
+    // 0 for a round aperture, otherwise the number of blades (polygonal bokeh)
+    pub aperture_blades: usize,
+    pub aperture_rotation: f64,
+    // Rays get a time drawn uniformly from [shutter_open, shutter_close], so objects
+    // with an AnimatedTransform blur. Equal values mean an instantaneous exposure.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl Camera {
+    pub fn new(hsize: usize, vsize: usize, field_of_view: f64) -> Self {
+        let half_view = (field_of_view / 2.0).tan();
+        let aspect = hsize as f64 / vsize as f64;
+        let (half_width, half_height) = if aspect >= 1.0 {
+            (half_view, half_view / aspect)
+        } else {
+            (half_view * aspect, half_view)
+        };
+        Camera {
+            projection: Projection::Perspective,
+            hsize,
+            vsize,
+            field_of_view,
+            transform: Matrix::identity(),
+            inverse_transform: Matrix::identity(),
+            half_width,
+            half_height,
+            pixel_size: half_width * 2.0 / hsize as f64,
+            aperture: 0.0,
+            focal_distance: 1.0,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    pub fn orthographic(hsize: usize, vsize: usize, view_width: f64) -> Self {
+        let mut camera = Camera::new(hsize, vsize, PI / 2.0);
+        camera.projection = Projection::Orthographic { view_width };
+        camera
+    }
+
+    pub fn fisheye(hsize: usize, vsize: usize, field_of_view: f64) -> Self {
+        let mut camera = Camera::new(hsize, vsize, PI / 2.0);
+        camera.field_of_view = field_of_view;
+        camera.projection = Projection::Fisheye;
+        camera
+    }
+
+    // the canvas is always twice as wide as it is high
+    pub fn equirectangular(hsize: usize) -> Self {
+        let mut camera = Camera::new(hsize, (hsize / 2).max(1), PI / 2.0);
+        camera.projection = Projection::Equirectangular;
+        camera
+    }
+
+    pub fn transform(&self) -> &Matrix {
+        &self.transform
+    }
+
+    // the inverse is needed for every ray, so work it out once here
+    pub fn set_transform(&mut self, transform: Matrix) {
+        self.inverse_transform = transform.inverse();
+        self.transform = transform;
+    }
+
+    // point on the lens in camera space, scaled to the aperture radius
+    fn lens_offset(&self, rng: &mut Rng) -> (f64, f64) {
+        let (u1, u2) = (rng.next_f64(), rng.next_f64());
+        let (x, y) = if self.aperture_blades >= 3 {
+            sampling::sample_polygon(
+                self.aperture_blades,
+                self.aperture_rotation,
+                u1,
+                u2,
+                rng.next_f64(),
+            )
+        } else {
+            sampling::sample_disk(u1, u2)
+        };
+        let radius = self.aperture / 2.0;
+        (x * radius, y * radius)
+    }
+
+    // Ray through continuous pixel coordinates (x, y); (0, 0) is the top-left corner of
+    // the canvas. With a non-zero aperture the origin is jittered over the lens.
+    // None when (x, y) falls outside the image circle of a fisheye.
+    pub fn ray_for_point(&self, x: f64, y: f64, rng: &mut Rng) -> Option<Ray> {
+        let lens = if self.projection == Projection::Perspective && self.aperture > 0.0 {
+            self.lens_offset(rng)
+        } else {
+            (0.0, 0.0)
+        };
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + rng.next_f64() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
+        self.ray_through(x, y, lens).map(|mut ray| {
+            ray.time = time;
+            ray
+        })
+    }
+
+    // ray through the centre of pixel (px, py), ignoring the lens, at shutter open
+    pub fn ray_for_pixel(&self, px: usize, py: usize) -> Option<Ray> {
+        self.ray_through(px as f64 + 0.5, py as f64 + 0.5, (0.0, 0.0))
+            .map(|mut ray| {
+                ray.time = self.shutter_open;
+                ray
+            })
+    }
+
+    fn ray_through(&self, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray> {
+        // camera space looks down -z, with +x to the left of the image like the book
+        let (origin, target) = match self.projection {
+            Projection::Perspective => {
+                let world_x = self.half_width - x * self.pixel_size;
+                let world_y = self.half_height - y * self.pixel_size;
+                // the canvas sits at z = -1, scaled out to the plane in focus
+                let focus = Tuple::new_point(
+                    world_x * self.focal_distance,
+                    world_y * self.focal_distance,
+                    -self.focal_distance,
+                );
+                (Tuple::new_point(lens.0, lens.1, 0.0), focus)
+            }
+            Projection::Orthographic { view_width } => {
+                let pixel_size = view_width / self.hsize as f64;
+                let world_x = view_width / 2.0 - x * pixel_size;
+                let world_y = pixel_size * self.vsize as f64 / 2.0 - y * pixel_size;
+                (
+                    Tuple::new_point(world_x, world_y, 0.0),
+                    Tuple::new_point(world_x, world_y, -1.0),
+                )
+            }
+            Projection::Fisheye => {
+                let half = self.hsize.min(self.vsize) as f64 / 2.0;
+                let nx = (x - self.hsize as f64 / 2.0) / half;
+                let ny = (self.vsize as f64 / 2.0 - y) / half;
+                let r = (nx * nx + ny * ny).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * self.field_of_view / 2.0;
+                let direction = if r == 0.0 {
+                    Tuple::new_vector(0.0, 0.0, -1.0)
+                } else {
+                    Tuple::new_vector(-theta.sin() * nx / r, theta.sin() * ny / r, -theta.cos())
+                };
+                let origin = Tuple::new_point(0.0, 0.0, 0.0);
+                (origin, origin.add(direction))
+            }
+            Projection::Equirectangular => {
+                let longitude = (x / self.hsize as f64 - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - y / self.vsize as f64) * PI;
+                let direction = Tuple::new_vector(
+                    -longitude.sin() * latitude.cos(),
+                    latitude.sin(),
+                    -longitude.cos() * latitude.cos(),
+                );
+                let origin = Tuple::new_point(0.0, 0.0, 0.0);
+                (origin, origin.add(direction))
+            }
+        };
+
+        let target = self.inverse_transform.multiply_tuple(&target);
+        let origin = self.inverse_transform.multiply_tuple(&origin);
+        let direction = target.minus(origin).normalize();
+        Some(Ray::new(origin, direction))
+    }
+
+    // Render with `shade` giving the colour seen along a ray. Lens positions are drawn
+    // per sample, so depth of field converges together with anti-aliasing. Samples
+    // outside a fisheye's image circle are black.
+    pub fn render<F>(&self, sampler: &Supersampler, shade: F) -> Canvas
+    where
+        F: Fn(Ray) -> Color,
+    {
+        let rng = RefCell::new(Rng::new(sampler.seed ^ 0x5DEE_CE66));
+        sampler.render(self.hsize, self.vsize, |x, y| {
+            match self.ray_for_point(x, y, &mut rng.borrow_mut()) {
+                Some(ray) => shade(ray),
+                None => Color::black(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn pixel_size() {
+        let c = Camera::new(200, 125, PI / 2.0);
+        assert!(math_utils::f64_equals(c.pixel_size, 0.01));
+        let c = Camera::new(125, 200, PI / 2.0);
+        assert!(math_utils::f64_equals(c.pixel_size, 0.01));
+    }
+
+    #[test]
+    fn rays_through_canvas() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        let r = c.ray_for_pixel(100, 50).unwrap();
+        assert!(r.origin.equals(&Tuple::new_point(0.0, 0.0, 0.0)));
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 0.0, -1.0)));
+
+        let r = c.ray_for_pixel(0, 0).unwrap();
+        assert!(r
+            .direction
+            .equals(&Tuple::new_vector(0.66519, 0.33259, -0.66851)));
+
+        c.set_transform(Matrix::translation(0.0, -2.0, 5.0));
+        let r = c.ray_for_pixel(100, 50).unwrap();
+        assert!(r.origin.equals(&Tuple::new_point(0.0, 2.0, -5.0)));
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn pinhole_matches_pixel_centres() {
+        let c = Camera::new(11, 11, PI / 3.0);
+        let mut rng = Rng::new(0);
+        let a = c.ray_for_point(3.5, 7.5, &mut rng).unwrap();
+        let b = c.ray_for_pixel(3, 7).unwrap();
+        assert!(a.origin.equals(&b.origin));
+        assert!(a.direction.equals(&b.direction));
+    }
+
+    #[test]
+    fn lens_rays_converge_on_focal_plane() {
+        let mut c = Camera::new(11, 11, PI / 3.0);
+        c.aperture = 0.5;
+        c.focal_distance = 4.0;
+        let mut rng = Rng::new(1);
+        let pinhole = c.ray_for_pixel(2, 8).unwrap();
+        let focus = pinhole.position(4.0 / -pinhole.direction.z);
+
+        let mut spread = false;
+        for _ in 0..50 {
+            let r = c.ray_for_point(2.5, 8.5, &mut rng).unwrap();
+            assert!(math_utils::f64_equals(r.origin.z, 0.0));
+            let radius = (r.origin.x * r.origin.x + r.origin.y * r.origin.y).sqrt();
+            assert!(radius <= 0.25 + math_utils::EPSILON);
+            if radius > 0.01 {
+                spread = true;
+            }
+            let hit = r.position(4.0 / -r.direction.z);
+            assert!(hit.equals(&focus));
+        }
+        assert!(spread);
+    }
+
+    #[test]
+    fn bladed_aperture() {
+        let mut c = Camera::new(11, 11, PI / 3.0);
+        c.aperture = 2.0;
+        c.aperture_blades = 4;
+        let mut rng = Rng::new(4);
+        for _ in 0..100 {
+            let r = c.ray_for_point(5.5, 5.5, &mut rng).unwrap();
+            assert!(r.origin.x.abs() + r.origin.y.abs() <= 1.0 + math_utils::EPSILON);
+        }
+    }
+
+    #[test]
+    fn render_with_constant_shading() {
+        let mut c = Camera::new(4, 3, PI / 2.0);
+        c.aperture = 0.1;
+        let image = c.render(&Supersampler::new(2), |_| Color::new(0.2, 0.4, 0.6));
+        assert_eq!(image.width(), 4);
+        assert_eq!(image.height(), 3);
+        assert!(image.pixel_at(3, 2).equals(&Color::new(0.2, 0.4, 0.6)));
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let c = Camera::orthographic(10, 10, 10.0);
+        let r = c.ray_for_pixel(0, 0).unwrap();
+        assert!(r.origin.equals(&Tuple::new_point(4.5, 4.5, 0.0)));
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 0.0, -1.0)));
+        let r = c.ray_for_pixel(9, 4).unwrap();
+        assert!(r.origin.equals(&Tuple::new_point(-4.5, 0.5, 0.0)));
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn fisheye_rays() {
+        let c = Camera::fisheye(100, 100, PI);
+        let mut rng = Rng::new(0);
+        let r = c.ray_for_point(50.0, 50.0, &mut rng).unwrap();
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 0.0, -1.0)));
+        // the edge of a 180 degree fisheye looks sideways
+        let r = c.ray_for_point(100.0, 50.0, &mut rng).unwrap();
+        assert!(r.direction.equals(&Tuple::new_vector(-1.0, 0.0, 0.0)));
+        let r = c.ray_for_point(50.0, 0.0, &mut rng).unwrap();
+        assert!(r.direction.equals(&Tuple::new_vector(0.0, 1.0, 0.0)));
+        // halfway out is 45 degrees off axis
+        let r = c.ray_for_point(75.0, 50.0, &mut rng).unwrap();
+        let s = 2.0_f64.sqrt() / 2.0;
+        assert!(r.direction.equals(&Tuple::new_vector(-s, 0.0, -s)));
+        // corners are outside the image circle
+        assert!(c.ray_for_pixel(0, 0).is_none());
+
+        let image = c.render(&Supersampler::new(1), |_| Color::new(1.0, 1.0, 1.0));
+        assert!(image.pixel_at(0, 0).equals(&Color::black()));
+        assert!(image.pixel_at(50, 50).equals(&Color::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn equirectangular_rays() {
+        let c = Camera::equirectangular(200);
+        assert_eq!(c.hsize, 200);
+        assert_eq!(c.vsize, 100);
+        let mut rng = Rng::new(0);
+        let cases = [
+            (100.0, 50.0, Tuple::new_vector(0.0, 0.0, -1.0)),
+            (150.0, 50.0, Tuple::new_vector(-1.0, 0.0, 0.0)),
+            (50.0, 50.0, Tuple::new_vector(1.0, 0.0, 0.0)),
+            (0.0, 50.0, Tuple::new_vector(0.0, 0.0, 1.0)),
+            (100.0, 0.0, Tuple::new_vector(0.0, 1.0, 0.0)),
+            (100.0, 100.0, Tuple::new_vector(0.0, -1.0, 0.0)),
+        ];
+        for (x, y, expected) in cases {
+            let r = c.ray_for_point(x, y, &mut rng).unwrap();
+            assert!(r.direction.equals(&expected));
+            assert!(r.origin.equals(&Tuple::new_point(0.0, 0.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn rays_sample_the_shutter_interval() {
+        let mut c = Camera::new(11, 11, PI / 3.0);
+        let mut rng = Rng::new(6);
+        assert_eq!(c.ray_for_point(1.0, 1.0, &mut rng).unwrap().time, 0.0);
+
+        c.shutter_open = 0.5;
+        c.shutter_close = 1.5;
+        let mut earliest = f64::MAX;
+        let mut latest = f64::MIN;
+        for _ in 0..100 {
+            let t = c.ray_for_point(1.0, 1.0, &mut rng).unwrap().time;
+            assert!((0.5..1.5).contains(&t));
+            earliest = earliest.min(t);
+            latest = latest.max(t);
+        }
+        assert!(latest - earliest > 0.5);
+        assert_eq!(c.ray_for_pixel(3, 3).unwrap().time, 0.5);
+    }
+}
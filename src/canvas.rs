@@ -1,11 +1,54 @@
-use std::io::Write;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
+use crate::composite::{PorterDuff, Rgba};
+use crate::exr::{self, ExrOptions};
+use crate::pfm;
+use crate::png::{self, PngOptions};
+use crate::tonemap::DisplayTransform;
 use crate::tuple::Color;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PpmFormat {
+    // P3: whitespace separated decimal values
+    Ascii,
+    // P6: raw bytes, big-endian pairs when maxval is over 255
+    Binary,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PpmOptions {
+    pub format: PpmFormat,
+    // largest sample value, up to 65535 for 16-bit output
+    pub maxval: u16,
+    pub transform: DisplayTransform,
+}
+
+impl PpmOptions {
+    // the classic plain P3 with 8-bit values and a hard clip
+    pub fn new() -> Self {
+        PpmOptions {
+            format: PpmFormat::Ascii,
+            maxval: 255,
+            transform: DisplayTransform::linear(),
+        }
+    }
+}
+
+impl Default for PpmOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Pixels are stored row by row in one buffer, top row first. Alpha is kept in a plane
+// of its own, and colours are premultiplied by it.
+#[derive(Clone)]
 pub struct Canvas {
-    pub width: usize,
-    pub height: usize,
-    pub pixels: Vec<Vec<Color>>,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    alpha: Vec<f64>,
 }
 
 impl Canvas {
@@ -16,70 +59,254 @@ impl Canvas {
         Canvas {
             width,
             height,
-            pixels: vec![vec![Color::black(); width]; height],
+            pixels: vec![Color::black(); width * height],
+            alpha: vec![1.0; width * height],
         }
     }
 
-    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn index(&self, x: usize, y: usize, action: &str) -> usize {
         if x >= self.width || y >= self.height {
-            panic!("Canvas bounds error. Tried writing pixel to ({},{}) (x,y) when valid coords are from (0,0) to ({},{})!", x, y, self.width - 1, self.height - 1);
+            panic!("Canvas bounds error. Tried {} ({},{}) (x,y) when valid coords are from (0,0) to ({},{})!", action, x, y, self.width as isize - 1, self.height as isize - 1);
         }
-        self.pixels[y][x] = color;
+        y * self.width + x
+    }
+
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let i = self.index(x, y, "writing pixel to");
+        self.pixels[i] = color;
     }
 
     pub fn pixel_at(&self, x: usize, y: usize) -> Color {
-        self.pixels[y][x]
+        self.pixels[self.index(x, y, "reading pixel at")]
+    }
+
+    pub fn alpha_at(&self, x: usize, y: usize) -> f64 {
+        self.alpha[self.index(x, y, "reading alpha at")]
+    }
+
+    pub fn write_alpha(&mut self, x: usize, y: usize, alpha: f64) {
+        let i = self.index(x, y, "writing alpha to");
+        self.alpha[i] = alpha;
+    }
+
+    pub fn rgba_at(&self, x: usize, y: usize) -> Rgba {
+        let i = self.index(x, y, "reading pixel at");
+        Rgba::new(self.pixels[i], self.alpha[i])
+    }
+
+    pub fn write_rgba(&mut self, x: usize, y: usize, rgba: Rgba) {
+        let i = self.index(x, y, "writing pixel to");
+        self.pixels[i] = rgba.color;
+        self.alpha[i] = rgba.alpha;
+    }
+
+    // true when no pixel lets anything behind it show through
+    pub fn is_opaque(&self) -> bool {
+        self.alpha.iter().all(|&a| a >= 1.0)
+    }
+
+    // For canvases filled with straight colours; the rest of the crate expects them
+    // premultiplied.
+    pub fn premultiply(&mut self) {
+        for (pixel, alpha) in self.pixels.iter_mut().zip(&self.alpha) {
+            *pixel = Rgba::new(*pixel, *alpha).premultiply().color;
+        }
+    }
+
+    pub fn unpremultiply(&mut self) {
+        for (pixel, alpha) in self.pixels.iter_mut().zip(&self.alpha) {
+            *pixel = Rgba::new(*pixel, *alpha).unpremultiply().color;
+        }
+    }
+
+    // This canvas as the source and `destination` underneath, e.g. a render over a
+    // background plate with PorterDuff::Over.
+    pub fn composite(&self, destination: &Canvas, op: PorterDuff) -> Canvas {
+        if (self.width, self.height) != (destination.width, destination.height) {
+            panic!("Canvas size mismatch. Tried compositing a {}x{} canvas with a {}x{} one!", self.width, self.height, destination.width, destination.height);
+        }
+        let mut result = Canvas::new(self.width, self.height);
+        for i in 0..self.pixels.len() {
+            let source = Rgba::new(self.pixels[i], self.alpha[i]);
+            let below = Rgba::new(destination.pixels[i], destination.alpha[i]);
+            let rgba = op.apply(source, below);
+            result.pixels[i] = rgba.color;
+            result.alpha[i] = rgba.alpha;
+        }
+        result
+    }
+
+    // like pixel_at, but None off the edge of the canvas
+    pub fn get(&self, x: usize, y: usize) -> Option<Color> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    // one slice per row, top to bottom
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &[Color]> + ExactSizeIterator {
+        // chunks of zero panic; a zero-width canvas just has no rows to hand out
+        self.pixels.chunks_exact(self.width.max(1))
+    }
+
+    pub fn rows_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = &mut [Color]> + ExactSizeIterator {
+        self.pixels.chunks_exact_mut(self.width.max(1))
+    }
+
+    // (x, y, colour) for every pixel in reading order
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, Color)> + '_ {
+        let width = self.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, *c))
+    }
+
+    // Calls `f(y, row)` for every row, sharing the rows out in bands between one scoped
+    // thread per available core.
+    pub fn par_rows_mut<F>(&mut self, f: F)
+    where
+        F: Fn(usize, &mut [Color]) + Sync,
+    {
+        if self.pixels.is_empty() {
+            return;
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let band = self.height.div_ceil(threads);
+        let width = self.width;
+        let f = &f;
+        std::thread::scope(|scope| {
+            for (i, rows) in self.pixels.chunks_mut(band * width).enumerate() {
+                scope.spawn(move || {
+                    for (j, row) in rows.chunks_exact_mut(width).enumerate() {
+                        f(i * band + j, row);
+                    }
+                });
+            }
+        });
     }
 
     pub fn to_ppm(&self) -> String {
-        let mut result = String::new();
-        let header = format!("P3\n{} {}\n255\n", self.width, self.height);
-        result.push_str(&header);
-
-        let mut line = String::new();
-
-        // to fix this with joins
-        for row in &self.pixels {
-            for pixel in row {
-                let (r, g, b) = pixel.ppm_str();
-                let vals = [r, g, b];
-                for val in vals {
-                    let s = val.to_string();
-                    // add 1 for the space
-                    if line.len() + s.len() + 1 <= 70 {
-                        if line.len() > 0 {
-                            line = line + " ";
+        self.to_ppm_with(&DisplayTransform::linear())
+    }
+
+    // PPM with exposure, tone mapping and gamma applied instead of a plain clip
+    pub fn to_ppm_with(&self, transform: &DisplayTransform) -> String {
+        let mut options = PpmOptions::new();
+        options.transform = *transform;
+        let mut bytes = Vec::new();
+        self.write_ppm(&mut bytes, &options)
+            .expect("writing to memory can't fail");
+        String::from_utf8(bytes).expect("P3 output is ASCII")
+    }
+
+    // Streams the image out a row at a time rather than building it in memory first.
+    pub fn write_ppm<W: Write>(&self, writer: W, options: &PpmOptions) -> io::Result<()> {
+        if options.maxval == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PPM maxval must be at least 1",
+            ));
+        }
+        let mut out = BufWriter::new(writer);
+        let magic = match options.format {
+            PpmFormat::Ascii => "P3",
+            PpmFormat::Binary => "P6",
+        };
+        write!(
+            out,
+            "{}\n{} {}\n{}\n",
+            magic, self.width, self.height, options.maxval
+        )?;
+
+        for row in self.rows() {
+            match options.format {
+                PpmFormat::Ascii => {
+                    // lines are kept to 70 characters, breaking between values
+                    let mut line_len = 0;
+                    for pixel in row {
+                        for val in options.transform.quantize(*pixel, options.maxval) {
+                            let s = val.to_string();
+                            if line_len == 0 {
+                                out.write_all(s.as_bytes())?;
+                                line_len = s.len();
+                            } else if line_len + s.len() < 70 {
+                                write!(out, " {}", s)?;
+                                line_len += s.len() + 1;
+                            } else {
+                                write!(out, "\n{}", s)?;
+                                line_len = s.len();
+                            }
+                        }
+                    }
+                    out.write_all(b"\n")?;
+                }
+                PpmFormat::Binary => {
+                    for pixel in row {
+                        for val in options.transform.quantize(*pixel, options.maxval) {
+                            if options.maxval < 256 {
+                                out.write_all(&[val as u8])?;
+                            } else {
+                                out.write_all(&val.to_be_bytes())?;
+                            }
                         }
-                        line = line + &s;
-                    } else {
-                        result = result + &line + "\n";
-                        line = String::new();
-                        line = line + &s;
                     }
                 }
             }
-            result = result + &line + "\n";
-            line = String::new();
         }
-        result
+        out.flush()
+    }
+
+    pub fn write_to_ppm(&self, path: &str) -> io::Result<()> {
+        self.write_ppm_file(path, &PpmOptions::new())
+    }
+
+    pub fn write_to_ppm_with(&self, path: &str, transform: &DisplayTransform) -> io::Result<()> {
+        let mut options = PpmOptions::new();
+        options.transform = *transform;
+        self.write_ppm_file(path, &options)
+    }
+
+    pub fn write_ppm_file(&self, path: &str, options: &PpmOptions) -> io::Result<()> {
+        self.write_ppm(File::create(path)?, options)
+    }
+
+    pub fn write_png(&self, path: &str, options: &PngOptions) -> io::Result<()> {
+        png::write_png(self, File::create(path)?, options)
+    }
+
+    // linear floating point output, with nothing clipped
+    pub fn write_pfm(&self, path: &str) -> io::Result<()> {
+        pfm::write_pfm(self, File::create(path)?)
     }
 
-    pub fn write_to_ppm(&self, path: &str) -> std::io::Result<()> {
-        let mut file = std::fs::File::create(path)?;
-        file.write_all(self.to_ppm().as_bytes())?;
-        Ok(())
+    pub fn write_exr(&self, path: &str, options: &ExrOptions) -> io::Result<()> {
+        exr::write_exr(self, File::create(path)?, options)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::math_utils;
 
     #[test]
     fn creating_canvas() {
         let mut c = Canvas::new(10, 20);
-        assert_eq!(c.width, 10);
-        assert_eq!(c.height, 20);
+        assert_eq!(c.width(), 10);
+        assert_eq!(c.height(), 20);
         for y in 0..20 {
             for x in 0..10 {
                 assert!(c.pixel_at(x, y).equals(&Color::black()));
@@ -132,4 +359,156 @@ mod tests {
         // println!("---\n{}\n-----", correct);
         assert!(ppm == correct)
     }
+
+    #[test]
+    fn ppm_with_display_transform() {
+        let mut c = Canvas::new(3, 1);
+        c.write_pixel(0, 0, Color::new(1.0, 1.0, 1.0));
+        c.write_pixel(1, 0, Color::new(3.0, 0.0, 0.0));
+        c.write_pixel(2, 0, Color::new(0.18, 0.18, 0.18));
+        assert_eq!(c.to_ppm_with(&DisplayTransform::linear()), c.to_ppm());
+
+        let ppm = c.to_ppm_with(&DisplayTransform::new());
+        assert_eq!(ppm, "P3\n3 1\n255\n188 188 188 225 0 0 109 109 109\n");
+    }
+
+    #[test]
+    fn binary_ppm() {
+        let mut c = Canvas::new(2, 1);
+        c.write_pixel(0, 0, Color::new(1.5, 0.5, 0.0));
+        c.write_pixel(1, 0, Color::new(0.2, 0.4, 0.6));
+        let mut options = PpmOptions::new();
+        options.format = PpmFormat::Binary;
+
+        let mut bytes = Vec::new();
+        c.write_ppm(&mut bytes, &options).unwrap();
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend([255, 128, 0, 51, 102, 153]);
+        assert_eq!(bytes, expected);
+
+        options.maxval = 65535;
+        let mut bytes = Vec::new();
+        c.write_ppm(&mut bytes, &options).unwrap();
+        let mut expected = b"P6\n2 1\n65535\n".to_vec();
+        for v in [65535u16, 32768, 0, 13107, 26214, 39321] {
+            expected.extend(v.to_be_bytes());
+        }
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn zero_maxval_is_rejected() {
+        let mut options = PpmOptions::new();
+        options.maxval = 0;
+        let mut bytes = Vec::new();
+        let err = Canvas::new(1, 1).write_ppm(&mut bytes, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn sixteen_bit_ascii_ppm_wraps_lines() {
+        let mut c = Canvas::new(6, 1);
+        for x in 0..6 {
+            c.write_pixel(x, 0, Color::new(1.0, 1.0, 1.0));
+        }
+        let mut options = PpmOptions::new();
+        options.maxval = 65535;
+        let mut bytes = Vec::new();
+        c.write_ppm(&mut bytes, &options).unwrap();
+        let ppm = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = ppm.lines().collect();
+        assert_eq!(lines[2], "65535");
+        // eleven values fit in 70 columns
+        assert_eq!(lines[3].len(), 65);
+        assert_eq!(lines[4].split(' ').count(), 7);
+        assert!(lines.iter().all(|l| l.len() <= 70));
+    }
+
+    #[test]
+    fn row_access_and_iteration() {
+        let mut c = Canvas::new(3, 2);
+        c.write_pixel(2, 1, Color::new(1.0, 0.0, 0.0));
+        let rows: Vec<&[Color]> = c.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1][2].equals(&Color::new(1.0, 0.0, 0.0)));
+
+        for (y, row) in c.rows_mut().enumerate() {
+            row[0] = Color::new(0.0, y as f64, 0.0);
+        }
+        assert!(c.pixel_at(0, 1).equals(&Color::new(0.0, 1.0, 0.0)));
+
+        let pixels: Vec<(usize, usize, Color)> = c.enumerate_pixels().collect();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!((pixels[5].0, pixels[5].1), (2, 1));
+        assert!(pixels[5].2.equals(&Color::new(1.0, 0.0, 0.0)));
+        assert_eq!((pixels[3].0, pixels[3].1), (0, 1));
+
+        assert!(c.get(2, 1).is_some());
+        assert!(c.get(3, 0).is_none());
+        assert!(c.get(0, 2).is_none());
+        assert_eq!(Canvas::new(0, 4).rows().count(), 0);
+    }
+
+    #[test]
+    fn parallel_rows() {
+        let mut c = Canvas::new(7, 33);
+        c.par_rows_mut(|y, row| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = Color::new(x as f64, y as f64, 1.0);
+            }
+        });
+        for (x, y, color) in c.enumerate_pixels() {
+            assert!(color.equals(&Color::new(x as f64, y as f64, 1.0)));
+        }
+        Canvas::new(0, 0).par_rows_mut(|_, _| panic!("no rows to visit"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Canvas bounds error. Tried reading pixel at (5,0)")]
+    fn reading_outside_the_canvas() {
+        Canvas::new(5, 5).pixel_at(5, 0);
+    }
+
+    #[test]
+    fn alpha_plane() {
+        let mut c = Canvas::new(2, 1);
+        assert!(c.is_opaque());
+        assert!(c.rgba_at(1, 0).equals(&Rgba::opaque(Color::black())));
+        c.write_rgba(0, 0, Rgba::new(Color::new(0.8, 0.4, 0.0), 0.5));
+        assert!(!c.is_opaque());
+        assert!(math_utils::f64_equals(c.alpha_at(0, 0), 0.5));
+
+        c.premultiply();
+        assert!(c.pixel_at(0, 0).equals(&Color::new(0.4, 0.2, 0.0)));
+        c.write_alpha(1, 0, 0.0);
+        c.unpremultiply();
+        assert!(c.pixel_at(0, 0).equals(&Color::new(0.8, 0.4, 0.0)));
+        assert!(c.pixel_at(1, 0).equals(&Color::black()));
+    }
+
+    #[test]
+    fn compositing_canvases() {
+        let mut render = Canvas::new(2, 1);
+        render.write_rgba(0, 0, Rgba::opaque(Color::new(1.0, 0.0, 0.0)));
+        render.write_rgba(1, 0, Rgba::transparent());
+        let mut plate = Canvas::new(2, 1);
+        for x in 0..2 {
+            plate.write_pixel(x, 0, Color::new(0.0, 0.0, 1.0));
+        }
+        let over = render.composite(&plate, PorterDuff::Over);
+        assert!(over.pixel_at(0, 0).equals(&Color::new(1.0, 0.0, 0.0)));
+        assert!(over.pixel_at(1, 0).equals(&Color::new(0.0, 0.0, 1.0)));
+        assert!(over.is_opaque());
+
+        let xor = render.composite(&plate, PorterDuff::Xor);
+        assert!(xor.rgba_at(0, 0).equals(&Rgba::transparent()));
+        assert!(xor.rgba_at(1, 0).equals(&Rgba::opaque(Color::new(0.0, 0.0, 1.0))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Canvas size mismatch")]
+    fn compositing_needs_equal_sizes() {
+        Canvas::new(2, 2).composite(&Canvas::new(2, 3), PorterDuff::Over);
+    }
 }
@@ -0,0 +1,474 @@
+use std::sync::OnceLock;
+
+use crate::spectrum::{self, MAX_WAVELENGTH, MIN_WAVELENGTH};
+use crate::tonemap::{srgb_decode, srgb_encode};
+use crate::tuple::Color;
+
+// A Color is just three numbers; these say what they mean. The renderer works in linear
+// sRGB throughout, so colours picked from a swatch or an image editor (sRGB-encoded, or
+// Display P3 on recent Macs) have to be converted in, and ACEScg values from other
+// renderers converted across. All the conversions go through CIE XYZ with a D65 white.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    // Rec. 709 primaries, linear: what the renderer and Color::luminance assume
+    LinearSrgb,
+    // Rec. 709 primaries with the sRGB curve: hex codes, PNGs, colour pickers
+    Srgb,
+    // ACES AP1 primaries, linear, with the ACES white (about D60)
+    AcesCg,
+    // DCI-P3 primaries with a D65 white, linear
+    LinearDisplayP3,
+    // DCI-P3 primaries with the sRGB curve, as wide gamut displays expect
+    DisplayP3,
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+// chromaticities (x, y) of the red, green and blue primaries and of the white
+type Chromaticities = [(f64, f64); 4];
+
+const SRGB: Chromaticities = [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65];
+const P3: Chromaticities = [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65];
+const AP1: Chromaticities = [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), ACES_WHITE];
+
+const D65: (f64, f64) = (0.3127, 0.3290);
+const ACES_WHITE: (f64, f64) = (0.32168, 0.33767);
+
+// cone response space for Bradford white point adaptation
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+// RGB to XYZ and back for each space, with XYZ always relative to D65
+struct Matrices {
+    srgb: (Matrix3, Matrix3),
+    p3: (Matrix3, Matrix3),
+    acescg: (Matrix3, Matrix3),
+}
+
+fn matrices() -> &'static Matrices {
+    static MATRICES: OnceLock<Matrices> = OnceLock::new();
+    MATRICES.get_or_init(|| {
+        let pair = |m: Matrix3| (m, invert(&m));
+        // ACEScg's white has to be carried over to D65 like any other colour
+        let to_d65 = adaptation(white_xyz(ACES_WHITE), white_xyz(D65));
+        Matrices {
+            srgb: pair(rgb_to_xyz_matrix(&SRGB)),
+            p3: pair(rgb_to_xyz_matrix(&P3)),
+            acescg: pair(multiply(&to_d65, &rgb_to_xyz_matrix(&AP1))),
+        }
+    })
+}
+
+fn white_xyz((x, y): (f64, f64)) -> (f64, f64, f64) {
+    (x / y, 1.0, (1.0 - x - y) / y)
+}
+
+// the matrix taking RGB in the given primaries to XYZ, with (1, 1, 1) landing on white
+fn rgb_to_xyz_matrix(space: &Chromaticities) -> Matrix3 {
+    let mut primaries = [[0.0; 3]; 3];
+    for (i, &(x, y)) in space[..3].iter().enumerate() {
+        let (px, py, pz) = white_xyz((x, y));
+        primaries[0][i] = px;
+        primaries[1][i] = py;
+        primaries[2][i] = pz;
+    }
+    let (sr, sg, sb) = transform(&invert(&primaries), white_xyz(space[3]));
+    let mut m = primaries;
+    for row in &mut m {
+        row[0] *= sr;
+        row[1] *= sg;
+        row[2] *= sb;
+    }
+    m
+}
+
+fn adaptation(from: (f64, f64, f64), to: (f64, f64, f64)) -> Matrix3 {
+    let (a, b) = (transform(&BRADFORD, from), transform(&BRADFORD, to));
+    let scale = [
+        [b.0 / a.0, 0.0, 0.0],
+        [0.0, b.1 / a.1, 0.0],
+        [0.0, 0.0, b.2 / a.2],
+    ];
+    multiply(&invert(&BRADFORD), &multiply(&scale, &BRADFORD))
+}
+
+fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn invert(m: &Matrix3) -> Matrix3 {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    let determinant: f64 = (0..3).map(|j| m[0][j] * adjugate[j][0]).sum();
+    adjugate.map(|row| row.map(|v| v / determinant))
+}
+
+fn transform(m: &Matrix3, (x, y, z): (f64, f64, f64)) -> (f64, f64, f64) {
+    (
+        m[0][0] * x + m[0][1] * y + m[0][2] * z,
+        m[1][0] * x + m[1][1] * y + m[1][2] * z,
+        m[2][0] * x + m[2][1] * y + m[2][2] * z,
+    )
+}
+
+fn channels(color: Color) -> (f64, f64, f64) {
+    (color.r(), color.g(), color.b())
+}
+
+fn color((r, g, b): (f64, f64, f64)) -> Color {
+    Color::new(r, g, b)
+}
+
+fn encode(color: Color) -> Color {
+    Color::new(
+        srgb_encode(color.r()),
+        srgb_encode(color.g()),
+        srgb_encode(color.b()),
+    )
+}
+
+fn decode(color: Color) -> Color {
+    Color::new(
+        srgb_decode(color.r()),
+        srgb_decode(color.g()),
+        srgb_decode(color.b()),
+    )
+}
+
+impl ColorSpace {
+    fn matrices(self) -> &'static (Matrix3, Matrix3) {
+        let m = matrices();
+        match self {
+            ColorSpace::LinearSrgb | ColorSpace::Srgb => &m.srgb,
+            ColorSpace::AcesCg => &m.acescg,
+            ColorSpace::LinearDisplayP3 | ColorSpace::DisplayP3 => &m.p3,
+        }
+    }
+
+    fn encoded(self) -> bool {
+        matches!(self, ColorSpace::Srgb | ColorSpace::DisplayP3)
+    }
+
+    pub fn rgb_to_xyz(self, c: Color) -> (f64, f64, f64) {
+        let linear = if self.encoded() { decode(c) } else { c };
+        transform(&self.matrices().0, channels(linear))
+    }
+
+    pub fn xyz_to_rgb(self, xyz: (f64, f64, f64)) -> Color {
+        let linear = color(transform(&self.matrices().1, xyz));
+        if self.encoded() {
+            encode(linear)
+        } else {
+            linear
+        }
+    }
+
+    // The same colour expressed in another space. Colours outside the target's gamut
+    // come out with negative channels rather than being clipped.
+    pub fn convert(self, c: Color, to: ColorSpace) -> Color {
+        if self == to {
+            return c;
+        }
+        to.xyz_to_rgb(self.rgb_to_xyz(c))
+    }
+
+    // relative luminance, the Y of XYZ
+    pub fn luminance(self, c: Color) -> f64 {
+        self.rgb_to_xyz(c).1
+    }
+}
+
+// CIE L*a*b* relative to D65 white, with L in [0, 100]
+pub fn xyz_to_lab((x, y, z): (f64, f64, f64)) -> (f64, f64, f64) {
+    let f = |t: f64| {
+        let delta: f64 = 6.0 / 29.0;
+        if t > delta * delta * delta {
+            t.cbrt()
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let white = white_xyz(D65);
+    let (fx, fy, fz) = (f(x / white.0), f(y / white.1), f(z / white.2));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+pub fn lab_to_xyz((l, a, b): (f64, f64, f64)) -> (f64, f64, f64) {
+    let f = |t: f64| {
+        let delta: f64 = 6.0 / 29.0;
+        if t > delta {
+            t * t * t
+        } else {
+            3.0 * delta * delta * (t - 4.0 / 29.0)
+        }
+    };
+    let white = white_xyz(D65);
+    let fy = (l + 16.0) / 116.0;
+    (
+        white.0 * f(fy + a / 500.0),
+        white.1 * f(fy),
+        white.2 * f(fy - b / 200.0),
+    )
+}
+
+// Hue in degrees [0, 360), saturation and value in [0, 1] for colours in [0, 1]. These
+// work on whatever RGB the colour holds, which for picker values is usually sRGB.
+pub fn rgb_to_hsv(c: Color) -> (f64, f64, f64) {
+    let (max, min) = extremes(c);
+    let saturation = if max > 0.0 { (max - min) / max } else { 0.0 };
+    (hue(c, max, min), saturation, max)
+}
+
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Color {
+    let chroma = value * saturation;
+    from_hue(hue, chroma, value - chroma)
+}
+
+// hue in degrees, saturation and lightness in [0, 1]
+pub fn rgb_to_hsl(c: Color) -> (f64, f64, f64) {
+    let (max, min) = extremes(c);
+    let lightness = (max + min) / 2.0;
+    let saturation = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+    (hue(c, max, min), saturation, lightness)
+}
+
+pub fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> Color {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    from_hue(hue, chroma, lightness - chroma / 2.0)
+}
+
+fn extremes(c: Color) -> (f64, f64) {
+    let max = c.r().max(c.g()).max(c.b());
+    let min = c.r().min(c.g()).min(c.b());
+    (max, min)
+}
+
+fn hue(c: Color, max: f64, min: f64) -> f64 {
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return 0.0;
+    }
+    let sector = if max == c.r() {
+        (c.g() - c.b()) / chroma
+    } else if max == c.g() {
+        (c.b() - c.r()) / chroma + 2.0
+    } else {
+        (c.r() - c.g()) / chroma + 4.0
+    };
+    (60.0 * sector).rem_euclid(360.0)
+}
+
+// the colour with the given hue and chroma, lifted by `offset` on every channel
+fn from_hue(hue: f64, chroma: f64, offset: f64) -> Color {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    Color::new(r + offset, g + offset, b + offset)
+}
+
+// Linear sRGB colour of a black body at `kelvin`, scaled to a luminance of one so that
+// a light's intensity stays the same whatever its temperature. Very low temperatures
+// fall outside the sRGB gamut; the negative parts are dropped. Temperatures below 500 K,
+// where the visible part of the spectrum underflows, and non-numbers give 500 K's red.
+pub fn kelvin(kelvin: f64) -> Color {
+    // second radiation constant, in nanometre kelvins
+    const C2: f64 = 1.4388e7;
+    let kelvin = kelvin.max(500.0);
+    let step = 5.0;
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    let mut wavelength = MIN_WAVELENGTH;
+    while wavelength <= MAX_WAVELENGTH {
+        // Planck's law, without the constant factors that the scaling removes anyway
+        let radiance = 1.0 / (wavelength.powi(5) * ((C2 / (wavelength * kelvin)).exp() - 1.0));
+        let (cx, cy, cz) = spectrum::cie_xyz(wavelength);
+        x += cx * radiance;
+        y += cy * radiance;
+        z += cz * radiance;
+        wavelength += step;
+    }
+    let rgb = ColorSpace::LinearSrgb.xyz_to_rgb((x, y, z));
+    let rgb = Color::new(rgb.r().max(0.0), rgb.g().max(0.0), rgb.b().max(0.0));
+    rgb.scale(1.0 / rgb.luminance())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    const SPACES: [ColorSpace; 5] = [
+        ColorSpace::LinearSrgb,
+        ColorSpace::Srgb,
+        ColorSpace::AcesCg,
+        ColorSpace::LinearDisplayP3,
+        ColorSpace::DisplayP3,
+    ];
+
+    fn close(a: Color, b: Color) -> bool {
+        a.equals(&b)
+    }
+
+    // for published values, which are only given to a few places
+    fn near(a: Color, b: Color) -> bool {
+        let d = a.minus(b);
+        d.r().abs().max(d.g().abs()).max(d.b().abs()) < 1e-4
+    }
+
+    #[test]
+    fn white_is_white_everywhere() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for from in SPACES {
+            assert!(math_utils::f64_equals(from.luminance(white), 1.0));
+            for to in SPACES {
+                let converted = from.convert(white, to);
+                assert!(
+                    close(converted, white),
+                    "{:?} -> {:?}: {:?}",
+                    from,
+                    to,
+                    converted
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let c = Color::new(0.8, 0.3, 0.05);
+        for from in SPACES {
+            for to in SPACES {
+                let back = to.convert(from.convert(c, to), from);
+                assert!(close(back, c), "{:?} <-> {:?}: {:?}", from, to, back);
+            }
+        }
+    }
+
+    #[test]
+    fn known_conversions() {
+        // mid grey in a colour picker is much darker than half in linear light
+        let grey = ColorSpace::Srgb.convert(Color::new(0.5, 0.5, 0.5), ColorSpace::LinearSrgb);
+        assert!(math_utils::f64_equals(grey.r(), 0.2140411));
+        // pure sRGB red sits inside the wider gamuts, so it isn't fully saturated there
+        let red = Color::new(1.0, 0.0, 0.0);
+        let p3 = ColorSpace::LinearSrgb.convert(red, ColorSpace::LinearDisplayP3);
+        assert!(near(p3, Color::new(0.8224622, 0.0331942, 0.0170826)));
+        let aces = ColorSpace::LinearSrgb.convert(red, ColorSpace::AcesCg);
+        assert!(near(aces, Color::new(0.6130974, 0.0701937, 0.0206156)));
+        // and pure P3 green is outside sRGB
+        let green =
+            ColorSpace::LinearDisplayP3.convert(Color::new(0.0, 1.0, 0.0), ColorSpace::LinearSrgb);
+        assert!(green.r() < 0.0 && green.b() < 0.0);
+        // luminance agrees with Color::luminance for linear sRGB
+        let c = Color::new(0.2, 0.7, 0.4);
+        assert!((ColorSpace::LinearSrgb.luminance(c) - c.luminance()).abs() < 1e-4);
+        let (x, y, z) = ColorSpace::LinearSrgb.rgb_to_xyz(Color::new(0.0, 0.0, 1.0));
+        assert!(near(
+            Color::new(x, y, z),
+            Color::new(0.1805, 0.0722, 0.9505)
+        ));
+    }
+
+    #[test]
+    fn lab() {
+        let (l, a, b) = xyz_to_lab(white_xyz(D65));
+        assert!(math_utils::f64_equals(l, 100.0));
+        assert!(a.abs() < 1e-9 && b.abs() < 1e-9);
+        assert_eq!(xyz_to_lab((0.0, 0.0, 0.0)).0, 0.0);
+        // sRGB red, from the usual reference tables
+        let (l, a, b) = xyz_to_lab(ColorSpace::LinearSrgb.rgb_to_xyz(Color::new(1.0, 0.0, 0.0)));
+        assert!((l - 53.24).abs() < 0.01 && (a - 80.09).abs() < 0.01 && (b - 67.20).abs() < 0.01);
+        for xyz in [(0.2, 0.3, 0.4), (0.001, 0.002, 0.0005), (0.9, 0.8, 0.1)] {
+            let back = lab_to_xyz(xyz_to_lab(xyz));
+            assert!(math_utils::f64_equals(back.0, xyz.0));
+            assert!(math_utils::f64_equals(back.1, xyz.1));
+            assert!(math_utils::f64_equals(back.2, xyz.2));
+        }
+    }
+
+    #[test]
+    fn hsv_and_hsl() {
+        let orange = Color::new(1.0, 0.5, 0.0);
+        let (h, s, v) = rgb_to_hsv(orange);
+        assert!(math_utils::f64_equals(h, 30.0) && s == 1.0 && v == 1.0);
+        let (h, s, l) = rgb_to_hsl(orange);
+        assert!(math_utils::f64_equals(h, 30.0) && s == 1.0 && l == 0.5);
+        assert_eq!(rgb_to_hsv(Color::new(0.2, 0.2, 0.2)), (0.0, 0.0, 0.2));
+        assert!(math_utils::f64_equals(
+            rgb_to_hsv(Color::new(1.0, 0.0, 0.5)).0,
+            330.0
+        ));
+
+        for c in [
+            Color::new(0.9, 0.1, 0.3),
+            Color::new(0.1, 0.8, 0.6),
+            Color::new(0.4, 0.3, 0.95),
+            Color::new(0.5, 0.5, 0.5),
+            Color::black(),
+        ] {
+            let (h, s, v) = rgb_to_hsv(c);
+            assert!(close(hsv_to_rgb(h, s, v), c), "{:?}", c);
+            let (h, s, l) = rgb_to_hsl(c);
+            assert!(close(hsl_to_rgb(h, s, l), c), "{:?}", c);
+        }
+        assert!(close(
+            hsv_to_rgb(480.0, 1.0, 1.0),
+            Color::new(0.0, 1.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn colour_temperature() {
+        let (candle, daylight, sky) = (kelvin(1900.0), kelvin(6504.0), kelvin(12000.0));
+        for c in [candle, daylight, sky] {
+            assert!(math_utils::f64_equals(c.luminance(), 1.0));
+        }
+        assert!(candle.r() > candle.g() && candle.g() > candle.b());
+        assert!(sky.b() > sky.g() && sky.g() > sky.r());
+        // D65 is close to, though not exactly, a black body at 6504 K
+        for channel in [daylight.r(), daylight.g(), daylight.b()] {
+            assert!((channel - 1.0).abs() < 0.1, "{:?}", daylight);
+        }
+        let ember = kelvin(500.0);
+        assert!(math_utils::f64_equals(ember.luminance(), 1.0));
+        for cold in [100.0, 0.0, -300.0, f64::NAN] {
+            assert!(kelvin(cold).equals(&ember), "{}", cold);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::canvas::Canvas;
+use crate::math_utils;
+use crate::tuple::Color;
+
+// Metrics for how far one canvas is from another, for checking renders against golden
+// images when the renderer's noise rules out an exact match.
+
+#[derive(Debug, Copy, Clone)]
+pub struct CompareOptions {
+    // how far a channel may drift before its pixel counts as different
+    pub tolerance: f64,
+    // the brightest value expected, which PSNR and SSIM are relative to
+    pub peak: f64,
+}
+
+impl CompareOptions {
+    pub fn new() -> Self {
+        CompareOptions {
+            tolerance: math_utils::EPSILON,
+            peak: 1.0,
+        }
+    }
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Comparison {
+    // mean squared error over the colour channels
+    pub mse: f64,
+    // peak signal to noise ratio in decibels; infinite for identical images
+    pub psnr: f64,
+    // mean structural similarity of the luminance, where 1 is identical
+    pub ssim: f64,
+    // largest difference in any channel, alpha included
+    pub max_error: f64,
+    // pixels with some channel further apart than the tolerance
+    pub pixels_over_tolerance: usize,
+}
+
+pub fn compare(expected: &Canvas, actual: &Canvas, options: &CompareOptions) -> Comparison {
+    check_sizes(expected, actual);
+    let mut squared = 0.0;
+    let mut max_error: f64 = 0.0;
+    let mut pixels_over_tolerance = 0;
+    for (x, y, _) in expected.enumerate_pixels() {
+        let (a, b) = (expected.pixel_at(x, y), actual.pixel_at(x, y));
+        for (u, v) in [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())] {
+            squared += (u - v) * (u - v);
+        }
+        let error = pixel_error(expected, actual, x, y);
+        max_error = max_error.max(error);
+        if error > options.tolerance {
+            pixels_over_tolerance += 1;
+        }
+    }
+
+    let samples = (expected.width() * expected.height() * 3).max(1);
+    let mse = squared / samples as f64;
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (options.peak * options.peak / mse).log10()
+    };
+    Comparison {
+        mse,
+        psnr,
+        ssim: ssim(expected, actual, options.peak),
+        max_error,
+        pixels_over_tolerance,
+    }
+}
+
+// A false colour map of where the images differ. Pixels within the tolerance show the
+// expected image dimmed to grey for context; the rest run from red through yellow to
+// white as their error grows towards the largest in the image.
+pub fn difference(expected: &Canvas, actual: &Canvas, options: &CompareOptions) -> Canvas {
+    check_sizes(expected, actual);
+    let mut max_error: f64 = 0.0;
+    for (x, y, _) in expected.enumerate_pixels() {
+        max_error = max_error.max(pixel_error(expected, actual, x, y));
+    }
+
+    let mut canvas = Canvas::new(expected.width(), expected.height());
+    for (x, y, pixel) in expected.enumerate_pixels() {
+        let error = pixel_error(expected, actual, x, y);
+        let color = if error > options.tolerance {
+            let t = error / max_error;
+            Color::new(1.0, (2.0 * t).min(1.0), (2.0 * t - 1.0).max(0.0))
+        } else {
+            let grey = 0.25 * pixel.luminance().clamp(0.0, 1.0);
+            Color::new(grey, grey, grey)
+        };
+        canvas.write_pixel(x, y, color);
+    }
+    canvas
+}
+
+fn check_sizes(expected: &Canvas, actual: &Canvas) {
+    if (expected.width(), expected.height()) != (actual.width(), actual.height()) {
+        panic!(
+            "Canvas size mismatch. Tried comparing a {}x{} canvas with a {}x{} one!",
+            expected.width(),
+            expected.height(),
+            actual.width(),
+            actual.height()
+        );
+    }
+}
+
+fn pixel_error(expected: &Canvas, actual: &Canvas, x: usize, y: usize) -> f64 {
+    let (a, b) = (expected.rgba_at(x, y), actual.rgba_at(x, y));
+    [
+        a.color.r() - b.color.r(),
+        a.color.g() - b.color.g(),
+        a.color.b() - b.color.b(),
+        a.alpha - b.alpha,
+    ]
+    .iter()
+    .fold(0.0, |max, d| d.abs().max(max))
+}
+
+// Wang et al.'s SSIM on luminance, with the local statistics weighted by a Gaussian of
+// sigma 1.5 and averaged over the image.
+fn ssim(expected: &Canvas, actual: &Canvas, peak: f64) -> f64 {
+    let (width, height) = (expected.width(), expected.height());
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let plane = |f: &dyn Fn(f64, f64) -> f64| {
+        let mut canvas = Canvas::new(width, height);
+        for (x, y, a) in expected.enumerate_pixels() {
+            let v = f(a.luminance(), actual.pixel_at(x, y).luminance());
+            canvas.write_pixel(x, y, Color::new(v, v, v));
+        }
+        canvas.gaussian_blur(1.5)
+    };
+    let mean_a = plane(&|a, _| a);
+    let mean_b = plane(&|_, b| b);
+    let square_a = plane(&|a, _| a * a);
+    let square_b = plane(&|_, b| b * b);
+    let product = plane(&|a, b| a * b);
+
+    let c1 = (0.01 * peak) * (0.01 * peak);
+    let c2 = (0.03 * peak) * (0.03 * peak);
+    let mut total = 0.0;
+    for (x, y, ma) in mean_a.enumerate_pixels() {
+        let (ma, mb) = (ma.r(), mean_b.pixel_at(x, y).r());
+        let var_a = square_a.pixel_at(x, y).r() - ma * ma;
+        let var_b = square_b.pixel_at(x, y).r() - mb * mb;
+        let covariance = product.pixel_at(x, y).r() - ma * mb;
+        total += (2.0 * ma * mb + c1) * (2.0 * covariance + c2)
+            / ((ma * ma + mb * mb + c1) * (var_a + var_b + c2));
+    }
+    total / (width * height) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite::Rgba;
+    use crate::rng::Rng;
+
+    fn gradient() -> Canvas {
+        let mut c = Canvas::new(16, 12);
+        for y in 0..12 {
+            for x in 0..16 {
+                c.write_pixel(x, y, Color::new(x as f64 / 16.0, y as f64 / 12.0, 0.5));
+            }
+        }
+        c
+    }
+
+    #[test]
+    fn identical_images() {
+        let c = gradient();
+        let result = compare(&c, &c, &CompareOptions::new());
+        assert_eq!(result.mse, 0.0);
+        assert_eq!(result.psnr, f64::INFINITY);
+        assert!(math_utils::f64_equals(result.ssim, 1.0));
+        assert_eq!(result.max_error, 0.0);
+        assert_eq!(result.pixels_over_tolerance, 0);
+    }
+
+    #[test]
+    fn errors_and_tolerance() {
+        let expected = gradient();
+        let mut actual = gradient();
+        actual.write_pixel(3, 4, actual.pixel_at(3, 4).add(Color::new(0.0, 0.5, 0.0)));
+        actual.write_rgba(5, 6, Rgba::new(actual.pixel_at(5, 6), 0.9));
+        actual.write_pixel(7, 8, actual.pixel_at(7, 8).add(Color::new(0.0, 0.0, 1e-7)));
+
+        let result = compare(&expected, &actual, &CompareOptions::new());
+        assert!(math_utils::f64_equals(result.max_error, 0.5));
+        assert_eq!(result.pixels_over_tolerance, 2);
+        assert!(math_utils::f64_equals(
+            result.mse,
+            0.25 / (16.0 * 12.0 * 3.0)
+        ));
+        let psnr = 10.0 * (1.0 / result.mse).log10();
+        assert!(math_utils::f64_equals(result.psnr, psnr));
+
+        let loose = CompareOptions {
+            tolerance: 0.2,
+            ..CompareOptions::new()
+        };
+        assert_eq!(compare(&expected, &actual, &loose).pixels_over_tolerance, 1);
+    }
+
+    #[test]
+    fn ssim_prefers_noise_to_structural_change() {
+        let expected = gradient();
+        let mut rng = Rng::new(7);
+        let mut noisy = gradient();
+        for y in 0..12 {
+            for x in 0..16 {
+                let jitter = (rng.next_f64() - 0.5) * 0.02;
+                let p = noisy.pixel_at(x, y).add(Color::new(jitter, jitter, jitter));
+                noisy.write_pixel(x, y, p);
+            }
+        }
+        let flipped = expected.flip_horizontal();
+
+        let options = CompareOptions::new();
+        let noise = compare(&expected, &noisy, &options);
+        let structure = compare(&expected, &flipped, &options);
+        assert!(noise.ssim > 0.9 && noise.ssim < 1.0, "{}", noise.ssim);
+        assert!(structure.ssim < noise.ssim);
+        assert!(noise.psnr > structure.psnr);
+    }
+
+    #[test]
+    fn difference_highlights_changed_pixels() {
+        let expected = gradient();
+        let mut actual = gradient();
+        actual.write_pixel(1, 1, Color::new(1.0, 1.0, 1.0));
+        actual.write_pixel(2, 2, actual.pixel_at(2, 2).add(Color::new(0.01, 0.0, 0.0)));
+
+        let diff = difference(&expected, &actual, &CompareOptions::new());
+        assert!(diff.pixel_at(1, 1).equals(&Color::new(1.0, 1.0, 1.0)));
+        let small = diff.pixel_at(2, 2);
+        assert!(math_utils::f64_equals(small.r(), 1.0) && small.b() == 0.0);
+        let grey = 0.25 * expected.pixel_at(0, 0).luminance();
+        assert!(diff.pixel_at(0, 0).equals(&Color::new(grey, grey, grey)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Canvas size mismatch")]
+    fn comparing_needs_equal_sizes() {
+        compare(
+            &Canvas::new(2, 2),
+            &Canvas::new(3, 2),
+            &CompareOptions::new(),
+        );
+    }
+}
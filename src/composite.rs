@@ -0,0 +1,136 @@
+use crate::math_utils;
+use crate::tuple::Color;
+
+// A colour with coverage. Canvases and the compositing operators work with the colour
+// premultiplied by alpha, so a fully transparent pixel is always black.
+#[derive(Debug, Copy, Clone)]
+pub struct Rgba {
+    pub color: Color,
+    pub alpha: f64,
+}
+
+impl Rgba {
+    pub fn new(color: Color, alpha: f64) -> Self {
+        Rgba { color, alpha }
+    }
+
+    pub fn opaque(color: Color) -> Self {
+        Rgba::new(color, 1.0)
+    }
+
+    pub fn transparent() -> Self {
+        Rgba::new(Color::black(), 0.0)
+    }
+
+    // straight colour to premultiplied
+    pub fn premultiply(self) -> Self {
+        Rgba::new(self.color.scale(self.alpha), self.alpha)
+    }
+
+    // premultiplied colour back to straight; transparent pixels come out black
+    pub fn unpremultiply(self) -> Self {
+        if self.alpha <= 0.0 {
+            return Rgba::new(Color::black(), self.alpha);
+        }
+        Rgba::new(self.color.scale(1.0 / self.alpha), self.alpha)
+    }
+
+    pub fn equals(&self, other: &Rgba) -> bool {
+        self.color.equals(&other.color) && math_utils::f64_equals(self.alpha, other.alpha)
+    }
+}
+
+// Porter and Duff's operators for combining a source with a destination, both
+// premultiplied. Each keeps some fraction of the source and of the destination
+// depending on how much of the other covers the pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PorterDuff {
+    // source in front of destination
+    Over,
+    // source only where the destination is
+    In,
+    // source only where the destination isn't
+    Out,
+    // source over destination, but only where the destination is
+    Atop,
+    // each only where the other isn't
+    Xor,
+}
+
+impl PorterDuff {
+    pub fn apply(self, source: Rgba, destination: Rgba) -> Rgba {
+        let (a, b) = (source.alpha, destination.alpha);
+        let (fa, fb) = match self {
+            PorterDuff::Over => (1.0, 1.0 - a),
+            PorterDuff::In => (b, 0.0),
+            PorterDuff::Out => (1.0 - b, 0.0),
+            PorterDuff::Atop => (b, 1.0 - a),
+            PorterDuff::Xor => (1.0 - b, 1.0 - a),
+        };
+        Rgba::new(
+            source.color.scale(fa).add(destination.color.scale(fb)),
+            a * fa + b * fb,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red(alpha: f64) -> Rgba {
+        Rgba::new(Color::new(1.0, 0.0, 0.0), alpha).premultiply()
+    }
+
+    fn blue(alpha: f64) -> Rgba {
+        Rgba::new(Color::new(0.0, 0.0, 1.0), alpha).premultiply()
+    }
+
+    #[test]
+    fn premultiplying() {
+        let straight = Rgba::new(Color::new(0.8, 0.4, 0.2), 0.5);
+        let premultiplied = straight.premultiply();
+        assert!(premultiplied.equals(&Rgba::new(Color::new(0.4, 0.2, 0.1), 0.5)));
+        assert!(premultiplied.unpremultiply().equals(&straight));
+        assert!(Rgba::new(Color::new(1.0, 1.0, 1.0), 0.0)
+            .unpremultiply()
+            .equals(&Rgba::transparent()));
+    }
+
+    #[test]
+    fn opaque_operands() {
+        let (src, dst) = (red(1.0), blue(1.0));
+        assert!(PorterDuff::Over.apply(src, dst).equals(&src));
+        assert!(PorterDuff::In.apply(src, dst).equals(&src));
+        assert!(PorterDuff::Out.apply(src, dst).equals(&Rgba::transparent()));
+        assert!(PorterDuff::Atop.apply(src, dst).equals(&src));
+        assert!(PorterDuff::Xor.apply(src, dst).equals(&Rgba::transparent()));
+        // nothing underneath
+        let empty = Rgba::transparent();
+        assert!(PorterDuff::Over.apply(src, empty).equals(&src));
+        assert!(PorterDuff::In.apply(src, empty).equals(&empty));
+        assert!(PorterDuff::Out.apply(src, empty).equals(&src));
+        assert!(PorterDuff::Atop.apply(src, empty).equals(&empty));
+        assert!(PorterDuff::Xor.apply(src, empty).equals(&src));
+    }
+
+    #[test]
+    fn partial_coverage() {
+        let (src, dst) = (red(0.5), blue(0.5));
+        let over = PorterDuff::Over.apply(src, dst);
+        assert!(over.equals(&Rgba::new(Color::new(0.5, 0.0, 0.25), 0.75)));
+        let atop = PorterDuff::Atop.apply(src, dst);
+        assert!(atop.equals(&Rgba::new(Color::new(0.25, 0.0, 0.25), 0.5)));
+        let xor = PorterDuff::Xor.apply(src, dst);
+        assert!(xor.equals(&Rgba::new(Color::new(0.25, 0.0, 0.25), 0.5)));
+        let inside = PorterDuff::In.apply(src, dst);
+        assert!(inside.equals(&Rgba::new(Color::new(0.25, 0.0, 0.0), 0.25)));
+        let out = PorterDuff::Out.apply(src, dst);
+        assert!(out.equals(&inside));
+        // over a solid plate the result is always opaque
+        assert!(math_utils::f64_equals(
+            PorterDuff::Over.apply(src, blue(1.0)).alpha,
+            1.0
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::rng::Rng;
+use crate::spectrum::REFERENCE_WAVELENGTH;
+use crate::tuple::{Color, Tuple};
+
+// Refractive index as a function of wavelength (in nanometres).
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    Constant(f64),
+    // n = a + b / lambda^2, with lambda in micrometres
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum(b_i lambda^2 / (lambda^2 - c_i)), with lambda in micrometres
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Schott N-BK7 crown glass
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+            c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+        }
+    }
+
+    pub fn diamond() -> Self {
+        Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.1750 * 0.1750, 0.1060 * 0.1060, 0.0],
+        }
+    }
+
+    pub fn index_at(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Dispersion::Constant(n) => *n,
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+// Fraction of light reflected at a smooth boundary, for unpolarised light arriving at
+// `cos_i` to the normal and passing from index n1 into n2. 1 under total internal
+// reflection.
+pub fn fresnel(cos_i: f64, n1: f64, n2: f64) -> f64 {
+    let eta = n1 / n2;
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n1 * cos_t - n2 * cos_i) / (n1 * cos_t + n2 * cos_i);
+    (rs * rs + rp * rp) / 2.0
+}
+
+// Smooth glass-like boundary between air and a transparent material. It only ever
+// reflects or refracts in one exact direction, so it can't be sampled towards lights.
+#[derive(Debug, Copy, Clone)]
+pub struct Dielectric {
+    // filter applied to light passing through or off the surface
+    pub color: Color,
+    pub ior: Dispersion,
+}
+
+impl Dielectric {
+    pub fn new(ior: Dispersion) -> Self {
+        Dielectric {
+            color: Color::new(1.0, 1.0, 1.0),
+            ior,
+        }
+    }
+
+    // Continue a ray travelling along `direction` that hit a surface with outward
+    // `normal`. Reflection is chosen with the Fresnel probability, so the returned
+    // weight is just the colour. Rays without a wavelength use the reference one.
+    pub fn scatter(
+        &self,
+        direction: Tuple,
+        normal: Tuple,
+        wavelength: Option<f64>,
+        rng: &mut Rng,
+    ) -> (Tuple, Color) {
+        let direction = direction.normalize();
+        let n = self
+            .ior
+            .index_at(wavelength.unwrap_or(REFERENCE_WAVELENGTH));
+        let entering = direction.dot(normal) < 0.0;
+        let (normal, n1, n2) = if entering {
+            (normal, 1.0, n)
+        } else {
+            (normal.negate(), n, 1.0)
+        };
+
+        let cos_i = -direction.dot(normal);
+        let reflected = direction.reflect(normal);
+        if rng.next_f64() < fresnel(cos_i, n1, n2) {
+            return (reflected, self.color);
+        }
+
+        let eta = n1 / n2;
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let refracted = direction
+            .multiply(eta)
+            .add(normal.multiply(eta * cos_i - cos_t))
+            .normalize();
+        (refracted, self.color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn glass_indices() {
+        assert!((Dispersion::bk7().index_at(587.56) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::diamond().index_at(589.3) - 2.417).abs() < 2e-3);
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!(math_utils::f64_equals(cauchy.index_at(500.0), 1.516));
+        assert!(math_utils::f64_equals(
+            Dispersion::Constant(1.33).index_at(400.0),
+            1.33
+        ));
+        // normal dispersion: blue bends more than red
+        for d in [Dispersion::bk7(), Dispersion::diamond(), cauchy] {
+            assert!(d.index_at(450.0) > d.index_at(650.0));
+        }
+    }
+
+    #[test]
+    fn fresnel_reflectance() {
+        // 4% at normal incidence for n = 1.5
+        assert!(math_utils::f64_equals(fresnel(1.0, 1.0, 1.5), 0.04));
+        assert!(math_utils::f64_equals(fresnel(0.0, 1.0, 1.5), 1.0));
+        // beyond the critical angle inside the glass
+        assert!(math_utils::f64_equals(fresnel(0.5, 1.5, 1.0), 1.0));
+    }
+
+    #[test]
+    fn refraction_follows_snell() {
+        let glass = Dielectric::new(Dispersion::Constant(1.5));
+        let normal = Tuple::new_vector(0.0, 1.0, 0.0);
+        let s = std::f64::consts::FRAC_1_SQRT_2;
+        let incoming = Tuple::new_vector(s, -s, 0.0);
+        let mut rng = Rng::new(4);
+        let mut refracted = 0;
+        for _ in 0..1000 {
+            let (d, weight) = glass.scatter(incoming, normal, None, &mut rng);
+            assert!(weight.equals(&Color::new(1.0, 1.0, 1.0)));
+            if d.y < 0.0 {
+                refracted += 1;
+                let sin_t = d.x / d.magnitude();
+                assert!(math_utils::f64_equals(sin_t * 1.5, s));
+            } else {
+                assert!(d.equals(&Tuple::new_vector(s, s, 0.0)));
+            }
+        }
+        // about 5% reflect at 45 degrees
+        assert!(refracted > 920 && refracted < 980, "{}", refracted);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        let glass = Dielectric::new(Dispersion::Constant(1.5));
+        let normal = Tuple::new_vector(0.0, 1.0, 0.0);
+        // leaving the glass at a grazing angle
+        let incoming = Tuple::new_vector(0.9, 0.1, 0.0).normalize();
+        let mut rng = Rng::new(1);
+        for _ in 0..20 {
+            let (d, _) = glass.scatter(incoming, normal, None, &mut rng);
+            assert!(d.y < 0.0);
+        }
+    }
+
+    #[test]
+    fn wavelength_changes_the_refracted_direction() {
+        let prism = Dielectric::new(Dispersion::diamond());
+        let normal = Tuple::new_vector(0.0, 1.0, 0.0);
+        let incoming = Tuple::new_vector(0.6, -0.8, 0.0);
+        let refract = |wavelength| {
+            let mut rng = Rng::new(0);
+            loop {
+                let (d, _) = prism.scatter(incoming, normal, Some(wavelength), &mut rng);
+                if d.y < 0.0 {
+                    return d;
+                }
+            }
+        };
+        // blue is bent further towards the normal
+        assert!(refract(450.0).x < refract(650.0).x);
+    }
+}
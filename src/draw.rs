@@ -0,0 +1,473 @@
+use crate::canvas::Canvas;
+use crate::composite::{PorterDuff, Rgba};
+use crate::tuple::Color;
+
+// 2D drawing on a canvas, for overlays and plots. Coordinates are in pixels with whole
+// numbers at pixel centres, and anything off the canvas is quietly clipped.
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// a blank column and row between characters
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+// Rows of a 5x7 glyph, top first, with the leftmost pixel in bit 4. Lower case letters
+// share the capitals, and anything unknown is drawn as a question mark.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '\'' => [0x0c, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// width and height in pixels that draw_text will cover
+pub fn text_size(text: &str) -> (usize, usize) {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    if longest == 0 {
+        return (0, 0);
+    }
+    (longest * ADVANCE - 1, lines.len() * LINE_HEIGHT - 1)
+}
+
+// fractional part, and what's left of the pixel after it
+fn fpart(v: f64) -> f64 {
+    v - v.floor()
+}
+
+fn rfpart(v: f64) -> f64 {
+    1.0 - fpart(v)
+}
+
+// Liang-Barsky: the part of the segment inside the box, or None if it misses entirely
+fn clip_line(
+    (x0, y0, x1, y1): (f64, f64, f64, f64),
+    (x_min, y_min, x_max, y_max): (f64, f64, f64, f64),
+) -> Option<(f64, f64, f64, f64)> {
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for (p, q) in [
+        (-dx, x0 - x_min),
+        (dx, x_max - x0),
+        (-dy, y0 - y_min),
+        (dy, y_max - y0),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = f64::max(t0, q / p);
+        } else {
+            t1 = f64::min(t1, q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some((x0 + t0 * dx, y0 + t0 * dy, x0 + t1 * dx, y0 + t1 * dy))
+}
+
+impl Canvas {
+    pub fn plot(&mut self, x: isize, y: isize, color: Color) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height() {
+            self.write_rgba(x as usize, y as usize, Rgba::opaque(color));
+        }
+    }
+
+    // lay `color` over the pixel as if it covered `coverage` of it
+    pub fn blend_pixel(&mut self, x: isize, y: isize, color: Color, coverage: f64) {
+        if x < 0 || y < 0 || x as usize >= self.width() || y as usize >= self.height() {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let coverage = coverage.clamp(0.0, 1.0);
+        let source = Rgba::new(color.scale(coverage), coverage);
+        self.write_rgba(x, y, PorterDuff::Over.apply(source, self.rgba_at(x, y)));
+    }
+
+    // Xiaolin Wu's anti-aliased line
+    pub fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, color: Color) {
+        if ![x0, y0, x1, y1].iter().all(|v| v.is_finite()) {
+            return;
+        }
+        // only walk the part near the canvas, leaving room for the anti-aliased neighbours
+        // and end caps so what lands on the canvas is unchanged
+        let margin = 2.0;
+        let bounds = (
+            -margin,
+            -margin,
+            self.width() as f64 + margin,
+            self.height() as f64 + margin,
+        );
+        let (x0, y0, x1, y1) = match clip_line((x0, y0, x1, y1), bounds) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let (mut x0, mut y0, mut x1, mut y1) = if steep {
+            (y0, x0, y1, x1)
+        } else {
+            (x0, y0, x1, y1)
+        };
+        if x0 > x1 {
+            std::mem::swap(&mut x0, &mut x1);
+            std::mem::swap(&mut y0, &mut y1);
+        }
+        let dx = x1 - x0;
+        let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+        let mut plot = |x: f64, y: f64, coverage: f64| {
+            let (x, y) = if steep { (y, x) } else { (x, y) };
+            self.blend_pixel(x as isize, y as isize, color, coverage);
+        };
+
+        // the end points only get the part of their pixel the line actually reaches
+        let x_start = x0.round();
+        let y_start = y0 + gradient * (x_start - x0);
+        let gap = rfpart(x0 + 0.5);
+        plot(x_start, y_start.floor(), rfpart(y_start) * gap);
+        plot(x_start, y_start.floor() + 1.0, fpart(y_start) * gap);
+
+        let x_end = x1.round();
+        let y_end = y1 + gradient * (x_end - x1);
+        let gap = fpart(x1 + 0.5);
+        plot(x_end, y_end.floor(), rfpart(y_end) * gap);
+        plot(x_end, y_end.floor() + 1.0, fpart(y_end) * gap);
+
+        let mut y = y_start + gradient;
+        let mut x = x_start + 1.0;
+        while x < x_end {
+            plot(x, y.floor(), rfpart(y));
+            plot(x, y.floor() + 1.0, fpart(y));
+            y += gradient;
+            x += 1.0;
+        }
+    }
+
+    // midpoint circle outline
+    pub fn draw_circle(&mut self, cx: isize, cy: isize, radius: isize, color: Color) {
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+        while x >= y {
+            for (dx, dy) in [(x, y), (y, x)] {
+                self.plot(cx + dx, cy + dy, color);
+                self.plot(cx - dx, cy + dy, color);
+                self.plot(cx + dx, cy - dy, color);
+                self.plot(cx - dx, cy - dy, color);
+            }
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    // every pixel whose centre is within `radius` of the centre
+    pub fn fill_circle(&mut self, cx: isize, cy: isize, radius: isize, color: Color) {
+        for dy in -radius..=radius {
+            let half = ((radius * radius - dy * dy) as f64).sqrt() as isize;
+            for x in cx - half..=cx + half {
+                self.plot(x, cy + dy, color);
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: Color) {
+        let clip = |v: isize, size: usize| v.clamp(0, size as isize) as usize;
+        let (x0, x1) = (
+            clip(x, self.width()),
+            clip(x + width as isize, self.width()),
+        );
+        let (y0, y1) = (
+            clip(y, self.height()),
+            clip(y + height as isize, self.height()),
+        );
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.write_rgba(x, y, Rgba::opaque(color));
+            }
+        }
+    }
+
+    // Fills the pixels whose centres are inside the polygon, using the even-odd rule so
+    // self-intersecting outlines get holes.
+    pub fn fill_polygon(&mut self, points: &[(f64, f64)], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        for y in 0..self.height() {
+            let yc = y as f64;
+            let mut crossings: Vec<f64> = Vec::new();
+            for (i, &(ax, ay)) in points.iter().enumerate() {
+                let (bx, by) = points[(i + 1) % points.len()];
+                if (ay <= yc) != (by <= yc) {
+                    crossings.push(ax + (yc - ay) / (by - ay) * (bx - ax));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for span in crossings.chunks_exact(2) {
+                let start = span[0].ceil().max(0.0) as usize;
+                let end = (span[1].ceil().max(0.0) as usize).min(self.width());
+                for x in start..end {
+                    self.write_rgba(x, y, Rgba::opaque(color));
+                }
+            }
+        }
+    }
+
+    // Repaints the 4-connected region of pixels matching the one at (x, y).
+    pub fn flood_fill(&mut self, x: usize, y: usize, color: Color) {
+        let target = match self.get(x, y) {
+            Some(target) if !target.equals(&color) => target,
+            _ => return,
+        };
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if !self.get(x, y).is_some_and(|c| c.equals(&target)) {
+                continue;
+            }
+            self.write_rgba(x, y, Rgba::opaque(color));
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            stack.push((x + 1, y));
+            stack.push((x, y + 1));
+        }
+    }
+
+    // Text in the built-in 5x7 font with its top left corner at (x, y).
+    pub fn draw_text(&mut self, x: isize, y: isize, text: &str, color: Color) {
+        for (line_index, line) in text.split('\n').enumerate() {
+            let top = y + (line_index * LINE_HEIGHT) as isize;
+            for (i, c) in line.chars().enumerate() {
+                let left = x + (i * ADVANCE) as isize;
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for column in 0..GLYPH_WIDTH {
+                        if bits & (0x10 >> column) != 0 {
+                            self.plot(left + column as isize, top + row as isize, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn white() -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    // the canvas as text, '#' for pixels with any red in them
+    fn picture(c: &Canvas) -> Vec<String> {
+        c.rows()
+            .map(|row| {
+                row.iter()
+                    .map(|p| if p.r() > 0.0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drawing_off_the_canvas_is_clipped() {
+        let mut c = Canvas::new(4, 3);
+        c.plot(-1, 0, white());
+        c.plot(4, 2, white());
+        c.blend_pixel(0, 3, white(), 1.0);
+        c.draw_line(-10.0, -10.0, 20.0, 20.0, white());
+        c.draw_circle(2, 1, 10, white());
+        c.fill_rect(-5, -5, 100, 100, white());
+        c.fill_polygon(&[(-9.0, -9.0), (50.0, 0.0), (0.0, 50.0)], white());
+        c.draw_text(-3, -3, "CLIP", white());
+        assert!(c.pixel_at(3, 2).equals(&white()));
+    }
+
+    #[test]
+    fn long_and_broken_lines_are_clipped_first() {
+        let mut short = Canvas::new(10, 10);
+        short.draw_line(0.0, 0.0, 9.0, 4.5, white());
+        let mut long = Canvas::new(10, 10);
+        long.draw_line(-1e15, -5e14, 1e15, 5e14, white());
+        for x in 1..9 {
+            for y in 0..10 {
+                let (a, b) = (short.pixel_at(x, y).r(), long.pixel_at(x, y).r());
+                assert!(math_utils::f64_equals(a, b), "pixel {} {}", x, y);
+            }
+        }
+        // lines that miss the canvas or aren't finite draw nothing
+        let mut c = Canvas::new(4, 4);
+        c.draw_line(-10.0, -10.0, 20.0, -5.0, white());
+        c.draw_line(f64::NAN, 1.0, 2.0, 2.0, white());
+        c.draw_line(0.0, 1.0, f64::INFINITY, 1.0, white());
+        assert!(c
+            .enumerate_pixels()
+            .all(|(_, _, p)| p.equals(&Color::black())));
+    }
+
+    #[test]
+    fn axis_aligned_lines_are_crisp() {
+        let mut c = Canvas::new(6, 3);
+        c.draw_line(0.0, 1.0, 5.0, 1.0, white());
+        c.draw_line(5.0, 0.0, 5.0, 2.0, white());
+        for x in 1..5 {
+            assert!(c.pixel_at(x, 1).equals(&white()));
+            assert!(c.pixel_at(x, 0).equals(&Color::black()));
+            assert!(c.pixel_at(x, 2).equals(&Color::black()));
+        }
+        // end points on pixel centres get half their pixel
+        assert!(c.pixel_at(0, 1).equals(&Color::new(0.5, 0.5, 0.5)));
+        assert!(c.pixel_at(5, 1).equals(&white()));
+    }
+
+    #[test]
+    fn diagonal_lines_are_anti_aliased() {
+        let mut c = Canvas::new(10, 10);
+        c.draw_line(0.0, 0.0, 9.0, 4.5, white());
+        // every interior column's coverage adds up to one pixel
+        for x in 1..9 {
+            let total: f64 = (0..10).map(|y| c.pixel_at(x, y).r()).sum();
+            assert!(math_utils::f64_equals(total, 1.0), "column {}", x);
+        }
+        assert!(c.pixel_at(3, 1).r() > 0.0 && c.pixel_at(3, 1).r() < 1.0);
+        // steep lines are covered row by row instead
+        let mut c = Canvas::new(10, 10);
+        c.draw_line(2.0, 9.0, 5.0, 0.0, white());
+        for y in 1..9 {
+            let total: f64 = (0..10).map(|x| c.pixel_at(x, y).r()).sum();
+            assert!(math_utils::f64_equals(total, 1.0), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn circles() {
+        let mut c = Canvas::new(7, 7);
+        c.draw_circle(3, 3, 3, white());
+        assert_eq!(
+            picture(&c),
+            ["..###..", ".#...#.", "#.....#", "#.....#", "#.....#", ".#...#.", "..###..",]
+        );
+        let mut c = Canvas::new(5, 5);
+        c.fill_circle(2, 2, 2, white());
+        assert_eq!(picture(&c), ["..#..", ".###.", "#####", ".###.", "..#.."]);
+    }
+
+    #[test]
+    fn rectangles_and_polygons() {
+        let mut c = Canvas::new(5, 4);
+        c.fill_rect(1, 1, 3, 2, white());
+        assert_eq!(picture(&c), [".....", ".###.", ".###.", "....."]);
+
+        let mut c = Canvas::new(5, 5);
+        c.fill_polygon(&[(0.0, -0.5), (4.5, 4.5), (0.0, 4.5)], white());
+        assert_eq!(picture(&c), ["#....", "##...", "###..", "####.", "#####"]);
+
+        // a pentagram's middle is outside under the even-odd rule
+        let star: Vec<(f64, f64)> = (0..5)
+            .map(|i| {
+                let angle = i as f64 * 4.0 * std::f64::consts::PI / 5.0;
+                (10.0 + 9.5 * angle.sin(), 10.0 - 9.5 * angle.cos())
+            })
+            .collect();
+        let mut c = Canvas::new(21, 21);
+        c.fill_polygon(&star, white());
+        assert!(c.pixel_at(10, 10).equals(&Color::black()));
+        assert!(c.pixel_at(10, 2).equals(&white()));
+    }
+
+    #[test]
+    fn flood_fill_stays_inside_outlines() {
+        let mut c = Canvas::new(9, 9);
+        c.draw_circle(4, 4, 3, Color::new(0.0, 1.0, 0.0));
+        c.flood_fill(4, 4, white());
+        assert!(c.pixel_at(4, 4).equals(&white()));
+        assert!(c.pixel_at(2, 4).equals(&white()));
+        assert!(c.pixel_at(0, 0).equals(&Color::black()));
+        assert!(c.pixel_at(8, 4).equals(&Color::black()));
+        // filling with the colour already there does nothing
+        c.flood_fill(0, 0, Color::black());
+        c.flood_fill(20, 20, white());
+        assert!(c.pixel_at(0, 0).equals(&Color::black()));
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(text_size("AB"), (11, 7));
+        assert_eq!(text_size("A\nBCD"), (17, 15));
+        assert_eq!(text_size(""), (0, 0));
+
+        let mut c = Canvas::new(11, 7);
+        c.draw_text(0, 0, "1t", white());
+        assert_eq!(
+            picture(&c),
+            [
+                "..#...#####",
+                ".##.....#..",
+                "..#.....#..",
+                "..#.....#..",
+                "..#.....#..",
+                "..#.....#..",
+                ".###....#..",
+            ]
+        );
+    }
+}
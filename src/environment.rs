@@ -0,0 +1,295 @@
+use std::f64::consts::PI;
+
+use crate::canvas::Canvas;
+use crate::rng::Rng;
+use crate::tuple::{Color, Tuple};
+
+// Light arriving from infinitely far away, stored as an equirectangular image (e.g. one
+// loaded with hdr::read_hdr). The layout matches Projection::Equirectangular: the
+// centre column looks down -z, the top row straight up.
+//
+// Directions are importance sampled in proportion to pixel luminance, weighted by the
+// solid angle each row covers, using a marginal distribution over rows and a
+// conditional one over the pixels of each row.
+pub struct EnvironmentMap {
+    canvas: Canvas,
+    pub intensity: f64,
+    // normalised cumulative distributions; row_cdf over rows, column_cdfs within a row
+    row_cdf: Vec<f64>,
+    column_cdfs: Vec<Vec<f64>>,
+    total_weight: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(canvas: Canvas) -> Self {
+        let mut map = EnvironmentMap {
+            canvas,
+            intensity: 1.0,
+            row_cdf: Vec::new(),
+            column_cdfs: Vec::new(),
+            total_weight: 0.0,
+        };
+
+        let mut row_weights = Vec::with_capacity(map.canvas.height());
+        for y in 0..map.canvas.height() {
+            let weights: Vec<f64> = (0..map.canvas.width()).map(|x| map.weight(x, y)).collect();
+            let (cdf, sum) = cumulative(&weights);
+            map.column_cdfs.push(cdf);
+            row_weights.push(sum);
+        }
+        let (row_cdf, total) = cumulative(&row_weights);
+        map.row_cdf = row_cdf;
+        map.total_weight = total;
+        map
+    }
+
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    // radiance arriving from `direction`; an empty map is black all round
+    pub fn lookup(&self, direction: Tuple) -> Color {
+        if self.canvas.width() == 0 || self.canvas.height() == 0 {
+            return Color::black();
+        }
+        let (x, y) = self.pixel_for(direction);
+        self.canvas.pixel_at(x, y).scale(self.intensity)
+    }
+
+    // Direction towards the environment, the radiance arriving along it and the pdf
+    // with respect to solid angle. None for a completely black map.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Tuple, Color, f64)> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+        let y = sample_cdf(&self.row_cdf, rng.next_f64());
+        let x = sample_cdf(&self.column_cdfs[y], rng.next_f64());
+        let u = (x as f64 + rng.next_f64()) / self.canvas.width() as f64;
+        let v = (y as f64 + rng.next_f64()) / self.canvas.height() as f64;
+        let direction = direction_for(u, v);
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((
+            direction,
+            self.canvas.pixel_at(x, y).scale(self.intensity),
+            pdf,
+        ))
+    }
+
+    // probability density (solid angle) that `sample` returns `direction`
+    pub fn pdf(&self, direction: Tuple) -> f64 {
+        if self.total_weight <= 0.0 {
+            return 0.0;
+        }
+        let direction = direction.normalize();
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel_for(direction);
+        let pixels = (self.canvas.width() * self.canvas.height()) as f64;
+        // density over the unit square, then the Jacobian of the mapping to the sphere
+        let pdf_uv = self.weight(x, y) / self.total_weight * pixels;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn pixel_for(&self, direction: Tuple) -> (usize, usize) {
+        let (u, v) = uv_for(direction);
+        let x = ((u * self.canvas.width() as f64) as usize).min(self.canvas.width() - 1);
+        let y = ((v * self.canvas.height() as f64) as usize).min(self.canvas.height() - 1);
+        (x, y)
+    }
+
+    // sampling weight of a pixel: its luminance times the solid angle of its row
+    fn weight(&self, x: usize, y: usize) -> f64 {
+        let theta = (y as f64 + 0.5) / self.canvas.height() as f64 * PI;
+        self.canvas.pixel_at(x, y).luminance().max(0.0) * theta.sin()
+    }
+}
+
+// longitude and latitude scaled to [0, 1], v = 0 straight up
+fn uv_for(direction: Tuple) -> (f64, f64) {
+    let direction = direction.normalize();
+    let longitude = (-direction.x).atan2(-direction.z);
+    let latitude = direction.y.clamp(-1.0, 1.0).asin();
+    (longitude / (2.0 * PI) + 0.5, 0.5 - latitude / PI)
+}
+
+fn direction_for(u: f64, v: f64) -> Tuple {
+    let longitude = (u - 0.5) * 2.0 * PI;
+    let latitude = (0.5 - v) * PI;
+    Tuple::new_vector(
+        -longitude.sin() * latitude.cos(),
+        latitude.sin(),
+        -longitude.cos() * latitude.cos(),
+    )
+}
+
+// running sum scaled to end at 1, and the total
+fn cumulative(weights: &[f64]) -> (Vec<f64>, f64) {
+    let mut sum = 0.0;
+    let mut cdf: Vec<f64> = weights
+        .iter()
+        .map(|w| {
+            sum += w;
+            sum
+        })
+        .collect();
+    if sum > 0.0 {
+        for c in &mut cdf {
+            *c /= sum;
+        }
+    }
+    (cdf, sum)
+}
+
+// first index whose cumulative value exceeds u
+fn sample_cdf(cdf: &[f64], u: f64) -> usize {
+    cdf.partition_point(|&c| c <= u).min(cdf.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn uniform(width: usize, height: usize, color: Color) -> EnvironmentMap {
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                canvas.write_pixel(x, y, color);
+            }
+        }
+        EnvironmentMap::new(canvas)
+    }
+
+    #[test]
+    fn directions_round_trip_through_uv() {
+        let cases = [
+            (Tuple::new_vector(0.0, 0.0, -1.0), 0.5, 0.5),
+            (Tuple::new_vector(-1.0, 0.0, 0.0), 0.75, 0.5),
+            (Tuple::new_vector(1.0, 0.0, 0.0), 0.25, 0.5),
+            (Tuple::new_vector(0.0, 0.5, -0.5), 0.5, 0.25),
+        ];
+        for (d, u, v) in cases {
+            let (pu, pv) = uv_for(d);
+            assert!(math_utils::f64_equals(pu, u));
+            assert!(math_utils::f64_equals(pv, v));
+            assert!(direction_for(pu, pv).equals(&d.normalize()));
+        }
+        // longitude is meaningless at the poles, latitude isn't
+        let (_, v) = uv_for(Tuple::new_vector(0.0, -1.0, 0.0));
+        assert!(math_utils::f64_equals(v, 1.0));
+    }
+
+    #[test]
+    fn lookup_finds_the_pixel_in_that_direction() {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_pixel(2, 0, Color::new(5.0, 4.0, 3.0));
+        let mut map = EnvironmentMap::new(canvas);
+        map.intensity = 2.0;
+        // up and slightly towards -x is the top row, third column
+        let d = Tuple::new_vector(-0.1, 1.0, -0.1);
+        assert!(map.lookup(d).equals(&Color::new(10.0, 8.0, 6.0)));
+        assert!(map
+            .lookup(Tuple::new_vector(0.0, -1.0, 0.1))
+            .equals(&Color::black()));
+    }
+
+    #[test]
+    fn uniform_map_covers_the_whole_sphere() {
+        let map = uniform(16, 8, Color::new(1.0, 1.0, 1.0));
+        let mut rng = Rng::new(8);
+        let n = 20000;
+        let mut area = 0.0;
+        for _ in 0..n {
+            let (d, radiance, pdf) = map.sample(&mut rng).unwrap();
+            assert!(math_utils::f64_equals(d.magnitude(), 1.0));
+            assert!(radiance.equals(&Color::new(1.0, 1.0, 1.0)));
+            area += 1.0 / pdf;
+        }
+        let area = area / n as f64;
+        assert!((area / (4.0 * PI) - 1.0).abs() < 0.02, "{}", area);
+    }
+
+    #[test]
+    fn samples_follow_the_bright_pixels() {
+        let mut canvas = Canvas::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                canvas.write_pixel(x, y, Color::new(0.01, 0.01, 0.01));
+            }
+        }
+        canvas.write_pixel(5, 1, Color::new(100.0, 100.0, 100.0));
+        let map = EnvironmentMap::new(canvas);
+        let mut rng = Rng::new(3);
+        let n = 1000;
+        let mut hits = 0;
+        for _ in 0..n {
+            let (d, _, pdf) = map.sample(&mut rng).unwrap();
+            assert!(math_utils::f64_equals(pdf, map.pdf(d)));
+            if map.pixel_for(d) == (5, 1) {
+                hits += 1;
+            }
+        }
+        assert!(hits > 950, "{}", hits);
+    }
+
+    #[test]
+    fn estimator_integrates_the_map() {
+        // integral of the radiance over the sphere, estimated with importance sampling
+        let mut canvas = Canvas::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                let v = (x + y) as f64;
+                canvas.write_pixel(x, y, Color::new(v, v, v));
+            }
+        }
+        let map = EnvironmentMap::new(canvas);
+
+        let mut expected = 0.0;
+        for y in 0..4 {
+            let theta0 = y as f64 / 4.0 * PI;
+            let theta1 = (y + 1) as f64 / 4.0 * PI;
+            let row_solid_angle = 2.0 * PI * (theta0.cos() - theta1.cos()) / 8.0;
+            for x in 0..8 {
+                expected += (x + y) as f64 * row_solid_angle;
+            }
+        }
+
+        let mut rng = Rng::new(5);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let (_, radiance, pdf) = map.sample(&mut rng).unwrap();
+            sum += radiance.r() / pdf;
+        }
+        let estimate = sum / n as f64;
+        assert!(
+            (estimate - expected).abs() / expected < 0.02,
+            "{} vs {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn black_maps_cannot_be_sampled() {
+        let map = uniform(4, 2, Color::black());
+        assert!(map.sample(&mut Rng::new(0)).is_none());
+        assert!(math_utils::f64_equals(
+            map.pdf(Tuple::new_vector(0.0, 0.0, 1.0)),
+            0.0
+        ));
+
+        for (width, height) in [(0, 0), (0, 3), (3, 0)] {
+            let empty = EnvironmentMap::new(Canvas::new(width, height));
+            let ahead = Tuple::new_vector(0.0, 0.0, -1.0);
+            assert!(empty.lookup(ahead).equals(&Color::black()));
+            assert!(empty.sample(&mut Rng::new(0)).is_none());
+            assert_eq!(empty.pdf(ahead), 0.0);
+        }
+    }
+}
@@ -0,0 +1,693 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::canvas::Canvas;
+use crate::composite::Rgba;
+use crate::tuple::Color;
+use crate::zlib;
+
+// Minimal OpenEXR support: single-part scanline images with R, G, B and optionally A
+// channels, either uncompressed or ZIP compressed. As in the canvas, colours are
+// premultiplied by alpha. Other channels are skipped when reading; tiled, deep and
+// multi-part files are rejected.
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+const TILED: u32 = 0x200;
+const NON_IMAGE: u32 = 0x800;
+const MULTI_PART: u32 = 0x1000;
+
+const NO_COMPRESSION: u8 = 0;
+const ZIPS_COMPRESSION: u8 = 2;
+const ZIP_COMPRESSION: u8 = 3;
+// deflate can't shrink data by more than this, which bounds what a ZIP file may hold
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExrPixelType {
+    // 16-bit floats: plenty for colour and half the size
+    Half,
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExrCompression {
+    None,
+    // zlib over blocks of 16 scanlines
+    Zip,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ExrOptions {
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+    // add an A channel with the canvas alpha
+    pub alpha: bool,
+}
+
+impl ExrOptions {
+    // what compositing packages write by default
+    pub fn new() -> Self {
+        ExrOptions {
+            pixel_type: ExrPixelType::Half,
+            compression: ExrCompression::Zip,
+            alpha: false,
+        }
+    }
+}
+
+impl Default for ExrOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Nearest half-precision value, rounding ties to even. Too-large values become
+// infinity.
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // infinity, or a quiet NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let e = exponent - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, remainder, halfway) = if e <= 0 {
+        // subnormal or zero
+        if e < -10 {
+            return sign;
+        }
+        let m = mantissa | 0x80_0000;
+        let shift = (14 - e) as u32;
+        (m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        ((e as u32) << 10 | mantissa >> 13, mantissa & 0x1fff, 0x1000)
+    };
+    // a carry out of the mantissa correctly bumps the exponent
+    let rounded = if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    };
+    sign | rounded as u16
+}
+
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if sign != 0 {
+                -magnitude
+            } else {
+                magnitude
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | mantissa << 13),
+        _ => f32::from_bits(sign | (exponent + 112) << 23 | mantissa << 13),
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+fn box2i(x_min: i32, y_min: i32, x_max: i32, y_max: i32) -> Vec<u8> {
+    [x_min, y_min, x_max, y_max]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+// ZIP blocks split the bytes into two interleaved halves and store differences between
+// neighbours before deflating, which helps a lot on smooth float data.
+fn zip_block(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut reordered = vec![0u8; raw.len()];
+    for (i, &byte) in raw.iter().enumerate() {
+        let index = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        reordered[index] = byte;
+    }
+    for i in (1..reordered.len()).rev() {
+        reordered[i] = reordered[i]
+            .wrapping_sub(reordered[i - 1])
+            .wrapping_add(128);
+    }
+    zlib::compress(&reordered)
+}
+
+fn unzip_block(data: &[u8], expected: usize) -> io::Result<Vec<u8>> {
+    let mut reordered = zlib::decompress(data)?;
+    if reordered.len() != expected {
+        return Err(invalid("ZIP block has the wrong size"));
+    }
+    for i in 1..reordered.len() {
+        reordered[i] = reordered[i - 1]
+            .wrapping_add(reordered[i])
+            .wrapping_sub(128);
+    }
+    let half = expected.div_ceil(2);
+    Ok((0..expected)
+        .map(|i| {
+            if i % 2 == 0 {
+                reordered[i / 2]
+            } else {
+                reordered[half + i / 2]
+            }
+        })
+        .collect())
+}
+
+pub fn write_exr<W: Write>(canvas: &Canvas, writer: W, options: &ExrOptions) -> io::Result<()> {
+    if canvas.width() == 0 || canvas.height() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "OpenEXR images can't be empty",
+        ));
+    }
+    let (type_code, sample_size) = match options.pixel_type {
+        ExrPixelType::Half => (1i32, 2),
+        ExrPixelType::Float => (2i32, 4),
+    };
+    let (compression, lines_per_block) = match options.compression {
+        ExrCompression::None => (NO_COMPRESSION, 1),
+        ExrCompression::Zip => (ZIP_COMPRESSION, 16),
+    };
+
+    let mut header = MAGIC.to_vec();
+    header.extend(VERSION.to_le_bytes());
+    // channels are listed, and stored, in alphabetical order
+    let names: &[&str] = if options.alpha {
+        &["A", "B", "G", "R"]
+    } else {
+        &["B", "G", "R"]
+    };
+    let mut channels = Vec::new();
+    for name in names {
+        channels.extend(name.as_bytes());
+        channels.push(0);
+        channels.extend(type_code.to_le_bytes());
+        // linear flag and three reserved bytes, then x and y sampling
+        channels.extend([0, 0, 0, 0]);
+        channels.extend(1i32.to_le_bytes());
+        channels.extend(1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window = box2i(0, 0, canvas.width() as i32 - 1, canvas.height() as i32 - 1);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[compression]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // increasing y
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let mut blocks = Vec::new();
+    for y0 in (0..canvas.height()).step_by(lines_per_block) {
+        let y1 = (y0 + lines_per_block).min(canvas.height());
+        let mut raw = Vec::with_capacity((y1 - y0) * canvas.width() * names.len() * sample_size);
+        for y in y0..y1 {
+            for &name in names {
+                for x in 0..canvas.width() {
+                    let rgba = canvas.rgba_at(x, y);
+                    let value = match name {
+                        "A" => rgba.alpha,
+                        "B" => rgba.color.b(),
+                        "G" => rgba.color.g(),
+                        _ => rgba.color.r(),
+                    } as f32;
+                    match options.pixel_type {
+                        ExrPixelType::Half => raw.extend(f32_to_half(value).to_le_bytes()),
+                        ExrPixelType::Float => raw.extend(value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let data = match options.compression {
+            ExrCompression::Zip => {
+                let compressed = zip_block(&raw);
+                // blocks that don't shrink are stored as they are
+                if compressed.len() < raw.len() {
+                    compressed
+                } else {
+                    raw
+                }
+            }
+            ExrCompression::None => raw,
+        };
+        let mut block = (y0 as i32).to_le_bytes().to_vec();
+        block.extend((data.len() as i32).to_le_bytes());
+        block.extend(data);
+        blocks.push(block);
+    }
+
+    let mut out = BufWriter::new(writer);
+    out.write_all(&header)?;
+    let mut offset = (header.len() + 8 * blocks.len()) as u64;
+    for block in &blocks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += block.len() as u64;
+    }
+    for block in &blocks {
+        out.write_all(block)?;
+    }
+    out.flush()
+}
+
+pub fn read_exr(path: &str) -> io::Result<Canvas> {
+    decode_exr(&std::fs::read(path)?)
+}
+
+struct Channel {
+    name: String,
+    // 0 unsigned int, 1 half, 2 float
+    pixel_type: i32,
+}
+
+impl Channel {
+    fn size(&self) -> usize {
+        if self.pixel_type == 1 {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+pub fn decode_exr(bytes: &[u8]) -> io::Result<Canvas> {
+    if bytes.get(..4) != Some(&MAGIC[..]) {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    let version = read_u32(bytes, 4)?;
+    if version & 0xff != VERSION {
+        return Err(invalid(&format!("unsupported version {}", version & 0xff)));
+    }
+    if version & (TILED | NON_IMAGE | MULTI_PART) != 0 {
+        return Err(invalid("only single-part scanline images are supported"));
+    }
+
+    let mut pos = 8;
+    let mut channels = None;
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = read_string(bytes, &mut pos)?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = read_string(bytes, &mut pos)?;
+        let size = read_u32(bytes, pos)? as usize;
+        let value = take(bytes, pos + 4, size).ok_or_else(|| invalid("truncated header"))?;
+        pos += 4 + size;
+        match name.as_str() {
+            "channels" => channels = Some(read_channels(value)?),
+            "compression" => compression = value.first().copied(),
+            "dataWindow" if size == 16 => {
+                let v = |i: usize| read_u32(value, i * 4).map(|v| v as i32);
+                window = Some((v(0)?, v(1)?, v(2)?, v(3)?));
+            }
+            _ => {}
+        }
+    }
+    let missing = |name| invalid(&format!("missing {} attribute", name));
+    let channels = channels.ok_or_else(|| missing("channels"))?;
+    let compression = compression.ok_or_else(|| missing("compression"))?;
+    let (x_min, y_min, x_max, y_max) = window.ok_or_else(|| missing("dataWindow"))?;
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid("empty data window"));
+    }
+    let span = |min: i32, max: i32| usize::try_from(max as i64 - min as i64 + 1).ok();
+    let (width, height) = span(x_min, x_max)
+        .zip(span(y_min, y_max))
+        .ok_or_else(|| invalid("data window too large"))?;
+    let lines_per_block = match compression {
+        NO_COMPRESSION | ZIPS_COMPRESSION => 1,
+        ZIP_COMPRESSION => 16,
+        c => return Err(invalid(&format!("unsupported compression {}", c))),
+    };
+
+    // a file can't describe more pixels than its offset table and blocks could hold,
+    // so a corrupt header fails here instead of allocating a huge canvas
+    let too_large = || invalid("image too large for the file");
+    let block_count = height.div_ceil(lines_per_block);
+    let line_size = channels
+        .iter()
+        .try_fold(0usize, |sum, c| {
+            sum.checked_add(c.size().checked_mul(width)?)
+        })
+        .ok_or_else(too_large)?;
+    let image_size = line_size.checked_mul(height).ok_or_else(too_large)?;
+    let ratio = if compression == NO_COMPRESSION {
+        1
+    } else {
+        MAX_DEFLATE_RATIO
+    };
+    let available = bytes.len().saturating_sub(pos);
+    if block_count > available / 8
+        || image_size / ratio > available
+        || width.checked_mul(height).is_none()
+    {
+        return Err(too_large());
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    for i in 0..block_count {
+        let offset = usize::try_from(read_u64(bytes, pos + i * 8)?)
+            .map_err(|_| invalid("scanline offset out of range"))?;
+        let y = read_u32(bytes, offset)? as i32 as i64 - y_min as i64;
+        let size = read_u32(bytes, offset.saturating_add(4))? as usize;
+        let data = take(bytes, offset.saturating_add(8), size)
+            .ok_or_else(|| invalid("truncated pixel data"))?;
+        if y < 0 || y as usize >= height {
+            return Err(invalid("scanline block outside the data window"));
+        }
+        let y = y as usize;
+        let lines = lines_per_block.min(height - y);
+        let expected = line_size * lines;
+        let raw = if size == expected {
+            data.to_vec()
+        } else if compression != NO_COMPRESSION {
+            unzip_block(data, expected)?
+        } else {
+            return Err(invalid("scanline block has the wrong size"));
+        };
+
+        let mut samples = raw.as_slice();
+        for line in 0..lines {
+            let mut rgba = vec![[0.0, 0.0, 0.0, 1.0]; width];
+            for channel in &channels {
+                let (values, rest) = samples.split_at(channel.size() * width);
+                samples = rest;
+                let index = match channel.name.as_str() {
+                    "R" => 0,
+                    "G" => 1,
+                    "B" => 2,
+                    "A" => 3,
+                    _ => continue,
+                };
+                for (x, v) in values.chunks_exact(channel.size()).enumerate() {
+                    rgba[x][index] = match channel.pixel_type {
+                        0 => u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as f64,
+                        1 => half_to_f32(u16::from_le_bytes([v[0], v[1]])) as f64,
+                        _ => f32::from_le_bytes([v[0], v[1], v[2], v[3]]) as f64,
+                    };
+                }
+            }
+            for (x, [r, g, b, a]) in rgba.into_iter().enumerate() {
+                canvas.write_rgba(x, y + line, Rgba::new(Color::new(r, g, b), a));
+            }
+        }
+    }
+    Ok(canvas)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// the `count` bytes starting at `pos`, if the file is long enough
+fn take(bytes: &[u8], pos: usize, count: usize) -> Option<&[u8]> {
+    bytes.get(pos..)?.get(..count)
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> io::Result<u32> {
+    take(bytes, pos, 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> io::Result<u64> {
+    let low = read_u32(bytes, pos)? as u64;
+    let high = read_u32(bytes, pos.saturating_add(4))? as u64;
+    Ok(high << 32 | low)
+}
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> io::Result<String> {
+    let rest = bytes.get(*pos..).unwrap_or(&[]);
+    let end = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("unexpected end of header"))?;
+    *pos += end + 1;
+    Ok(String::from_utf8_lossy(&rest[..end]).to_string())
+}
+
+fn read_channels(value: &[u8]) -> io::Result<Vec<Channel>> {
+    let mut channels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let name = read_string(value, &mut pos)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let pixel_type = read_u32(value, pos)? as i32;
+        let sampling = (read_u32(value, pos + 8)?, read_u32(value, pos + 12)?);
+        pos += 16;
+        if !(0..=2).contains(&pixel_type) {
+            return Err(invalid(&format!("unknown pixel type {}", pixel_type)));
+        }
+        if sampling != (1, 1) {
+            return Err(invalid("subsampled channels are not supported"));
+        }
+        channels.push(Channel { name, pixel_type });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(canvas: &Canvas, options: &ExrOptions) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_exr(canvas, &mut bytes, options).unwrap();
+        bytes
+    }
+
+    // values that are exact in half precision, including some well above 1
+    fn test_canvas(width: usize, height: usize) -> Canvas {
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = Color::new(x as f64 * 0.25, (y % 7) as f64 * 16.0, -0.5);
+                canvas.write_pixel(x, y, color);
+            }
+        }
+        canvas
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.0), 0);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert!(half_to_f32(f32_to_half(f32::NAN)).is_nan());
+        // smallest subnormal, and something that underflows
+        assert_eq!(f32_to_half(2f32.powi(-24)), 1);
+        assert_eq!(f32_to_half(2f32.powi(-26)), 0);
+        // ties round to even
+        assert_eq!(f32_to_half(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+
+        for half in (0..0x7c00).step_by(7) {
+            assert_eq!(f32_to_half(half_to_f32(half)), half);
+            assert_eq!(f32_to_half(half_to_f32(half | 0x8000)), half | 0x8000);
+        }
+        assert_eq!(half_to_f32(0x3555), 0.333_251_95);
+    }
+
+    #[test]
+    fn header_layout() {
+        let bytes = encode(&test_canvas(3, 2), &ExrOptions::new());
+        assert_eq!(bytes[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let mut channel_list = b"channels\0chlist\0".to_vec();
+        channel_list.extend(55i32.to_le_bytes());
+        channel_list.extend(b"B\0\x01\0\0\0\0\0\0\0\x01\0\0\0\x01\0\0\0");
+        assert_eq!(bytes[8..8 + channel_list.len()], channel_list[..]);
+
+        let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+        let window = find(b"dataWindow\0box2i\0").unwrap() + 17;
+        assert_eq!(
+            bytes[window..window + 20],
+            [16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]
+        );
+        let compression = find(b"compression\0compression\0").unwrap() + 24;
+        assert_eq!(bytes[compression..compression + 5], [1, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn uncompressed_scanlines() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(0, 0, Color::new(1.0, 2.0, 3.0));
+        let options = ExrOptions {
+            pixel_type: ExrPixelType::Float,
+            compression: ExrCompression::None,
+            alpha: false,
+        };
+        let bytes = encode(&canvas, &options);
+        // one offset then one block: y, size, then B, G and R for the whole line
+        let offset = u64::from_le_bytes(
+            bytes[bytes.len() - 40..bytes.len() - 32]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(offset as usize, bytes.len() - 32);
+        let block = &bytes[bytes.len() - 32..];
+        assert_eq!(block[..8], [0, 0, 0, 0, 24, 0, 0, 0]);
+        let floats: Vec<f32> = block[8..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats, [3.0, 0.0, 2.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let canvas = test_canvas(13, 37);
+        for pixel_type in [ExrPixelType::Half, ExrPixelType::Float] {
+            for compression in [ExrCompression::None, ExrCompression::Zip] {
+                let options = ExrOptions {
+                    pixel_type,
+                    compression,
+                    alpha: false,
+                };
+                let bytes = encode(&canvas, &options);
+                let decoded = decode_exr(&bytes).unwrap();
+                assert_eq!((decoded.width(), decoded.height()), (13, 37));
+                for y in 0..37 {
+                    for x in 0..13 {
+                        assert!(
+                            decoded.pixel_at(x, y).equals(&canvas.pixel_at(x, y)),
+                            "{:?} {:?} at {} {}",
+                            pixel_type,
+                            compression,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+        let zipped = encode(&canvas, &ExrOptions::new());
+        let uncompressed = encode(
+            &canvas,
+            &ExrOptions {
+                pixel_type: ExrPixelType::Half,
+                compression: ExrCompression::None,
+                alpha: false,
+            },
+        );
+        assert!(zipped.len() < uncompressed.len() / 2);
+    }
+
+    #[test]
+    fn zip_predictor_round_trip() {
+        for len in [0, 1, 2, 7, 100] {
+            let raw: Vec<u8> = (0..len).map(|i| (i * 97 % 251) as u8).collect();
+            assert_eq!(unzip_block(&zip_block(&raw), len).unwrap(), raw);
+        }
+    }
+
+    #[test]
+    fn rejecting_bad_files() {
+        assert!(decode_exr(b"PF\n1 1\n-1.0\n").is_err());
+        let mut bytes = encode(&test_canvas(2, 2), &ExrOptions::new());
+        let mut tiled = bytes.clone();
+        tiled[5] = 0x02;
+        assert!(decode_exr(&tiled).is_err());
+        bytes.truncate(bytes.len() - 3);
+        match decode_exr(&bytes) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("truncated file decoded"),
+        }
+    }
+
+    #[test]
+    fn rejecting_corrupt_sizes_and_offsets() {
+        let options = ExrOptions {
+            compression: ExrCompression::None,
+            ..ExrOptions::new()
+        };
+        let bytes = encode(&test_canvas(3, 2), &options);
+        let expect_invalid = |bytes: &[u8]| match decode_exr(bytes) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("corrupt file decoded"),
+        };
+        let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+        let window = find(b"dataWindow\0box2i\0").unwrap() + 21;
+        // two offsets, then a block per line: y, size, then three channels of three halves
+        let table = bytes.len() - 2 * (8 + 3 * 3 * 2) - 2 * 8;
+        assert_eq!(bytes[table..table + 8], ((table + 16) as u64).to_le_bytes());
+
+        for (x_min, x_max) in [(i32::MIN, i32::MAX), (0, 1 << 20), (-5, 1 << 30)] {
+            let mut huge = bytes.clone();
+            huge[window..window + 4].copy_from_slice(&x_min.to_le_bytes());
+            huge[window + 8..window + 12].copy_from_slice(&x_max.to_le_bytes());
+            expect_invalid(&huge);
+        }
+        for offset in [
+            u64::MAX,
+            u64::MAX - 3,
+            bytes.len() as u64 - 5,
+            0x1_0000_0000,
+        ] {
+            let mut corrupt = bytes.clone();
+            corrupt[table..table + 8].copy_from_slice(&offset.to_le_bytes());
+            expect_invalid(&corrupt);
+        }
+        // every truncation fails cleanly rather than panicking
+        for len in 0..bytes.len() {
+            assert!(decode_exr(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn alpha_channel() {
+        let mut canvas = test_canvas(3, 2);
+        canvas.write_rgba(1, 1, Rgba::new(Color::new(0.25, 0.5, 0.0), 0.5));
+        canvas.write_rgba(2, 0, Rgba::transparent());
+        let mut options = ExrOptions::new();
+        options.alpha = true;
+        let bytes = encode(&canvas, &options);
+        let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+        assert!(find(b"chlist\0\x49\0\0\0A\0").is_some());
+
+        let decoded = decode_exr(&bytes).unwrap();
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!(decoded.rgba_at(x, y).equals(&canvas.rgba_at(x, y)));
+            }
+        }
+        // without the channel everything reads back opaque
+        assert!(decode_exr(&encode(&canvas, &ExrOptions::new()))
+            .unwrap()
+            .is_opaque());
+    }
+}
@@ -0,0 +1,521 @@
+use std::f64::consts::PI;
+
+use crate::canvas::Canvas;
+use crate::composite::Rgba;
+use crate::tuple::Color;
+
+// Image processing that makes new canvases: resampling, cropping, flips and rotations,
+// and convolution. Filters work on the premultiplied colour and alpha alike, and
+// samples past the edges repeat the border pixels.
+
+type Sample = [f64; 4];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Resample {
+    Nearest,
+    // tent filter over the four nearest pixels
+    Bilinear,
+    // Catmull-Rom cubic: sharper than bilinear, with slight ringing
+    Bicubic,
+    // windowed sinc over three lobes, the sharpest of the four
+    Lanczos3,
+}
+
+impl Resample {
+    // how far from the sample the filter reaches, in source pixels
+    fn support(self) -> f64 {
+        match self {
+            Resample::Nearest => 0.5,
+            Resample::Bilinear => 1.0,
+            Resample::Bicubic => 2.0,
+            Resample::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Resample::Nearest => (x < 0.5) as u8 as f64,
+            Resample::Bilinear => (1.0 - x).max(0.0),
+            Resample::Bicubic => {
+                if x < 1.0 {
+                    1.5 * x * x * x - 2.5 * x * x + 1.0
+                } else if x < 2.0 {
+                    -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Resample::Lanczos3 => {
+                if x == 0.0 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// A convolution kernel with odd dimensions, centred on its middle weight. Weights are
+// listed row by row.
+#[derive(Debug, Clone)]
+pub struct Kernel {
+    pub width: usize,
+    pub height: usize,
+    pub weights: Vec<f64>,
+}
+
+impl Kernel {
+    pub fn new(width: usize, height: usize, weights: Vec<f64>) -> Self {
+        if width.is_multiple_of(2) || height.is_multiple_of(2) || weights.len() != width * height {
+            panic!(
+                "Kernel error. A {}x{} kernel needs odd dimensions and {} weights, but got {}!",
+                width,
+                height,
+                width * height,
+                weights.len()
+            );
+        }
+        Kernel {
+            width,
+            height,
+            weights,
+        }
+    }
+
+    // Normalised 1D Gaussian out to three standard deviations, laid out horizontally.
+    // A sigma of zero or less doesn't blur at all.
+    pub fn gaussian_row(sigma: f64) -> Self {
+        if sigma.is_nan() || sigma <= 0.0 {
+            return Kernel::new(1, 1, vec![1.0]);
+        }
+        let radius = (3.0 * sigma).ceil().max(0.0) as usize;
+        let weights: Vec<f64> = (0..2 * radius + 1)
+            .map(|i| {
+                let x = i as f64 - radius as f64;
+                (-x * x / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        Kernel::new(weights.len(), 1, weights.iter().map(|w| w / sum).collect())
+    }
+
+    pub fn transposed(&self) -> Self {
+        let weights = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .map(|(x, y)| self.weights[y * self.width + x])
+            .collect();
+        Kernel::new(self.height, self.width, weights)
+    }
+
+    pub fn sharpen() -> Self {
+        Kernel::new(3, 3, vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0])
+    }
+
+    // horizontal Sobel operator; transpose it for the vertical one
+    pub fn sobel() -> Self {
+        Kernel::new(3, 3, vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0])
+    }
+}
+
+fn to_samples(canvas: &Canvas) -> Vec<Sample> {
+    (0..canvas.height())
+        .flat_map(|y| (0..canvas.width()).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let p = canvas.rgba_at(x, y);
+            [p.color.r(), p.color.g(), p.color.b(), p.alpha]
+        })
+        .collect()
+}
+
+// Filtering an opaque canvas leaves it opaque; rounding in the weights would otherwise
+// leave alpha a hair under one.
+fn from_samples(width: usize, height: usize, samples: &[Sample], opaque: bool) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    for (i, s) in samples.iter().enumerate() {
+        let alpha = if opaque { 1.0 } else { s[3] };
+        let rgba = Rgba::new(Color::new(s[0], s[1], s[2]), alpha);
+        canvas.write_rgba(i % width, i / width, rgba);
+    }
+    canvas
+}
+
+// Resamples `count` lines of `len` samples each, where line n's samples start at
+// n * line_step and are `step` apart. Returns the lines at `new_len` samples each, one
+// after another.
+fn resample_lines(
+    samples: &[Sample],
+    count: usize,
+    len: usize,
+    line_step: usize,
+    step: usize,
+    new_len: usize,
+    filter: Resample,
+) -> Vec<Vec<Sample>> {
+    let scale = len as f64 / new_len as f64;
+    // when shrinking, the filter widens to cover every source pixel
+    let stretch = scale.max(1.0);
+    let taps: Vec<Vec<(usize, f64)>> = (0..new_len)
+        .map(|i| {
+            let centre = (i as f64 + 0.5) * scale - 0.5;
+            if filter == Resample::Nearest {
+                let nearest = ((i as f64 + 0.5) * scale) as usize;
+                return vec![(nearest.min(len - 1), 1.0)];
+            }
+            let radius = filter.support() * stretch;
+            let first = (centre - radius).floor() as isize;
+            let last = (centre + radius).ceil() as isize;
+            let mut taps: Vec<(usize, f64)> = (first..=last)
+                .map(|j| {
+                    let w = filter.weight((j as f64 - centre) / stretch);
+                    (j.clamp(0, len as isize - 1) as usize, w)
+                })
+                .filter(|&(_, w)| w != 0.0)
+                .collect();
+            let sum: f64 = taps.iter().map(|&(_, w)| w).sum();
+            for tap in &mut taps {
+                tap.1 /= sum;
+            }
+            taps
+        })
+        .collect();
+
+    (0..count)
+        .map(|n| {
+            taps.iter()
+                .map(|taps| {
+                    let mut out = [0.0; 4];
+                    for &(j, w) in taps {
+                        let s = samples[n * line_step + j * step];
+                        for c in 0..4 {
+                            out[c] += s[c] * w;
+                        }
+                    }
+                    out
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl Canvas {
+    fn remap<F>(&self, width: usize, height: usize, source: F) -> Canvas
+    where
+        F: Fn(usize, usize) -> (usize, usize),
+    {
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = source(x, y);
+                canvas.write_rgba(x, y, self.rgba_at(sx, sy));
+            }
+        }
+        canvas
+    }
+
+    pub fn resize(&self, width: usize, height: usize, filter: Resample) -> Canvas {
+        if self.width() == 0 || self.height() == 0 || width == 0 || height == 0 {
+            return Canvas::new(width, height);
+        }
+        let samples = to_samples(self);
+        // rows first, then columns of the result
+        let rows: Vec<Sample> = resample_lines(
+            &samples,
+            self.height(),
+            self.width(),
+            self.width(),
+            1,
+            width,
+            filter,
+        )
+        .concat();
+        let columns = resample_lines(&rows, width, self.height(), 1, width, height, filter);
+        let mut out = vec![[0.0; 4]; width * height];
+        for (x, column) in columns.iter().enumerate() {
+            for (y, s) in column.iter().enumerate() {
+                out[y * width + x] = *s;
+            }
+        }
+        from_samples(width, height, &out, self.is_opaque())
+    }
+
+    // The part of the canvas inside the rectangle, which is clipped to the canvas.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Canvas {
+        let x = x.min(self.width());
+        let y = y.min(self.height());
+        let width = width.min(self.width() - x);
+        let height = height.min(self.height() - y);
+        self.remap(width, height, |cx, cy| (x + cx, y + cy))
+    }
+
+    // mirrored left to right
+    pub fn flip_horizontal(&self) -> Canvas {
+        let w = self.width();
+        self.remap(w, self.height(), |x, y| (w - 1 - x, y))
+    }
+
+    pub fn flip_vertical(&self) -> Canvas {
+        let h = self.height();
+        self.remap(self.width(), h, |x, y| (x, h - 1 - y))
+    }
+
+    // a quarter turn clockwise
+    pub fn rotate_90(&self) -> Canvas {
+        let h = self.height();
+        self.remap(h, self.width(), |x, y| (y, h - 1 - x))
+    }
+
+    pub fn rotate_180(&self) -> Canvas {
+        let (w, h) = (self.width(), self.height());
+        self.remap(w, h, |x, y| (w - 1 - x, h - 1 - y))
+    }
+
+    // a quarter turn anticlockwise
+    pub fn rotate_270(&self) -> Canvas {
+        let w = self.width();
+        self.remap(self.height(), w, |x, y| (w - 1 - y, x))
+    }
+
+    pub fn convolve(&self, kernel: &Kernel) -> Canvas {
+        let (w, h) = (self.width() as isize, self.height() as isize);
+        let samples = to_samples(self);
+        let (cx, cy) = ((kernel.width / 2) as isize, (kernel.height / 2) as isize);
+        let mut out = vec![[0.0; 4]; samples.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = [0.0; 4];
+                for ky in 0..kernel.height {
+                    let sy = (y + ky as isize - cy).clamp(0, h - 1);
+                    for kx in 0..kernel.width {
+                        let weight = kernel.weights[ky * kernel.width + kx];
+                        if weight == 0.0 {
+                            continue;
+                        }
+                        let sx = (x + kx as isize - cx).clamp(0, w - 1);
+                        let s = samples[(sy * w + sx) as usize];
+                        for c in 0..4 {
+                            sum[c] += s[c] * weight;
+                        }
+                    }
+                }
+                out[(y * w + x) as usize] = sum;
+            }
+        }
+        from_samples(self.width(), self.height(), &out, self.is_opaque())
+    }
+
+    // separable, so it stays fast for wide blurs
+    pub fn gaussian_blur(&self, sigma: f64) -> Canvas {
+        let row = Kernel::gaussian_row(sigma);
+        self.convolve(&row).convolve(&row.transposed())
+    }
+
+    pub fn sharpen(&self) -> Canvas {
+        self.convolve(&Kernel::sharpen())
+    }
+
+    // Sobel gradient magnitude of each colour channel, as an opaque image.
+    pub fn edges(&self) -> Canvas {
+        let sobel = Kernel::sobel();
+        let gx = self.convolve(&sobel);
+        let gy = self.convolve(&sobel.transposed());
+        let mut canvas = Canvas::new(self.width(), self.height());
+        for (x, y, a) in gx.enumerate_pixels() {
+            let b = gy.pixel_at(x, y);
+            let magnitude = |a: f64, b: f64| (a * a + b * b).sqrt();
+            canvas.write_pixel(
+                x,
+                y,
+                Color::new(
+                    magnitude(a.r(), b.r()),
+                    magnitude(a.g(), b.g()),
+                    magnitude(a.b(), b.b()),
+                ),
+            );
+        }
+        canvas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn grey(v: f64) -> Color {
+        Color::new(v, v, v)
+    }
+
+    // a 3x2 canvas with a different value in every pixel
+    fn numbered() -> Canvas {
+        let mut c = Canvas::new(3, 2);
+        for (i, (x, y)) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]
+            .into_iter()
+            .enumerate()
+        {
+            c.write_pixel(x, y, grey(i as f64));
+        }
+        c
+    }
+
+    fn values(c: &Canvas) -> Vec<Vec<f64>> {
+        c.rows()
+            .map(|row| row.iter().map(|p| p.r()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn flips_and_rotations() {
+        let c = numbered();
+        assert_eq!(
+            values(&c.flip_horizontal()),
+            [[2.0, 1.0, 0.0], [5.0, 4.0, 3.0]]
+        );
+        assert_eq!(
+            values(&c.flip_vertical()),
+            [[3.0, 4.0, 5.0], [0.0, 1.0, 2.0]]
+        );
+        assert_eq!(values(&c.rotate_90()), [[3.0, 0.0], [4.0, 1.0], [5.0, 2.0]]);
+        assert_eq!(values(&c.rotate_180()), [[5.0, 4.0, 3.0], [2.0, 1.0, 0.0]]);
+        assert_eq!(
+            values(&c.rotate_270()),
+            [[2.0, 5.0], [1.0, 4.0], [0.0, 3.0]]
+        );
+        assert_eq!(
+            values(&c.rotate_90().rotate_90().rotate_90().rotate_90()),
+            values(&c)
+        );
+    }
+
+    #[test]
+    fn cropping_clips_to_the_canvas() {
+        let c = numbered();
+        assert_eq!(values(&c.crop(1, 0, 2, 2)), [[1.0, 2.0], [4.0, 5.0]]);
+        assert_eq!(values(&c.crop(2, 1, 10, 10)), [[5.0]]);
+        let empty = c.crop(5, 5, 2, 2);
+        assert_eq!((empty.width(), empty.height()), (0, 0));
+    }
+
+    #[test]
+    fn resizing_keeps_flat_images_flat() {
+        let mut c = Canvas::new(7, 5);
+        for x in 0..7 {
+            for y in 0..5 {
+                c.write_rgba(x, y, Rgba::new(grey(0.25), 0.5));
+            }
+        }
+        for filter in [
+            Resample::Nearest,
+            Resample::Bilinear,
+            Resample::Bicubic,
+            Resample::Lanczos3,
+        ] {
+            for (w, h) in [(3, 2), (16, 11), (7, 5)] {
+                let r = c.resize(w, h, filter);
+                assert_eq!((r.width(), r.height()), (w, h));
+                for (x, y, p) in r.enumerate_pixels() {
+                    assert!(p.equals(&grey(0.25)), "{:?} {} {}", filter, x, y);
+                    assert!(math_utils::f64_equals(r.alpha_at(x, y), 0.5));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resampling_filters() {
+        let c = numbered();
+        assert_eq!(
+            values(&c.resize(6, 2, Resample::Nearest)),
+            [
+                [0.0, 0.0, 1.0, 1.0, 2.0, 2.0],
+                [3.0, 3.0, 4.0, 4.0, 5.0, 5.0]
+            ]
+        );
+        // doubling with a tent puts new samples a quarter of the way between old ones
+        let row = &values(&c.resize(6, 2, Resample::Bilinear))[0];
+        for (got, want) in row.iter().zip([0.0, 0.25, 0.75, 1.25, 1.75, 2.0]) {
+            assert!(math_utils::f64_equals(*got, want), "{:?}", row);
+        }
+        // shrinking averages over the source pixels
+        let mut stripes = Canvas::new(8, 1);
+        for x in (0..8).step_by(2) {
+            stripes.write_pixel(x, 0, grey(1.0));
+        }
+        let half = stripes.resize(4, 1, Resample::Bilinear);
+        assert!(half.pixel_at(1, 0).equals(&grey(0.5)));
+        assert!(half.pixel_at(2, 0).equals(&grey(0.5)));
+
+        // interpolating filters pass through the original samples at the same size
+        for filter in [Resample::Bicubic, Resample::Lanczos3] {
+            let same = c.resize(3, 2, filter);
+            for (x, y, p) in same.enumerate_pixels() {
+                assert!(p.equals(&c.pixel_at(x, y)), "{:?} {} {}", filter, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn kernels() {
+        let row = Kernel::gaussian_row(1.0);
+        assert_eq!((row.width, row.height), (7, 1));
+        assert!(math_utils::f64_equals(row.weights.iter().sum(), 1.0));
+        assert!(row.weights[3] > row.weights[2] && row.weights[2] > row.weights[1]);
+        let column = row.transposed();
+        assert_eq!((column.width, column.height), (1, 7));
+        for sigma in [0.0, -1.0, f64::NAN] {
+            assert_eq!(Kernel::gaussian_row(sigma).weights, [1.0]);
+        }
+        let sobel = Kernel::sobel().transposed();
+        assert_eq!(sobel.weights[..3], [-1.0, -2.0, -1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Kernel error")]
+    fn kernels_need_odd_sizes() {
+        Kernel::new(2, 1, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn blurring_spreads_a_point() {
+        let mut c = Canvas::new(9, 9);
+        c.write_pixel(4, 4, grey(1.0));
+        let blurred = c.gaussian_blur(1.0);
+        let total: f64 = blurred.enumerate_pixels().map(|(_, _, p)| p.r()).sum();
+        assert!(math_utils::f64_equals(total, 1.0));
+        assert!(blurred.pixel_at(4, 4).r() < 1.0);
+        assert!(blurred.pixel_at(3, 4).r() > blurred.pixel_at(2, 4).r());
+        assert!(math_utils::f64_equals(
+            blurred.pixel_at(3, 4).r(),
+            blurred.pixel_at(4, 5).r()
+        ));
+        assert!(blurred.is_opaque());
+        assert!(c.gaussian_blur(0.0).pixel_at(4, 4).equals(&grey(1.0)));
+    }
+
+    #[test]
+    fn sharpening_and_edges() {
+        let mut c = Canvas::new(6, 3);
+        for y in 0..3 {
+            for x in 3..6 {
+                c.write_pixel(x, y, grey(1.0));
+            }
+        }
+        let sharp = c.sharpen();
+        // overshoot on both sides of the step, flat areas untouched
+        assert!(sharp.pixel_at(2, 1).equals(&grey(-1.0)));
+        assert!(sharp.pixel_at(3, 1).equals(&grey(2.0)));
+        assert!(sharp.pixel_at(0, 1).equals(&grey(0.0)));
+        assert!(sharp.pixel_at(5, 1).equals(&grey(1.0)));
+
+        let edges = c.edges();
+        assert!(edges.is_opaque());
+        assert!(edges.pixel_at(0, 1).equals(&grey(0.0)));
+        assert!(edges.pixel_at(2, 1).equals(&grey(4.0)));
+        assert!(edges.pixel_at(3, 1).equals(&grey(4.0)));
+        assert!(edges.pixel_at(5, 0).equals(&grey(0.0)));
+    }
+}
@@ -0,0 +1,239 @@
+use std::io;
+
+use crate::canvas::Canvas;
+use crate::tuple::Color;
+
+// Reader for Radiance .hdr (RGBE) images, as used for most HDR environment maps.
+// Only the usual "-Y height +X width" orientation is supported, with either flat or
+// adaptive run-length encoded scanlines.
+
+pub fn read_hdr(path: &str) -> io::Result<Canvas> {
+    decode_hdr(&std::fs::read(path)?)
+}
+
+pub fn decode_hdr(bytes: &[u8]) -> io::Result<Canvas> {
+    let mut pos = 0;
+
+    let magic = read_line(bytes, &mut pos)?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+    loop {
+        let line = read_line(bytes, &mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(invalid(&format!("unsupported format {}", format)));
+            }
+        }
+    }
+
+    let resolution = read_line(bytes, &mut pos)?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) if w > 0 && h > 0 => (w, h),
+            _ => return Err(invalid(&format!("bad resolution {}", resolution))),
+        },
+        _ => return Err(invalid(&format!("unsupported orientation {}", resolution))),
+    };
+
+    // check the file could hold that many pixels before allocating them
+    let needed = min_scanline_bytes(width)
+        .and_then(|n| n.checked_mul(height))
+        .filter(|&n| n <= bytes.len() - pos)
+        .and(width.checked_mul(height));
+    if needed.is_none() {
+        return Err(invalid(&format!("{} too large for the file", resolution)));
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    for y in 0..height {
+        let scanline = read_scanline(bytes, &mut pos, width)?;
+        for (x, rgbe) in scanline.iter().enumerate() {
+            canvas.write_pixel(x, y, rgbe_to_color(*rgbe));
+        }
+    }
+    Ok(canvas)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_line(bytes: &[u8], pos: &mut usize) -> io::Result<String> {
+    let rest = &bytes[*pos..];
+    let end = rest
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid("unexpected end of header"))?;
+    *pos += end + 1;
+    Ok(String::from_utf8_lossy(&rest[..end]).trim_end().to_string())
+}
+
+// fewest bytes a scanline of `width` pixels can be stored in
+fn min_scanline_bytes(width: usize) -> Option<usize> {
+    if rle_width(width) {
+        // the marker, then every channel in runs of at most 127 pixels, two bytes each
+        Some(4 + 8 * width.div_ceil(127))
+    } else {
+        width.checked_mul(4)
+    }
+}
+
+fn rle_width(width: usize) -> bool {
+    (8..0x8000).contains(&width)
+}
+
+// the `count` bytes starting at `pos`, if the file is long enough
+fn take(bytes: &[u8], pos: usize, count: usize) -> Option<&[u8]> {
+    bytes.get(pos..)?.get(..count)
+}
+
+fn read_scanline(bytes: &[u8], pos: &mut usize, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let truncated = || invalid("truncated pixel data");
+    let start = take(bytes, *pos, 4).ok_or_else(truncated)?;
+
+    // adaptive RLE: a 2 2 marker, the width, then each channel run-length coded on its own
+    if rle_width(width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0 {
+        if ((start[2] as usize) << 8 | start[3] as usize) != width {
+            return Err(invalid("scanline width mismatch"));
+        }
+        *pos += 4;
+        let mut scanline = vec![[0u8; 4]; width];
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = *bytes.get(*pos).ok_or_else(truncated)? as usize;
+                *pos += 1;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = *bytes.get(*pos).ok_or_else(truncated)?;
+                    *pos += 1;
+                    if count > width - x {
+                        return Err(invalid("run overflows scanline"));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 || count > width - x {
+                        return Err(invalid("bad run length"));
+                    }
+                    let values = take(bytes, *pos, count).ok_or_else(truncated)?;
+                    *pos += count;
+                    for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                }
+            }
+        }
+        return Ok(scanline);
+    }
+
+    if start[0] == 1 && start[1] == 1 && start[2] == 1 {
+        return Err(invalid("old-style run-length encoding is not supported"));
+    }
+    let size = width.checked_mul(4).ok_or_else(truncated)?;
+    let data = take(bytes, *pos, size).ok_or_else(truncated)?;
+    *pos += size;
+    Ok(data
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2], p[3]])
+        .collect())
+}
+
+// shared exponent: each mantissa is scaled by 2^(e - 128) / 256
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::black();
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - 136);
+    Color::new(rgbe[0] as f64 * f, rgbe[1] as f64 * f, rgbe[2] as f64 * f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: usize, height: usize) -> Vec<u8> {
+        format!(
+            "#?RADIANCE\n# made by hand\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=1.0\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn decoding_flat_scanlines() {
+        let mut bytes = header(2, 2);
+        // 1.0, 0.5 and 2.0 share the exponent of 1.0
+        bytes.extend([128, 64, 0, 129, 0, 0, 0, 0]);
+        bytes.extend([128, 128, 128, 128, 1, 2, 3, 140]);
+        let canvas = decode_hdr(&bytes).unwrap();
+        assert_eq!((canvas.width(), canvas.height()), (2, 2));
+        assert!(canvas.pixel_at(0, 0).equals(&Color::new(1.0, 0.5, 0.0)));
+        assert!(canvas.pixel_at(1, 0).equals(&Color::black()));
+        assert!(canvas.pixel_at(0, 1).equals(&Color::new(0.5, 0.5, 0.5)));
+        assert!(canvas.pixel_at(1, 1).equals(&Color::new(16.0, 32.0, 48.0)));
+    }
+
+    #[test]
+    fn decoding_run_length_encoded_scanlines() {
+        let width = 10;
+        let mut bytes = header(width, 1);
+        bytes.extend([2, 2, 0, width as u8]);
+        // red: a run of 6, then 4 literals
+        bytes.extend([128 + 6, 200, 4, 1, 2, 3, 4]);
+        // green and blue: one run each
+        bytes.extend([128 + 10, 0]);
+        bytes.extend([128 + 10, 100]);
+        // exponent: two runs
+        bytes.extend([128 + 5, 129, 128 + 5, 128]);
+        let canvas = decode_hdr(&bytes).unwrap();
+        assert!(canvas
+            .pixel_at(0, 0)
+            .equals(&Color::new(200.0 / 128.0, 0.0, 100.0 / 128.0)));
+        assert!(canvas
+            .pixel_at(9, 0)
+            .equals(&Color::new(4.0 / 256.0, 0.0, 100.0 / 256.0)));
+    }
+
+    #[test]
+    fn rejecting_bad_files() {
+        assert!(decode_hdr(b"P3\n1 1\n255\n").is_err());
+        assert!(decode_hdr(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n+X 1 -Y 1\n\0\0\0\0").is_err());
+
+        let mut truncated = header(2, 1);
+        truncated.extend([128, 128, 128, 128]);
+        match decode_hdr(&truncated) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("truncated file decoded"),
+        }
+
+        let mut overflow = header(8, 1);
+        overflow.extend([2, 2, 0, 8, 128 + 9, 1]);
+        assert!(decode_hdr(&overflow).is_err());
+    }
+
+    #[test]
+    fn rejecting_sizes_the_file_cannot_hold() {
+        // neither overflows nor allocates the canvas the header asks for
+        for (width, height) in [
+            (usize::MAX, usize::MAX),
+            (usize::MAX / 2, 1),
+            (100_000, 100_000),
+        ] {
+            let mut bytes = header(width, height);
+            bytes.extend([2, 2, 0, 8, 128 + 8, 1]);
+            match decode_hdr(&bytes) {
+                Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+                Ok(_) => panic!("{}x{} decoded", width, height),
+            }
+        }
+    }
+}
@@ -0,0 +1,685 @@
+use std::f64::consts::PI;
+
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::tuple::{Color, Tuple};
+
+// Falloff with distance d: 1 / (constant + linear * d + quadratic * d^2).
+// The default (1, 0, 0) means no falloff, which is what the book's point light does.
+#[derive(Debug, Copy, Clone)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Attenuation {
+    pub fn none() -> Self {
+        Attenuation {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.0,
+        }
+    }
+
+    pub fn inverse_square() -> Self {
+        Attenuation {
+            constant: 0.0,
+            linear: 0.0,
+            quadratic: 1.0,
+        }
+    }
+
+    pub fn factor(&self, distance: f64) -> f64 {
+        let denom = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        if denom <= 0.0 {
+            1.0
+        } else {
+            1.0 / denom
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PointLight {
+    pub position: Tuple,
+    pub intensity: Color,
+    pub attenuation: Attenuation,
+}
+
+impl PointLight {
+    pub fn new(position: Tuple, intensity: Color) -> Self {
+        PointLight {
+            position,
+            intensity,
+            attenuation: Attenuation::none(),
+        }
+    }
+}
+
+// Light arriving from infinitely far away (the sun): same direction and strength
+// everywhere. `direction` is the way the light travels, not the way to the light.
+#[derive(Debug, Copy, Clone)]
+pub struct DirectionalLight {
+    pub direction: Tuple,
+    pub intensity: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Tuple, intensity: Color) -> Self {
+        DirectionalLight {
+            direction: direction.normalize(),
+            intensity,
+        }
+    }
+}
+
+// Point light restricted to a cone. Full strength inside `inner_angle`, fading smoothly
+// to nothing at `outer_angle` (both half-angles in radians, measured from `direction`).
+#[derive(Debug, Copy, Clone)]
+pub struct SpotLight {
+    pub position: Tuple,
+    pub direction: Tuple,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub intensity: Color,
+    pub attenuation: Attenuation,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Tuple,
+        direction: Tuple,
+        inner_angle: f64,
+        outer_angle: f64,
+        intensity: Color,
+    ) -> Self {
+        SpotLight {
+            position,
+            direction: direction.normalize(),
+            inner_angle,
+            outer_angle,
+            intensity,
+            attenuation: Attenuation::none(),
+        }
+    }
+
+    // 1 inside the inner cone, 0 outside the outer cone, smoothstep in between
+    pub fn cone_factor(&self, point: Tuple) -> f64 {
+        let to_point = point.minus(self.position).normalize();
+        let cos_angle = to_point.dot(self.direction);
+        let cos_inner = self.inner_angle.cos();
+        let cos_outer = self.outer_angle.cos();
+        if cos_angle >= cos_inner {
+            return 1.0;
+        }
+        if cos_angle <= cos_outer || cos_inner <= cos_outer {
+            return 0.0;
+        }
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AreaShape {
+    // parallelogram spanned by two full edge vectors from one corner
+    Rect {
+        corner: Tuple,
+        full_uvec: Tuple,
+        full_vvec: Tuple,
+    },
+    Sphere {
+        center: Tuple,
+        radius: f64,
+    },
+}
+
+// A light with extent. Instead of a single position it is sampled on a usteps x vsteps
+// grid of cells (one point per cell), so shadows come out as a fraction of the light
+// that is visible rather than all-or-nothing.
+#[derive(Debug, Copy, Clone)]
+pub struct AreaLight {
+    pub shape: AreaShape,
+    pub usteps: usize,
+    pub vsteps: usize,
+    pub intensity: Color,
+    // false samples the centre of every cell, which gives banded but noise-free shadows
+    pub jitter: bool,
+}
+
+impl AreaLight {
+    pub fn new_rect(
+        corner: Tuple,
+        full_uvec: Tuple,
+        usteps: usize,
+        full_vvec: Tuple,
+        vsteps: usize,
+        intensity: Color,
+    ) -> Self {
+        AreaLight {
+            shape: AreaShape::Rect {
+                corner,
+                full_uvec,
+                full_vvec,
+            },
+            usteps,
+            vsteps,
+            intensity,
+            jitter: true,
+        }
+    }
+
+    pub fn new_sphere(
+        center: Tuple,
+        radius: f64,
+        usteps: usize,
+        vsteps: usize,
+        intensity: Color,
+    ) -> Self {
+        AreaLight {
+            shape: AreaShape::Sphere { center, radius },
+            usteps,
+            vsteps,
+            intensity,
+            jitter: true,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.usteps * self.vsteps
+    }
+
+    pub fn position(&self) -> Tuple {
+        match self.shape {
+            AreaShape::Rect {
+                corner,
+                full_uvec,
+                full_vvec,
+            } => corner
+                .add(full_uvec.multiply(0.5))
+                .add(full_vvec.multiply(0.5)),
+            AreaShape::Sphere { center, .. } => center,
+        }
+    }
+
+    // Point inside cell (u, v). With jitter the offset within the cell comes from `rng`,
+    // otherwise the cell centre is used.
+    pub fn point_on_light(&self, u: usize, v: usize, rng: &mut Rng) -> Tuple {
+        let (ju, jv) = if self.jitter {
+            (rng.next_f64(), rng.next_f64())
+        } else {
+            (0.5, 0.5)
+        };
+        let su = (u as f64 + ju) / self.usteps as f64;
+        let sv = (v as f64 + jv) / self.vsteps as f64;
+
+        match self.shape {
+            AreaShape::Rect {
+                corner,
+                full_uvec,
+                full_vvec,
+            } => corner
+                .add(full_uvec.multiply(su))
+                .add(full_vvec.multiply(sv)),
+            AreaShape::Sphere { center, radius } => {
+                // equal-area mapping of the unit square onto the sphere
+                let z = 1.0 - 2.0 * sv;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * su;
+                center.add(Tuple::new_vector(r * phi.cos(), r * phi.sin(), z).multiply(radius))
+            }
+        }
+    }
+
+    // one point per cell, stratified over the whole light
+    pub fn sample_points(&self, rng: &mut Rng) -> Vec<Tuple> {
+        let mut points = Vec::with_capacity(self.samples());
+        for v in 0..self.vsteps {
+            for u in 0..self.usteps {
+                points.push(self.point_on_light(u, v, rng));
+            }
+        }
+        points
+    }
+
+    pub fn area(&self) -> f64 {
+        match self.shape {
+            AreaShape::Rect {
+                full_uvec,
+                full_vvec,
+                ..
+            } => full_uvec.cross(full_vvec).magnitude(),
+            AreaShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+        }
+    }
+
+    // Surface normal at a point on the light. Rectangles emit from both faces, so the
+    // sign of their normal doesn't matter.
+    pub fn normal_at(&self, light_point: Tuple) -> Tuple {
+        match self.shape {
+            AreaShape::Rect {
+                full_uvec,
+                full_vvec,
+                ..
+            } => full_uvec.cross(full_vvec).normalize(),
+            AreaShape::Sphere { center, .. } => light_point.minus(center).normalize(),
+        }
+    }
+
+    // Uniform random point on the light, with its normal and the pdf per unit area.
+    pub fn sample_surface(&self, rng: &mut Rng) -> (Tuple, Tuple, f64) {
+        let u = rng.next_usize(self.usteps.max(1));
+        let v = rng.next_usize(self.vsteps.max(1));
+        let jittered = AreaLight {
+            jitter: true,
+            ..*self
+        };
+        let point = jittered.point_on_light(u, v, rng);
+        (point, self.normal_at(point), 1.0 / self.area())
+    }
+
+    // Density, per unit solid angle seen from `from`, of sample_surface picking
+    // `light_point`. 0 if the point is seen edge-on or from behind (spheres).
+    pub fn pdf_solid_angle(&self, from: Tuple, light_point: Tuple) -> f64 {
+        let v = light_point.minus(from);
+        let distance_squared = v.dot(v);
+        let cos_light = self.normal_at(light_point).dot(v.normalize().negate());
+        let cos_light = match self.shape {
+            AreaShape::Rect { .. } => cos_light.abs(),
+            AreaShape::Sphere { .. } => cos_light,
+        };
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cos_light * self.area())
+    }
+
+    // Closest positive distance along `ray` at which it hits the light's surface.
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        match self.shape {
+            AreaShape::Rect {
+                corner,
+                full_uvec,
+                full_vvec,
+            } => {
+                let normal = full_uvec.cross(full_vvec);
+                let denom = normal.dot(ray.direction);
+                if denom.abs() < f64::EPSILON {
+                    return None;
+                }
+                let t = normal.dot(corner.minus(ray.origin)) / denom;
+                if t <= 0.0 {
+                    return None;
+                }
+                // express the hit in the (u, v) frame of the parallelogram
+                let p = ray.position(t).minus(corner);
+                let uu = full_uvec.dot(full_uvec);
+                let uv = full_uvec.dot(full_vvec);
+                let vv = full_vvec.dot(full_vvec);
+                let pu = p.dot(full_uvec);
+                let pv = p.dot(full_vvec);
+                let det = uu * vv - uv * uv;
+                let a = (pu * vv - pv * uv) / det;
+                let b = (pv * uu - pu * uv) / det;
+                if (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b) {
+                    Some(t)
+                } else {
+                    None
+                }
+            }
+            AreaShape::Sphere { center, radius } => {
+                let oc = ray.origin.minus(center);
+                let a = ray.direction.dot(ray.direction);
+                let b = 2.0 * ray.direction.dot(oc);
+                let c = oc.dot(oc) - radius * radius;
+                let disc = b * b - 4.0 * a * c;
+                if disc < 0.0 {
+                    return None;
+                }
+                let t1 = (-b - disc.sqrt()) / (2.0 * a);
+                let t2 = (-b + disc.sqrt()) / (2.0 * a);
+                if t1 > 0.0 {
+                    Some(t1)
+                } else if t2 > 0.0 {
+                    Some(t2)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // Fraction of the light visible from `point`, between 0 and 1.
+    // `is_shadowed(light_point, point)` reports whether anything blocks the segment.
+    pub fn intensity_at<F>(&self, point: Tuple, rng: &mut Rng, is_shadowed: F) -> f64
+    where
+        F: Fn(Tuple, Tuple) -> bool,
+    {
+        let samples = self.samples();
+        if samples == 0 {
+            return 0.0;
+        }
+        let visible = self
+            .sample_points(rng)
+            .into_iter()
+            .filter(|light_point| !is_shadowed(*light_point, point))
+            .count();
+        visible as f64 / samples as f64
+    }
+}
+
+// Every light the renderer understands. Shading and shadow code should only go through
+// these methods so that it works the same for all of them.
+#[derive(Debug, Copy, Clone)]
+pub enum Light {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Spot(SpotLight),
+    Area(AreaLight),
+}
+
+impl Light {
+    // normalised vector from `point` towards the light
+    pub fn direction_from(&self, point: Tuple) -> Tuple {
+        match self {
+            Light::Point(l) => l.position.minus(point).normalize(),
+            Light::Spot(l) => l.position.minus(point).normalize(),
+            Light::Area(l) => l.position().minus(point).normalize(),
+            Light::Directional(l) => l.direction.negate(),
+        }
+    }
+
+    // how far a shadow ray has to travel before it reaches the light
+    pub fn distance_from(&self, point: Tuple) -> f64 {
+        match self {
+            Light::Point(l) => l.position.minus(point).magnitude(),
+            Light::Spot(l) => l.position.minus(point).magnitude(),
+            Light::Area(l) => l.position().minus(point).magnitude(),
+            Light::Directional(_) => f64::INFINITY,
+        }
+    }
+
+    // Light reaching `point`, ignoring occlusion: intensity after distance attenuation
+    // and the spot cone.
+    pub fn intensity_at(&self, point: Tuple) -> Color {
+        match self {
+            Light::Point(l) => {
+                let d = l.position.minus(point).magnitude();
+                l.intensity.scale(l.attenuation.factor(d))
+            }
+            Light::Spot(l) => {
+                let d = l.position.minus(point).magnitude();
+                l.intensity
+                    .scale(l.attenuation.factor(d) * l.cone_factor(point))
+            }
+            Light::Directional(l) => l.intensity,
+            Light::Area(l) => l.intensity,
+        }
+    }
+
+    // Fraction of the light visible from `point`: 0 or 1 for lights without extent,
+    // anything in between for area lights.
+    // `is_shadowed(point, direction, distance)` casts a shadow ray from `point` along
+    // the normalised `direction` and reports whether it is blocked within `distance`.
+    pub fn visibility<F>(&self, point: Tuple, rng: &mut Rng, is_shadowed: F) -> f64
+    where
+        F: Fn(Tuple, Tuple, f64) -> bool,
+    {
+        match self {
+            Light::Area(l) => l.intensity_at(point, rng, |light_point, p| {
+                let v = light_point.minus(p);
+                is_shadowed(p, v.normalize(), v.magnitude())
+            }),
+            _ => {
+                let blocked =
+                    is_shadowed(point, self.direction_from(point), self.distance_from(point));
+                if blocked {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn rect_light() -> AreaLight {
+        AreaLight::new_rect(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_vector(2.0, 0.0, 0.0),
+            4,
+            Tuple::new_vector(0.0, 0.0, 1.0),
+            2,
+            Color::new(1.0, 1.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn creating_rect_light() {
+        let light = rect_light();
+        assert_eq!(light.samples(), 8);
+        assert!(light.position().equals(&Tuple::new_point(1.0, 0.0, 0.5)));
+    }
+
+    #[test]
+    fn cell_centres_without_jitter() {
+        let mut light = rect_light();
+        light.jitter = false;
+        let mut rng = Rng::new(0);
+        let cases = [
+            (0, 0, Tuple::new_point(0.25, 0.0, 0.25)),
+            (1, 0, Tuple::new_point(0.75, 0.0, 0.25)),
+            (0, 1, Tuple::new_point(0.25, 0.0, 0.75)),
+            (2, 0, Tuple::new_point(1.25, 0.0, 0.25)),
+            (3, 1, Tuple::new_point(1.75, 0.0, 0.75)),
+        ];
+        for (u, v, expected) in cases {
+            assert!(light.point_on_light(u, v, &mut rng).equals(&expected));
+        }
+    }
+
+    #[test]
+    fn jittered_points_stay_in_their_cell() {
+        let light = rect_light();
+        let mut rng = Rng::new(3);
+        for _ in 0..50 {
+            let p = light.point_on_light(2, 1, &mut rng);
+            assert!(p.x >= 1.0 && p.x <= 1.5);
+            assert!(p.z >= 0.5 && p.z <= 1.0);
+        }
+    }
+
+    #[test]
+    fn sphere_light_points_lie_on_sphere() {
+        let center = Tuple::new_point(1.0, 2.0, 3.0);
+        let light = AreaLight::new_sphere(center, 0.5, 4, 4, Color::new(1.0, 1.0, 1.0));
+        let mut rng = Rng::new(11);
+        let points = light.sample_points(&mut rng);
+        assert_eq!(points.len(), 16);
+        for p in points {
+            assert!(math_utils::f64_equals(p.minus(center).magnitude(), 0.5));
+        }
+    }
+
+    #[test]
+    fn fractional_intensity() {
+        let light = rect_light();
+        let mut rng = Rng::new(1);
+        let point = Tuple::new_point(1.0, -1.0, 0.5);
+
+        assert!(math_utils::f64_equals(
+            light.intensity_at(point, &mut rng, |_, _| false),
+            1.0
+        ));
+        assert!(math_utils::f64_equals(
+            light.intensity_at(point, &mut rng, |_, _| true),
+            0.0
+        ));
+        // an occluder covering the half of the light with x < 1
+        assert!(math_utils::f64_equals(
+            light.intensity_at(point, &mut rng, |l, _| l.x < 1.0),
+            0.5
+        ));
+    }
+
+    #[test]
+    fn rect_light_as_emitter() {
+        let light = rect_light();
+        assert!(math_utils::f64_equals(light.area(), 2.0));
+        let mut rng = Rng::new(13);
+        for _ in 0..20 {
+            let (p, n, pdf) = light.sample_surface(&mut rng);
+            assert!(math_utils::f64_equals(p.y, 0.0));
+            assert!(p.x >= 0.0 && p.x <= 2.0 && p.z >= 0.0 && p.z <= 1.0);
+            assert!(math_utils::f64_equals(n.y.abs(), 1.0));
+            assert!(math_utils::f64_equals(pdf, 0.5));
+        }
+
+        let ray = Ray::new(
+            Tuple::new_point(1.0, -3.0, 0.5),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+        );
+        let t = light.intersect(&ray).unwrap();
+        assert!(math_utils::f64_equals(t, 3.0));
+        // d^2 / (cos * area) straight on
+        let pdf = light.pdf_solid_angle(ray.origin, ray.position(t));
+        assert!(math_utils::f64_equals(pdf, 9.0 / 2.0));
+
+        let beside = Ray::new(
+            Tuple::new_point(3.0, -3.0, 0.5),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+        );
+        assert!(light.intersect(&beside).is_none());
+    }
+
+    #[test]
+    fn sphere_light_as_emitter() {
+        let light = AreaLight::new_sphere(
+            Tuple::new_point(0.0, 0.0, 5.0),
+            1.0,
+            2,
+            2,
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let ray = Ray::new(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_vector(0.0, 0.0, 1.0),
+        );
+        let t = light.intersect(&ray).unwrap();
+        assert!(math_utils::f64_equals(t, 4.0));
+        let origin = Tuple::new_point(0.0, 0.0, 0.0);
+        // near side visible, far side hidden behind the sphere itself
+        assert!(light.pdf_solid_angle(origin, Tuple::new_point(0.0, 0.0, 4.0)) > 0.0);
+        assert!(math_utils::f64_equals(
+            light.pdf_solid_angle(origin, Tuple::new_point(0.0, 0.0, 6.0)),
+            0.0
+        ));
+    }
+
+    #[test]
+    fn attenuation() {
+        assert!(math_utils::f64_equals(
+            Attenuation::none().factor(10.0),
+            1.0
+        ));
+        assert!(math_utils::f64_equals(
+            Attenuation::inverse_square().factor(2.0),
+            0.25
+        ));
+
+        let mut l = PointLight::new(Tuple::new_point(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        l.attenuation = Attenuation::inverse_square();
+        let light = Light::Point(l);
+        let c = light.intensity_at(Tuple::new_point(0.0, 0.0, 4.0));
+        assert!(c.equals(&Color::new(0.0625, 0.0625, 0.0625)));
+    }
+
+    #[test]
+    fn directional_light() {
+        let light = Light::Directional(DirectionalLight::new(
+            Tuple::new_vector(0.0, -2.0, 0.0),
+            Color::new(1.0, 0.9, 0.8),
+        ));
+        for p in [
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_point(100.0, -5.0, 3.0),
+        ] {
+            assert!(light
+                .direction_from(p)
+                .equals(&Tuple::new_vector(0.0, 1.0, 0.0)));
+            assert!(light.intensity_at(p).equals(&Color::new(1.0, 0.9, 0.8)));
+            assert!(light.distance_from(p).is_infinite());
+        }
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let spot = SpotLight::new(
+            Tuple::new_point(0.0, 10.0, 0.0),
+            Tuple::new_vector(0.0, -1.0, 0.0),
+            PI / 8.0,
+            PI / 4.0,
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let light = Light::Spot(spot);
+        // straight below
+        assert!(light
+            .intensity_at(Tuple::new_point(0.0, 0.0, 0.0))
+            .equals(&Color::new(1.0, 1.0, 1.0)));
+        // 60 degrees off axis, outside the cone
+        let outside = Tuple::new_point(10.0 * (PI / 3.0).tan(), 0.0, 0.0);
+        assert!(light.intensity_at(outside).equals(&Color::black()));
+        // in the falloff band
+        let angle = 3.0 * PI / 16.0;
+        let edge = Tuple::new_point(10.0 * angle.tan(), 0.0, 0.0);
+        let f = spot.cone_factor(edge);
+        assert!(f > 0.0 && f < 1.0);
+    }
+
+    #[test]
+    fn visibility_is_generic_over_lights() {
+        let mut rng = Rng::new(0);
+        let point = Tuple::new_point(0.0, 0.0, 0.0);
+        let lights = [
+            Light::Point(PointLight::new(
+                Tuple::new_point(0.0, 5.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            )),
+            Light::Directional(DirectionalLight::new(
+                Tuple::new_vector(0.0, -1.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            )),
+            Light::Spot(SpotLight::new(
+                Tuple::new_point(0.0, 5.0, 0.0),
+                Tuple::new_vector(0.0, -1.0, 0.0),
+                0.3,
+                0.5,
+                Color::new(1.0, 1.0, 1.0),
+            )),
+            Light::Area(AreaLight::new_rect(
+                Tuple::new_point(-1.0, 5.0, -1.0),
+                Tuple::new_vector(2.0, 0.0, 0.0),
+                2,
+                Tuple::new_vector(0.0, 0.0, 2.0),
+                2,
+                Color::new(1.0, 1.0, 1.0),
+            )),
+        ];
+        for light in lights {
+            // a roof at y = 3 blocks everything above
+            let v = light.visibility(point, &mut rng, |p, dir, dist| {
+                let t = (3.0 - p.y) / dir.y;
+                t > 0.0 && t < dist
+            });
+            assert!(math_utils::f64_equals(v, 0.0));
+            let v = light.visibility(point, &mut rng, |_, _, _| false);
+            assert!(math_utils::f64_equals(v, 1.0));
+        }
+    }
+}
@@ -1,9 +1,38 @@
 use crate::{canvas::Canvas, tuple::Color};
 
+mod animation;
+mod camera;
 mod canvas;
+mod colorspace;
+mod compare;
+mod composite;
+mod dielectric;
+mod draw;
+mod environment;
+mod exr;
+mod filter;
+mod hdr;
+mod light;
+mod material;
 mod math_utils;
 mod matrix;
+mod media;
+mod microfacet;
+mod noise;
+mod normal_map;
+mod path_tracer;
+mod pfm;
+mod png;
+mod postprocess;
+mod quaternion;
+mod ray;
+mod rng;
+mod sampling;
+mod spectrum;
+mod tonemap;
 mod tuple;
+mod uv;
+mod zlib;
 
 struct Env {
     gravity: tuple::Tuple,
@@ -33,24 +62,47 @@ fn main() {
     let wind = tuple::Tuple::new_vector(-0.01, 0.0, 0.0);
     let e = Env { gravity, wind };
 
-    let mut c = Canvas::new(900, 550);
+    let mut c = Canvas::new(960, 580);
 
-    loop {
-        if p.position.x < 0.0
-            || p.position.x as usize >= c.width
-            || p.position.y < 0.0
-            || p.position.y as usize >= c.height
-        {
-            break;
-        }
+    // world units are pixels, with the origin just inside the bottom left corner
+    let margin = 40.0;
+    let height = c.height() as f64;
+    let to_canvas = |x: f64, y: f64| (margin + x, height - margin - y);
+
+    let grey = Color::new(0.6, 0.6, 0.6);
+    let (ox, oy) = to_canvas(0.0, 0.0);
+    c.draw_line(ox, oy, c.width() as f64 - 10.0, oy, grey);
+    c.draw_line(ox, oy, ox, 10.0, grey);
+    for i in (0..=900).step_by(100) {
+        let (x, y) = to_canvas(i as f64, 0.0);
+        c.draw_line(x, y, x, y + 4.0, grey);
+        let label = i.to_string();
+        let (w, _) = draw::text_size(&label);
+        c.draw_text(x as isize - w as isize / 2, y as isize + 8, &label, grey);
+    }
+    for i in (100..=500).step_by(100) {
+        let (x, y) = to_canvas(0.0, i as f64);
+        c.draw_line(x - 4.0, y, x, y, grey);
+        let label = i.to_string();
+        let (w, h) = draw::text_size(&label);
+        c.draw_text(
+            x as isize - 8 - w as isize,
+            y as isize - h as isize / 2,
+            &label,
+            grey,
+        );
+    }
+    c.draw_text(ox as isize + 8, 10, "HEIGHT", grey);
+    c.draw_text(c.width() as isize - 70, oy as isize - 16, "DISTANCE", grey);
+
+    let (mut last_x, mut last_y) = to_canvas(p.position.x, p.position.y);
+    while p.position.y >= 0.0 {
         // dbg!(p.position);
         // dbg!(p.velocity);
-        c.write_pixel(
-            p.position.x as usize,
-            c.height - p.position.y as usize,
-            Color::new(1.0, 0.0, 0.0),
-        );
         tick(&e, &mut p);
+        let (x, y) = to_canvas(p.position.x, p.position.y);
+        c.draw_line(last_x, last_y, x, y, Color::new(1.0, 0.0, 0.0));
+        (last_x, last_y) = (x, y);
     }
 
     match c.write_to_ppm("output.ppm") {
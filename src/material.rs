@@ -0,0 +1,199 @@
+use crate::light::Light;
+use crate::normal_map::NormalPerturbation;
+use crate::tuple::{Color, Tuple};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Material {
+    pub color: Color,
+    pub ambient: f64,
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+    // light given off by the surface itself; only the path tracer uses it
+    pub emissive: Color,
+    // bump or normal map tilting the normal the surface is shaded with
+    pub normal_perturbation: Option<NormalPerturbation>,
+}
+
+impl Material {
+    pub fn new() -> Self {
+        Material {
+            color: Color::new(1.0, 1.0, 1.0),
+            ambient: 0.1,
+            diffuse: 0.9,
+            specular: 0.9,
+            shininess: 200.0,
+            emissive: Color::black(),
+            normal_perturbation: None,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new()
+    }
+}
+
+// Phong shading for any kind of light. `visibility` is the fraction of the light that
+// reaches `point` (see Light::visibility): 0 fully shadowed, 1 fully lit.
+pub fn lighting(
+    material: &Material,
+    light: &Light,
+    point: Tuple,
+    eyev: Tuple,
+    normalv: Tuple,
+    visibility: f64,
+) -> Color {
+    let normalv = material
+        .normal_perturbation
+        .map_or(normalv, |p| p.apply(normalv, point));
+    let intensity = light.intensity_at(point);
+    let effective_color = material.color.multiply(intensity);
+    let lightv = light.direction_from(point);
+
+    let ambient = effective_color.scale(material.ambient);
+
+    let light_dot_normal = lightv.dot(normalv);
+    if light_dot_normal < 0.0 || visibility <= 0.0 {
+        // light on the other side of the surface, or fully blocked
+        return ambient;
+    }
+
+    let diffuse = effective_color.scale(material.diffuse * light_dot_normal);
+
+    let reflectv = lightv.negate().reflect(normalv);
+    let reflect_dot_eye = reflectv.dot(eyev);
+    let specular = if reflect_dot_eye <= 0.0 {
+        Color::black()
+    } else {
+        let factor = reflect_dot_eye.powf(material.shininess);
+        intensity.scale(material.specular * factor)
+    };
+
+    ambient.add(diffuse.add(specular).scale(visibility))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::{DirectionalLight, PointLight, SpotLight};
+
+    fn setup() -> (Material, Tuple) {
+        (Material::new(), Tuple::new_point(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn eye_between_light_and_surface() {
+        let (m, position) = setup();
+        let eyev = Tuple::new_vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let light = Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, -10.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &light, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::new(1.9, 1.9, 1.9)));
+    }
+
+    #[test]
+    fn eye_offset_45_degrees() {
+        let (m, position) = setup();
+        let s = 2.0_f64.sqrt() / 2.0;
+        let eyev = Tuple::new_vector(0.0, s, -s);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let light = Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, -10.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &light, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn eye_in_path_of_reflection() {
+        let (m, position) = setup();
+        let s = 2.0_f64.sqrt() / 2.0;
+        let eyev = Tuple::new_vector(0.0, -s, -s);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let light = Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 10.0, -10.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &light, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::new(1.6364, 1.6364, 1.6364)));
+    }
+
+    #[test]
+    fn light_behind_surface() {
+        let (m, position) = setup();
+        let eyev = Tuple::new_vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let light = Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, 10.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &light, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::new(0.1, 0.1, 0.1)));
+    }
+
+    #[test]
+    fn surface_in_shadow() {
+        let (m, position) = setup();
+        let eyev = Tuple::new_vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let light = Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, -10.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &light, position, eyev, normalv, 0.0);
+        assert!(result.equals(&Color::new(0.1, 0.1, 0.1)));
+        let result = lighting(&m, &light, position, eyev, normalv, 0.5);
+        assert!(result.equals(&Color::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn sun_and_spot_lighting() {
+        let (m, position) = setup();
+        let eyev = Tuple::new_vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+
+        let sun = Light::Directional(DirectionalLight::new(
+            Tuple::new_vector(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &sun, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::new(1.9, 1.9, 1.9)));
+
+        // a point outside the spot's cone gets no light at all, not even ambient, since the
+        // light's intensity there (which ambient is scaled by) is zero
+        let spot = Light::Spot(SpotLight::new(
+            Tuple::new_point(0.0, 0.0, -10.0),
+            Tuple::new_vector(0.0, 0.0, -1.0),
+            0.2,
+            0.4,
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        let result = lighting(&m, &spot, position, eyev, normalv, 1.0);
+        assert!(result.equals(&Color::black()));
+    }
+
+    #[test]
+    fn bumps_tilt_the_shading_normal() {
+        let (mut m, position) = setup();
+        let eyev = Tuple::new_vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::new_vector(0.0, 0.0, -1.0);
+        let sun = Light::Directional(DirectionalLight::new(
+            Tuple::new_vector(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ));
+        // a 45 degree slope: the light arrives at 45 degrees and reflects off sideways
+        m.normal_perturbation = Some(NormalPerturbation::Bump {
+            height: |p| p.x,
+            scale: 1.0,
+        });
+        let result = lighting(&m, &sun, position, eyev, normalv, 1.0);
+        let v = 0.1 + 0.9 / 2.0_f64.sqrt();
+        assert!(result.equals(&Color::new(v, v, v)));
+    }
+}
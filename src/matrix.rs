@@ -1,6 +1,7 @@
 use crate::math_utils;
 use crate::tuple;
 
+#[derive(Debug, Clone)]
 pub struct Matrix {
     pub rows: usize,
     pub cols: usize,
@@ -113,7 +114,12 @@ impl Matrix {
             let d = self.values[1][1];
             return a * d - b * c;
         }
-        return 0.0;
+        // expand along the first row
+        let mut det = 0.0;
+        for col in 0..self.cols {
+            det += self.values[0][col] * self.cofactor(0, col);
+        }
+        det
     }
 
     pub fn submatrix(&self, row: usize, col: usize) -> Self {
@@ -139,12 +145,90 @@ impl Matrix {
 
     pub fn cofactor(&self, row: usize, col: usize) -> f64 {
         let minor = self.minor(row, col);
-        if row + col % 2 == 1 {
+        if (row + col) % 2 == 1 {
             -minor
         } else {
             minor
         }
     }
+
+    pub fn is_invertible(&self) -> bool {
+        !math_utils::f64_equals(self.determinant(), 0.0)
+    }
+
+    pub fn inverse(&self) -> Self {
+        let det = self.determinant();
+        if math_utils::f64_equals(det, 0.0) {
+            panic!("Matrix is not invertible (determinant is 0)!");
+        }
+
+        let mut result = Matrix::new(self.rows, self.cols);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                // transposed on purpose: the inverse is the transposed cofactor matrix
+                result.values[col][row] = self.cofactor(row, col) / det;
+            }
+        }
+        result
+    }
+
+    pub fn translation(x: f64, y: f64, z: f64) -> Self {
+        let mut m = Matrix::identity();
+        m.values[0][3] = x;
+        m.values[1][3] = y;
+        m.values[2][3] = z;
+        m
+    }
+
+    pub fn scaling(x: f64, y: f64, z: f64) -> Self {
+        let mut m = Matrix::identity();
+        m.values[0][0] = x;
+        m.values[1][1] = y;
+        m.values[2][2] = z;
+        m
+    }
+
+    // rotations are in radians, clockwise when looking down the axis towards the origin
+    pub fn rotation_x(r: f64) -> Self {
+        let mut m = Matrix::identity();
+        m.values[1][1] = r.cos();
+        m.values[1][2] = -r.sin();
+        m.values[2][1] = r.sin();
+        m.values[2][2] = r.cos();
+        m
+    }
+
+    pub fn rotation_y(r: f64) -> Self {
+        let mut m = Matrix::identity();
+        m.values[0][0] = r.cos();
+        m.values[0][2] = r.sin();
+        m.values[2][0] = -r.sin();
+        m.values[2][2] = r.cos();
+        m
+    }
+
+    pub fn rotation_z(r: f64) -> Self {
+        let mut m = Matrix::identity();
+        m.values[0][0] = r.cos();
+        m.values[0][1] = -r.sin();
+        m.values[1][0] = r.sin();
+        m.values[1][1] = r.cos();
+        m
+    }
+
+    // Orients the world relative to an eye at `from` looking at `to`.
+    pub fn view_transform(from: tuple::Tuple, to: tuple::Tuple, up: tuple::Tuple) -> Self {
+        let forward = to.minus(from).normalize();
+        let left = forward.cross(up.normalize());
+        let true_up = left.cross(forward);
+        let orientation = Matrix::from_vec(&vec![
+            vec![left.x, left.y, left.z, 0.0],
+            vec![true_up.x, true_up.y, true_up.z, 0.0],
+            vec![-forward.x, -forward.y, -forward.z, 0.0],
+            vec![0.0, 0.0, 0.0, 1.0],
+        ]);
+        orientation.multiply_matrix(&Matrix::translation(-from.x, -from.y, -from.z))
+    }
 }
 
 #[cfg(test)]
@@ -368,4 +452,131 @@ mod tests {
         assert!(math_utils::f64_equals(mat_a.minor(1, 0), 25.0));
         assert!(math_utils::f64_equals(mat_a.cofactor(1, 0), -25.0));
     }
+
+    #[test]
+    fn invertibility() {
+        let mat_a = Matrix::from_vec(&vec![
+            vec![6.0, 4.0, 4.0, 4.0],
+            vec![5.0, 5.0, 7.0, 6.0],
+            vec![4.0, -9.0, 3.0, -7.0],
+            vec![9.0, 1.0, 7.0, -6.0],
+        ]);
+        assert!(math_utils::f64_equals(mat_a.determinant(), -2120.0));
+        assert!(mat_a.is_invertible());
+
+        let mat_a = Matrix::from_vec(&vec![
+            vec![-4.0, 2.0, -2.0, -3.0],
+            vec![9.0, 6.0, 2.0, 6.0],
+            vec![0.0, -5.0, 1.0, -5.0],
+            vec![0.0, 0.0, 0.0, 0.0],
+        ]);
+        assert!(math_utils::f64_equals(mat_a.determinant(), 0.0));
+        assert!(!mat_a.is_invertible());
+    }
+
+    #[test]
+    fn inverse() {
+        let mat_a = Matrix::from_vec(&vec![
+            vec![-5.0, 2.0, 6.0, -8.0],
+            vec![1.0, -5.0, 1.0, 8.0],
+            vec![7.0, 7.0, -6.0, -7.0],
+            vec![1.0, -3.0, 7.0, 4.0],
+        ]);
+        let mat_b = mat_a.inverse();
+        assert!(math_utils::f64_equals(mat_a.determinant(), 532.0));
+        assert!(math_utils::f64_equals(mat_a.cofactor(2, 3), -160.0));
+        assert!(math_utils::f64_equals(
+            mat_b.get_value(3, 2),
+            -160.0 / 532.0
+        ));
+        assert!(math_utils::f64_equals(mat_a.cofactor(3, 2), 105.0));
+        assert!(math_utils::f64_equals(mat_b.get_value(2, 3), 105.0 / 532.0));
+
+        // multiplying a product by the inverse gives back the original
+        let mat_c = Matrix::from_vec(&vec![
+            vec![8.0, 2.0, 2.0, 2.0],
+            vec![3.0, -1.0, 7.0, 0.0],
+            vec![7.0, 0.0, 5.0, 4.0],
+            vec![6.0, -2.0, 0.0, 5.0],
+        ]);
+        let product = mat_a.multiply_matrix(&mat_c);
+        assert!(product.multiply_matrix(&mat_c.inverse()).equals(&mat_a));
+    }
+
+    #[test]
+    fn translation_and_scaling() {
+        let transform = Matrix::translation(5.0, -3.0, 2.0);
+        let p = Tuple::new_point(-3.0, 4.0, 5.0);
+        assert!(transform
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(2.0, 1.0, 7.0)));
+        assert!(transform
+            .inverse()
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(-8.0, 7.0, 3.0)));
+        // vectors are unaffected by translation
+        let v = Tuple::new_vector(-3.0, 4.0, 5.0);
+        assert!(transform.multiply_tuple(&v).equals(&v));
+
+        let transform = Matrix::scaling(2.0, 3.0, 4.0);
+        assert!(transform
+            .multiply_tuple(&Tuple::new_point(-4.0, 6.0, 8.0))
+            .equals(&Tuple::new_point(-8.0, 18.0, 32.0)));
+    }
+
+    #[test]
+    fn rotations() {
+        let s = 2.0_f64.sqrt() / 2.0;
+        let quarter = std::f64::consts::PI / 4.0;
+
+        let p = Tuple::new_point(0.0, 1.0, 0.0);
+        assert!(Matrix::rotation_x(quarter)
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(0.0, s, s)));
+        assert!(Matrix::rotation_x(quarter)
+            .inverse()
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(0.0, s, -s)));
+
+        let p = Tuple::new_point(0.0, 0.0, 1.0);
+        assert!(Matrix::rotation_y(quarter)
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(s, 0.0, s)));
+
+        let p = Tuple::new_point(0.0, 1.0, 0.0);
+        assert!(Matrix::rotation_z(quarter)
+            .multiply_tuple(&p)
+            .equals(&Tuple::new_point(-s, s, 0.0)));
+    }
+
+    #[test]
+    fn view_transformations() {
+        let from = Tuple::new_point(0.0, 0.0, 0.0);
+        let up = Tuple::new_vector(0.0, 1.0, 0.0);
+        let t = Matrix::view_transform(from, Tuple::new_point(0.0, 0.0, -1.0), up);
+        assert!(t.equals(&Matrix::identity()));
+
+        let t = Matrix::view_transform(from, Tuple::new_point(0.0, 0.0, 1.0), up);
+        assert!(t.equals(&Matrix::scaling(-1.0, 1.0, -1.0)));
+
+        let t = Matrix::view_transform(
+            Tuple::new_point(0.0, 0.0, 8.0),
+            Tuple::new_point(0.0, 0.0, 0.0),
+            up,
+        );
+        assert!(t.equals(&Matrix::translation(0.0, 0.0, -8.0)));
+
+        let t = Matrix::view_transform(
+            Tuple::new_point(1.0, 3.0, 2.0),
+            Tuple::new_point(4.0, -2.0, 8.0),
+            Tuple::new_vector(1.0, 1.0, 0.0),
+        );
+        let expected = Matrix::from_vec(&vec![
+            vec![-0.50709, 0.50709, 0.67612, -2.36643],
+            vec![0.76772, 0.60609, 0.12122, -2.82843],
+            vec![-0.35857, 0.59761, -0.71714, 0.00000],
+            vec![0.00000, 0.00000, 0.00000, 1.00000],
+        ]);
+        assert!(t.equals(&expected));
+    }
 }
@@ -0,0 +1,224 @@
+use std::f64::consts::PI;
+
+use crate::rng::Rng;
+use crate::sampling;
+use crate::tuple::{Color, Tuple};
+
+// Global exponential fog. Not a real medium: it only blends what a ray sees towards
+// `color` by how far the ray travelled, which is cheap and gives atmospheric depth.
+// The haze only reaches `max_distance`, so the sun and sky beyond it are dimmed by the
+// whole depth of the fog but never lost entirely.
+#[derive(Debug, Copy, Clone)]
+pub struct Fog {
+    pub color: Color,
+    pub density: f64,
+    pub max_distance: f64,
+}
+
+impl Fog {
+    pub fn new(color: Color, density: f64, max_distance: f64) -> Self {
+        Fog {
+            color,
+            density,
+            max_distance,
+        }
+    }
+
+    // how much of the original colour survives `distance` (1 at 0, and at its lowest
+    // from max_distance on)
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        let distance = distance.min(self.max_distance);
+        if self.density <= 0.0 || distance <= 0.0 {
+            // no fog at all, even over an infinite distance
+            return 1.0;
+        }
+        (-self.density * distance).exp()
+    }
+
+    pub fn apply(&self, color: Color, distance: f64) -> Color {
+        self.color.lerp(color, self.transmittance(distance))
+    }
+}
+
+// Henyey-Greenstein phase function. g > 0 scatters forwards, g < 0 backwards,
+// g = 0 is isotropic.
+#[derive(Debug, Copy, Clone)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        HenyeyGreenstein { g }
+    }
+
+    // density per steradian for an angle theta between the incoming travel direction
+    // and the scattered direction
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    // New travel direction for a ray that was travelling along `direction`. The pdf
+    // equals the phase function, so the sampling weight is always 1.
+    pub fn sample(&self, direction: Tuple, rng: &mut Rng) -> Tuple {
+        let g = self.g;
+        let u1 = rng.next_f64();
+        let u2 = rng.next_f64();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }
+        .clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let (t, b) = sampling::orthonormal_basis(direction);
+        t.multiply(sin_theta * phi.cos())
+            .add(b.multiply(sin_theta * phi.sin()))
+            .add(direction.multiply(cos_theta))
+            .normalize()
+    }
+}
+
+// Uniform absorbing and scattering medium, e.g. smoke filling a shape's interior.
+// Coefficients are per unit distance; `color` tints the scattered light.
+#[derive(Debug, Copy, Clone)]
+pub struct HomogeneousMedium {
+    pub sigma_a: f64,
+    pub sigma_s: f64,
+    pub color: Color,
+    pub phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: f64, sigma_s: f64, g: f64) -> Self {
+        HomogeneousMedium {
+            sigma_a,
+            sigma_s,
+            color: Color::new(1.0, 1.0, 1.0),
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+
+    pub fn sigma_t(&self) -> f64 {
+        self.sigma_a + self.sigma_s
+    }
+
+    // chance that an interaction scatters rather than absorbs, tinted by `color`
+    pub fn albedo(&self) -> Color {
+        let sigma_t = self.sigma_t();
+        if sigma_t <= 0.0 {
+            return Color::black();
+        }
+        self.color.scale(self.sigma_s / sigma_t)
+    }
+
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        (-self.sigma_t() * distance).exp()
+    }
+
+    // Distance to the next interaction, exponentially distributed. Infinite in a
+    // medium that doesn't interact at all.
+    pub fn sample_distance(&self, rng: &mut Rng) -> f64 {
+        let sigma_t = self.sigma_t();
+        if sigma_t <= 0.0 {
+            return f64::INFINITY;
+        }
+        -(1.0 - rng.next_f64()).ln() / sigma_t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn fog_blends_by_distance() {
+        let fog = Fog::new(Color::new(0.5, 0.5, 0.5), 0.1, f64::INFINITY);
+        let red = Color::new(1.0, 0.0, 0.0);
+        assert!(fog.apply(red, 0.0).equals(&red));
+        assert!(fog
+            .apply(red, f64::INFINITY)
+            .equals(&Color::new(0.5, 0.5, 0.5)));
+        let t = (-1.0_f64).exp();
+        assert!(math_utils::f64_equals(fog.transmittance(10.0), t));
+        assert_eq!(
+            Fog::new(red, 0.0, f64::INFINITY).transmittance(f64::INFINITY),
+            1.0
+        );
+        // beyond its extent the fog thins no further
+        let layer = Fog::new(Color::new(0.5, 0.5, 0.5), 0.1, 10.0);
+        assert!(math_utils::f64_equals(
+            layer.transmittance(5.0),
+            fog.transmittance(5.0)
+        ));
+        assert!(math_utils::f64_equals(
+            layer.transmittance(f64::INFINITY),
+            t
+        ));
+        assert!(fog.apply(red, 10.0).equals(&Color::new(
+            0.5 + 0.5 * t,
+            0.5 - 0.5 * t,
+            0.5 - 0.5 * t
+        )));
+    }
+
+    #[test]
+    fn phase_function_is_normalised() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let hg = HenyeyGreenstein::new(g);
+            let steps = 20000;
+            let d = 2.0 / steps as f64;
+            let mut sum = 0.0;
+            for i in 0..steps {
+                let cos_theta = -1.0 + (i as f64 + 0.5) * d;
+                sum += hg.eval(cos_theta) * 2.0 * PI * d;
+            }
+            assert!((sum - 1.0).abs() < 1e-3, "g {} gave {}", g, sum);
+        }
+    }
+
+    #[test]
+    fn phase_samples_have_mean_cosine_g() {
+        let direction = Tuple::new_vector(0.0, 1.0, 0.0);
+        let mut rng = Rng::new(31);
+        for g in [-0.5, 0.0, 0.6] {
+            let hg = HenyeyGreenstein::new(g);
+            let n = 20000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let d = hg.sample(direction, &mut rng);
+                assert!(math_utils::f64_equals(d.magnitude(), 1.0));
+                sum += d.dot(direction);
+            }
+            assert!((sum / n as f64 - g).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn medium_coefficients() {
+        let m = HomogeneousMedium::new(0.5, 1.5, 0.0);
+        assert!(math_utils::f64_equals(m.sigma_t(), 2.0));
+        assert!(m.albedo().equals(&Color::new(0.75, 0.75, 0.75)));
+        assert!(math_utils::f64_equals(
+            m.transmittance(1.0),
+            (-2.0_f64).exp()
+        ));
+
+        let mut rng = Rng::new(2);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += m.sample_distance(&mut rng);
+        }
+        // mean free path is 1 / sigma_t
+        assert!((sum / n as f64 - 0.5).abs() < 0.02);
+
+        let clear = HomogeneousMedium::new(0.0, 0.0, 0.0);
+        assert!(clear.sample_distance(&mut rng).is_infinite());
+    }
+}
@@ -0,0 +1,254 @@
+use std::f64::consts::PI;
+
+use crate::normal_map::NormalPerturbation;
+use crate::rng::Rng;
+use crate::sampling;
+use crate::tuple::{Color, Tuple};
+
+// roughness is clamped so that the distribution never becomes a delta
+const MIN_ALPHA: f64 = 0.001;
+
+// GGX / Trowbridge-Reitz normal distribution.
+pub fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+    if n_dot_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+// Smith masking term for one direction.
+pub fn smith_g1(n_dot_v: f64, alpha: f64) -> f64 {
+    if n_dot_v <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    2.0 * n_dot_v / (n_dot_v + (a2 + (1.0 - a2) * n_dot_v * n_dot_v).sqrt())
+}
+
+// Separable Smith shadowing-masking.
+pub fn smith_g(n_dot_v: f64, n_dot_l: f64, alpha: f64) -> f64 {
+    smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha)
+}
+
+pub fn fresnel_schlick(f0: Color, cos_theta: f64) -> Color {
+    let f = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0.add(Color::new(1.0, 1.0, 1.0).minus(f0).scale(f))
+}
+
+// Physically based material in the glTF metallic/roughness convention.
+#[derive(Debug, Copy, Clone)]
+pub struct PbrMaterial {
+    pub base_color: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub emissive: Color,
+    pub normal_perturbation: Option<NormalPerturbation>,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Color, metallic: f64, roughness: f64) -> Self {
+        PbrMaterial {
+            base_color,
+            metallic,
+            roughness,
+            emissive: Color::black(),
+            normal_perturbation: None,
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    // reflectance at normal incidence: 4% for dielectrics, the base colour for metals
+    pub fn f0(&self) -> Color {
+        Color::new(0.04, 0.04, 0.04).lerp(self.base_color, self.metallic)
+    }
+
+    // chance of sampling the specular lobe rather than the diffuse one
+    fn specular_probability(&self) -> f64 {
+        (0.5 + 0.5 * self.metallic).clamp(0.0, 1.0)
+    }
+
+    // BRDF value for light arriving from `wi` and leaving towards `wo`; all vectors
+    // point away from the surface
+    pub fn eval(&self, normal: Tuple, wo: Tuple, wi: Tuple) -> Color {
+        let n_dot_v = normal.dot(wo);
+        let n_dot_l = normal.dot(wi);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return Color::black();
+        }
+        let h = wo.add(wi).normalize();
+        let n_dot_h = normal.dot(h);
+        let v_dot_h = wo.dot(h);
+        let alpha = self.alpha();
+
+        let f = fresnel_schlick(self.f0(), v_dot_h);
+        let d = ggx_distribution(n_dot_h, alpha);
+        let g = smith_g(n_dot_v, n_dot_l, alpha);
+        let specular = f.scale(d * g / (4.0 * n_dot_v * n_dot_l));
+
+        let kd = Color::new(1.0, 1.0, 1.0)
+            .minus(f)
+            .scale(1.0 - self.metallic);
+        let diffuse = kd.multiply(self.base_color).scale(1.0 / PI);
+
+        diffuse.add(specular)
+    }
+
+    // probability density (solid angle) that `sample` returns `wi`
+    pub fn pdf(&self, normal: Tuple, wo: Tuple, wi: Tuple) -> f64 {
+        let n_dot_l = normal.dot(wi);
+        if n_dot_l <= 0.0 || normal.dot(wo) <= 0.0 {
+            return 0.0;
+        }
+        let h = wo.add(wi).normalize();
+        let n_dot_h = normal.dot(h);
+        let v_dot_h = wo.dot(h);
+        let specular_pdf = if v_dot_h > 0.0 {
+            ggx_distribution(n_dot_h, self.alpha()) * n_dot_h / (4.0 * v_dot_h)
+        } else {
+            0.0
+        };
+        let diffuse_pdf = n_dot_l / PI;
+        let p = self.specular_probability();
+        p * specular_pdf + (1.0 - p) * diffuse_pdf
+    }
+
+    // Importance sample an incoming direction: GGX half vectors for the specular lobe,
+    // cosine-weighted for the diffuse one. Returns the direction and its pdf.
+    pub fn sample(&self, normal: Tuple, wo: Tuple, rng: &mut Rng) -> Option<(Tuple, f64)> {
+        let u1 = rng.next_f64();
+        let u2 = rng.next_f64();
+        let wi = if rng.next_f64() < self.specular_probability() {
+            let alpha = self.alpha();
+            let theta = (alpha * (u1 / (1.0 - u1)).sqrt()).atan();
+            let phi = 2.0 * PI * u2;
+            let (t, b) = sampling::orthonormal_basis(normal);
+            let h = t
+                .multiply(theta.sin() * phi.cos())
+                .add(b.multiply(theta.sin() * phi.sin()))
+                .add(normal.multiply(theta.cos()));
+            wo.negate().reflect(h)
+        } else {
+            sampling::cosine_hemisphere(normal, u1, u2)
+        };
+
+        let pdf = self.pdf(normal, wo, wi);
+        if pdf <= 0.0 {
+            None
+        } else {
+            Some((wi, pdf))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn normal() -> Tuple {
+        Tuple::new_vector(0.0, 0.0, 1.0)
+    }
+
+    fn direction(theta: f64, phi: f64) -> Tuple {
+        Tuple::new_vector(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+
+    #[test]
+    fn distribution_is_normalised() {
+        // projected microfacet area integrates to one
+        for alpha in [0.2, 0.5, 0.9] {
+            let steps = 2000;
+            let d_theta = (PI / 2.0) / steps as f64;
+            let mut sum = 0.0;
+            for i in 0..steps {
+                let theta = (i as f64 + 0.5) * d_theta;
+                sum += ggx_distribution(theta.cos(), alpha)
+                    * theta.cos()
+                    * theta.sin()
+                    * d_theta
+                    * 2.0
+                    * PI;
+            }
+            assert!((sum - 1.0).abs() < 1e-3, "alpha {} gave {}", alpha, sum);
+        }
+    }
+
+    #[test]
+    fn schlick_fresnel() {
+        let f0 = Color::new(0.04, 0.5, 1.0);
+        assert!(fresnel_schlick(f0, 1.0).equals(&f0));
+        assert!(fresnel_schlick(f0, 0.0).equals(&Color::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn metallic_controls_f0() {
+        let red = Color::new(0.9, 0.1, 0.1);
+        assert!(PbrMaterial::new(red, 0.0, 0.5)
+            .f0()
+            .equals(&Color::new(0.04, 0.04, 0.04)));
+        assert!(PbrMaterial::new(red, 1.0, 0.5).f0().equals(&red));
+    }
+
+    #[test]
+    fn smith_masking_limits() {
+        assert!(math_utils::f64_equals(smith_g1(1.0, 0.5), 1.0));
+        assert!(math_utils::f64_equals(smith_g1(0.0, 0.5), 0.0));
+        assert!(smith_g(0.3, 0.3, 0.8) < smith_g(0.3, 0.3, 0.2));
+    }
+
+    #[test]
+    fn eval_is_reciprocal() {
+        let m = PbrMaterial::new(Color::new(0.8, 0.6, 0.2), 0.3, 0.4);
+        let a = direction(0.3, 0.1);
+        let b = direction(1.1, 2.5);
+        assert!(m.eval(normal(), a, b).equals(&m.eval(normal(), b, a)));
+        // nothing from below the surface
+        assert!(m
+            .eval(normal(), a, direction(2.0, 0.0))
+            .equals(&Color::black()));
+    }
+
+    #[test]
+    fn importance_sampling_matches_uniform_estimate() {
+        let m = PbrMaterial::new(Color::new(1.0, 1.0, 1.0), 0.7, 0.5);
+        let wo = direction(0.6, 0.0);
+        let n = 40000;
+
+        // importance sampled estimate of the directional albedo
+        let mut rng = Rng::new(21);
+        let mut importance = 0.0;
+        for _ in 0..n {
+            if let Some((wi, pdf)) = m.sample(normal(), wo, &mut rng) {
+                assert!(math_utils::f64_equals(pdf, m.pdf(normal(), wo, wi)));
+                importance += m.eval(normal(), wo, wi).r() * normal().dot(wi) / pdf;
+            }
+        }
+        importance /= n as f64;
+
+        // same integral with uniform hemisphere samples
+        let mut uniform = 0.0;
+        for _ in 0..n {
+            let z = rng.next_f64();
+            let wi = direction(z.acos(), 2.0 * PI * rng.next_f64());
+            uniform += m.eval(normal(), wo, wi).r() * z * 2.0 * PI;
+        }
+        uniform /= n as f64;
+
+        assert!(importance > 0.5 && importance <= 1.01, "{}", importance);
+        assert!(
+            (importance - uniform).abs() < 0.03,
+            "{} vs {}",
+            importance,
+            uniform
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::rng::Rng;
+use crate::tuple::{Color, Tuple};
+
+// Seedable 3D gradient noise. The permutation table is shuffled from the seed, so the
+// same seed gives the same noise field everywhere.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    perm: [u8; 512],
+}
+
+// gradient directions used by simplex noise (midpoints of the cube edges)
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    let u = if h & 1 == 0 { u } else { -u };
+    let v = if h & 2 == 0 { v } else { -v };
+    u + v
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut p: [u8; 256] = [0; 256];
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+        // Fisher-Yates
+        let mut rng = Rng::new(seed);
+        for i in (1..256).rev() {
+            let j = rng.next_usize(i + 1);
+            p.swap(i, j);
+        }
+
+        let mut perm = [0; 512];
+        for i in 0..512 {
+            perm[i] = p[i & 255];
+        }
+        Noise { perm }
+    }
+
+    fn hash(&self, i: usize) -> u8 {
+        self.perm[i & 511]
+    }
+
+    // Improved Perlin noise, roughly in [-1, 1]. Zero at every integer lattice point.
+    pub fn perlin(&self, point: Tuple) -> f64 {
+        let fx = point.x.floor();
+        let fy = point.y.floor();
+        let fz = point.z.floor();
+        let xi = (fx as i64 & 255) as usize;
+        let yi = (fy as i64 & 255) as usize;
+        let zi = (fz as i64 & 255) as usize;
+        let x = point.x - fx;
+        let y = point.y - fy;
+        let z = point.z - fz;
+
+        let u = fade(x);
+        let v = fade(y);
+        let w = fade(z);
+
+        let a = self.hash(xi) as usize + yi;
+        let aa = self.hash(a) as usize + zi;
+        let ab = self.hash(a + 1) as usize + zi;
+        let b = self.hash(xi + 1) as usize + yi;
+        let ba = self.hash(b) as usize + zi;
+        let bb = self.hash(b + 1) as usize + zi;
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(self.hash(aa), x, y, z),
+                    grad(self.hash(ba), x - 1.0, y, z),
+                ),
+                lerp(
+                    u,
+                    grad(self.hash(ab), x, y - 1.0, z),
+                    grad(self.hash(bb), x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(self.hash(aa + 1), x, y, z - 1.0),
+                    grad(self.hash(ba + 1), x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(self.hash(ab + 1), x, y - 1.0, z - 1.0),
+                    grad(self.hash(bb + 1), x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    // 3D simplex noise (after Stefan Gustavson's reference implementation), in [-1, 1].
+    pub fn simplex(&self, point: Tuple) -> f64 {
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+
+        // skew the input space to find which simplex cell we're in
+        let s = (point.x + point.y + point.z) * F3;
+        let i = (point.x + s).floor();
+        let j = (point.y + s).floor();
+        let k = (point.z + s).floor();
+        let t = (i + j + k) * G3;
+        let x0 = point.x - (i - t);
+        let y0 = point.y - (j - t);
+        let z0 = point.z - (k - t);
+
+        // which of the six tetrahedra
+        let (i1, j1, k1, i2, j2, k2) = if x0 >= y0 {
+            if y0 >= z0 {
+                (1, 0, 0, 1, 1, 0)
+            } else if x0 >= z0 {
+                (1, 0, 0, 1, 0, 1)
+            } else {
+                (0, 0, 1, 1, 0, 1)
+            }
+        } else if y0 < z0 {
+            (0, 0, 1, 0, 1, 1)
+        } else if x0 < z0 {
+            (0, 1, 0, 0, 1, 1)
+        } else {
+            (0, 1, 0, 1, 1, 0)
+        };
+
+        let corners = [
+            (x0, y0, z0, 0, 0, 0),
+            (
+                x0 - i1 as f64 + G3,
+                y0 - j1 as f64 + G3,
+                z0 - k1 as f64 + G3,
+                i1,
+                j1,
+                k1,
+            ),
+            (
+                x0 - i2 as f64 + 2.0 * G3,
+                y0 - j2 as f64 + 2.0 * G3,
+                z0 - k2 as f64 + 2.0 * G3,
+                i2,
+                j2,
+                k2,
+            ),
+            (
+                x0 - 1.0 + 3.0 * G3,
+                y0 - 1.0 + 3.0 * G3,
+                z0 - 1.0 + 3.0 * G3,
+                1,
+                1,
+                1,
+            ),
+        ];
+
+        let ii = (i as i64 & 255) as usize;
+        let jj = (j as i64 & 255) as usize;
+        let kk = (k as i64 & 255) as usize;
+
+        let mut total = 0.0;
+        for (x, y, z, di, dj, dk) in corners {
+            let t = 0.6 - x * x - y * y - z * z;
+            if t < 0.0 {
+                continue;
+            }
+            let gi = self.hash(ii + di + self.hash(jj + dj + self.hash(kk + dk) as usize) as usize)
+                as usize
+                % 12;
+            let g = GRAD3[gi];
+            let t2 = t * t;
+            total += t2 * t2 * (g[0] * x + g[1] * y + g[2] * z);
+        }
+
+        // scale the result to cover [-1, 1]
+        32.0 * total
+    }
+
+    // Fractal Brownian motion: octaves of Perlin noise at increasing frequency and
+    // decreasing amplitude, normalised back to roughly [-1, 1].
+    pub fn fbm(&self, point: Tuple, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves {
+            sum += amplitude * self.perlin(point.multiply(frequency));
+            norm += amplitude;
+            amplitude *= gain;
+            frequency *= lacunarity;
+        }
+        if norm == 0.0 {
+            0.0
+        } else {
+            sum / norm
+        }
+    }
+
+    // Like fbm, but sums the absolute value of each octave, giving the creased look
+    // used for marble veins and fire. Roughly in [0, 1].
+    pub fn turbulence(&self, point: Tuple, octaves: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves {
+            sum += amplitude * self.perlin(point.multiply(frequency)).abs();
+            norm += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        if norm == 0.0 {
+            0.0
+        } else {
+            sum / norm
+        }
+    }
+}
+
+// Veins along x, bent by turbulence.
+#[derive(Debug, Clone)]
+pub struct MarblePattern {
+    pub a: Color,
+    pub b: Color,
+    pub frequency: f64,
+    pub turbulence: f64,
+    pub octaves: usize,
+    noise: Noise,
+}
+
+impl MarblePattern {
+    pub fn new(a: Color, b: Color, seed: u64) -> Self {
+        MarblePattern {
+            a,
+            b,
+            frequency: 4.0,
+            turbulence: 5.0,
+            octaves: 6,
+            noise: Noise::new(seed),
+        }
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Color {
+        let t = self.noise.turbulence(point, self.octaves);
+        let v = 0.5 * (1.0 + (point.x * self.frequency + self.turbulence * t).sin());
+        self.a.lerp(self.b, v)
+    }
+}
+
+// Concentric rings around the y axis, with the radius jittered by noise.
+#[derive(Debug, Clone)]
+pub struct WoodPattern {
+    pub a: Color,
+    pub b: Color,
+    pub rings: f64,
+    pub distortion: f64,
+    noise: Noise,
+}
+
+impl WoodPattern {
+    pub fn new(a: Color, b: Color, seed: u64) -> Self {
+        WoodPattern {
+            a,
+            b,
+            rings: 8.0,
+            distortion: 0.3,
+            noise: Noise::new(seed),
+        }
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Color {
+        let r = (point.x * point.x + point.z * point.z).sqrt();
+        let v = r * self.rings + self.distortion * self.rings * self.noise.perlin(point);
+        self.a.lerp(self.b, v - v.floor())
+    }
+}
+
+// Soft fBm blobs: `a` is the sky, `b` the cloud colour. `coverage` shifts how much of
+// the field turns into cloud.
+#[derive(Debug, Clone)]
+pub struct CloudPattern {
+    pub a: Color,
+    pub b: Color,
+    pub coverage: f64,
+    pub octaves: usize,
+    noise: Noise,
+}
+
+impl CloudPattern {
+    pub fn new(a: Color, b: Color, seed: u64) -> Self {
+        CloudPattern {
+            a,
+            b,
+            coverage: 0.5,
+            octaves: 6,
+            noise: Noise::new(seed),
+        }
+    }
+
+    pub fn pattern_at(&self, point: Tuple) -> Color {
+        let n = 0.5 * (self.noise.fbm(point, self.octaves, 2.0, 0.5) + 1.0);
+        let v = (n + self.coverage - 0.5).clamp(0.0, 1.0);
+        self.a.lerp(self.b, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    fn sample_points() -> Vec<Tuple> {
+        let mut points = Vec::new();
+        for i in 0..200 {
+            let f = i as f64;
+            points.push(Tuple::new_point(f * 0.37 - 30.0, f * 0.11 + 2.5, f * -0.23));
+        }
+        points
+    }
+
+    #[test]
+    fn noise_is_deterministic_per_seed() {
+        let a = Noise::new(1234);
+        let b = Noise::new(1234);
+        let c = Noise::new(4321);
+        let mut differs = false;
+        for p in sample_points() {
+            assert_eq!(a.perlin(p), b.perlin(p));
+            assert_eq!(a.simplex(p), b.simplex(p));
+            if a.perlin(p) != c.perlin(p) {
+                differs = true;
+            }
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn perlin_is_zero_on_lattice() {
+        let n = Noise::new(9);
+        for x in -3..3 {
+            for z in -3..3 {
+                let p = Tuple::new_point(x as f64, 7.0, z as f64);
+                assert!(math_utils::f64_equals(n.perlin(p), 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let n = Noise::new(5);
+        for p in sample_points() {
+            assert!(n.perlin(p).abs() <= 1.0);
+            assert!(n.simplex(p).abs() <= 1.0);
+            assert!(n.fbm(p, 5, 2.0, 0.5).abs() <= 1.0);
+            let t = n.turbulence(p, 5);
+            assert!((0.0..=1.0).contains(&t));
+        }
+    }
+
+    #[test]
+    fn noise_is_continuous() {
+        let n = Noise::new(77);
+        let p = Tuple::new_point(1.3, 2.7, -0.4);
+        let q = p.add(Tuple::new_vector(1e-6, 1e-6, 1e-6));
+        assert!((n.perlin(p) - n.perlin(q)).abs() < 1e-4);
+        assert!((n.simplex(p) - n.simplex(q)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn patterns_blend_between_colors() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let black = Color::black();
+        let marble = MarblePattern::new(white, black, 1);
+        let wood = WoodPattern::new(white, black, 1);
+        let clouds = CloudPattern::new(white, black, 1);
+        for p in sample_points() {
+            for c in [
+                marble.pattern_at(p),
+                wood.pattern_at(p),
+                clouds.pattern_at(p),
+            ] {
+                assert!((0.0..=1.0).contains(&c.r()));
+                assert!(math_utils::f64_equals(c.r(), c.g()));
+                assert!(math_utils::f64_equals(c.g(), c.b()));
+            }
+        }
+        // same seed, same image
+        let again = MarblePattern::new(white, black, 1);
+        let p = Tuple::new_point(0.3, 0.2, 0.1);
+        assert!(marble.pattern_at(p).equals(&again.pattern_at(p)));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+use crate::canvas::Canvas;
+use crate::tuple::{Color, Tuple};
+use crate::uv::UvMapping;
+
+// step used for the finite-difference gradient of a bump pattern
+const BUMP_DELTA: f64 = 0.0001;
+
+// Perturb `normal` by a scalar height field. The gradient of `height` is estimated
+// with central differences and the part of it tangent to the surface tilts the normal.
+// `scale` controls how strong the bumps look; 0 leaves the normal untouched.
+pub fn bump_normal<F>(normal: Tuple, point: Tuple, scale: f64, height: F) -> Tuple
+where
+    F: Fn(Tuple) -> f64,
+{
+    let dx = Tuple::new_vector(BUMP_DELTA, 0.0, 0.0);
+    let dy = Tuple::new_vector(0.0, BUMP_DELTA, 0.0);
+    let dz = Tuple::new_vector(0.0, 0.0, BUMP_DELTA);
+    let gradient = Tuple::new_vector(
+        height(point.add(dx)) - height(point.minus(dx)),
+        height(point.add(dy)) - height(point.minus(dy)),
+        height(point.add(dz)) - height(point.minus(dz)),
+    )
+    .divide(2.0 * BUMP_DELTA);
+
+    let surface_gradient = gradient.minus(normal.multiply(gradient.dot(normal)));
+    normal.minus(surface_gradient.multiply(scale)).normalize()
+}
+
+// Tangent (direction of increasing u) of a triangle from its positions and texture
+// coordinates. Returns None when the uvs are degenerate.
+pub fn triangle_tangent(
+    p1: Tuple,
+    p2: Tuple,
+    p3: Tuple,
+    uv1: (f64, f64),
+    uv2: (f64, f64),
+    uv3: (f64, f64),
+) -> Option<Tuple> {
+    let e1 = p2.minus(p1);
+    let e2 = p3.minus(p1);
+    let (du1, dv1) = (uv2.0 - uv1.0, uv2.1 - uv1.1);
+    let (du2, dv2) = (uv3.0 - uv1.0, uv3.1 - uv1.1);
+    let det = du1 * dv2 - du2 * dv1;
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+    let tangent = e1.multiply(dv2).minus(e2.multiply(dv1)).divide(det);
+    Some(tangent.normalize())
+}
+
+// Tangent-space normal map read from a canvas. Colors encode vectors as
+// (r, g, b) = (x, y, z) * 0.5 + 0.5, the usual convention for exported maps.
+pub struct NormalMap {
+    pub canvas: Canvas,
+    pub strength: f64,
+}
+
+impl fmt::Debug for NormalMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NormalMap")
+            .field("width", &self.canvas.width())
+            .field("height", &self.canvas.height())
+            .field("strength", &self.strength)
+            .finish()
+    }
+}
+
+impl NormalMap {
+    pub fn new(canvas: Canvas) -> Self {
+        NormalMap {
+            canvas,
+            strength: 1.0,
+        }
+    }
+
+    // nearest texel; v = 0 is the bottom row of the image. An empty map reads as flat.
+    fn color_at(&self, u: f64, v: f64) -> Color {
+        if self.canvas.width() == 0 || self.canvas.height() == 0 {
+            return Color::new(0.5, 0.5, 1.0);
+        }
+        let u = u.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let x = (u * (self.canvas.width() - 1) as f64).round() as usize;
+        let y = ((1.0 - v) * (self.canvas.height() - 1) as f64).round() as usize;
+        self.canvas.pixel_at(x, y)
+    }
+
+    // the tangent-space vector stored at (u, v)
+    pub fn sample(&self, u: f64, v: f64) -> Tuple {
+        let c = self.color_at(u, v);
+        let n = Tuple::new_vector(
+            (c.r() * 2.0 - 1.0) * self.strength,
+            (c.g() * 2.0 - 1.0) * self.strength,
+            c.b() * 2.0 - 1.0,
+        );
+        n.normalize()
+    }
+
+    // Bring the sampled normal into world space using the surface frame at the hit.
+    // `tangent` need not be exactly perpendicular to `normal`; it is re-orthogonalised.
+    pub fn perturb(&self, normal: Tuple, tangent: Tuple, u: f64, v: f64) -> Tuple {
+        let t = tangent.minus(normal.multiply(normal.dot(tangent)));
+        if t.magnitude() < f64::EPSILON {
+            // a tangent along the normal gives no frame to tilt in
+            return normal;
+        }
+        let t = t.normalize();
+        let b = normal.cross(t);
+        let m = self.sample(u, v);
+        t.multiply(m.x)
+            .add(b.multiply(m.y))
+            .add(normal.multiply(m.z))
+            .normalize()
+    }
+}
+
+// How a material tilts its shading normal. Materials are Copy, so a normal map is
+// borrowed for the whole run: load it once and keep it in a static or leak it.
+#[derive(Debug, Copy, Clone)]
+pub enum NormalPerturbation {
+    // bumps from a height field, with `scale` as in bump_normal
+    Bump {
+        height: fn(Tuple) -> f64,
+        scale: f64,
+    },
+    // a tangent-space normal map, read at the uv `mapping` gives the point
+    Map {
+        map: &'static NormalMap,
+        mapping: UvMapping,
+    },
+}
+
+impl NormalPerturbation {
+    // the normal to shade with at `point`, on a surface whose own normal is `normal`
+    pub fn apply(&self, normal: Tuple, point: Tuple) -> Tuple {
+        match *self {
+            NormalPerturbation::Bump { height, scale } => bump_normal(normal, point, scale, height),
+            NormalPerturbation::Map { map, mapping } => {
+                let (u, v) = mapping.map(point);
+                map.perturb(normal, mapping.tangent(point), u, v)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn flat_height_keeps_normal() {
+        let n = Tuple::new_vector(0.0, 1.0, 0.0);
+        let p = Tuple::new_point(0.3, 0.0, 0.7);
+        let result = bump_normal(n, p, 1.0, |_| 0.5);
+        assert!(result.equals(&n));
+    }
+
+    #[test]
+    fn bump_tilts_away_from_slope() {
+        // height rises with x, so the normal leans towards -x
+        let n = Tuple::new_vector(0.0, 1.0, 0.0);
+        let p = Tuple::new_point(0.0, 0.0, 0.0);
+        let result = bump_normal(n, p, 1.0, |q| q.x);
+        let expected = Tuple::new_vector(-1.0, 1.0, 0.0).normalize();
+        assert!(result.equals(&expected));
+        assert!(bump_normal(n, p, 0.0, |q| q.x).equals(&n));
+    }
+
+    #[test]
+    fn tangent_of_triangle() {
+        let t = triangle_tangent(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_point(2.0, 0.0, 0.0),
+            Tuple::new_point(0.0, 2.0, 0.0),
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (0.0, 1.0),
+        )
+        .unwrap();
+        assert!(t.equals(&Tuple::new_vector(1.0, 0.0, 0.0)));
+
+        // uvs flipped in u
+        let t = triangle_tangent(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_point(2.0, 0.0, 0.0),
+            Tuple::new_point(0.0, 2.0, 0.0),
+            (1.0, 0.0),
+            (0.0, 0.0),
+            (1.0, 1.0),
+        )
+        .unwrap();
+        assert!(t.equals(&Tuple::new_vector(-1.0, 0.0, 0.0)));
+
+        let degenerate = triangle_tangent(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_point(2.0, 0.0, 0.0),
+            Tuple::new_point(0.0, 2.0, 0.0),
+            (0.5, 0.5),
+            (0.5, 0.5),
+            (0.5, 0.5),
+        );
+        assert!(degenerate.is_none());
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_normal() {
+        let mut c = Canvas::new(2, 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                c.write_pixel(x, y, Color::new(0.5, 0.5, 1.0));
+            }
+        }
+        let map = NormalMap::new(c);
+        let n = Tuple::new_vector(0.0, 0.0, 1.0);
+        let t = Tuple::new_vector(1.0, 0.0, 0.0);
+        assert!(map.perturb(n, t, 0.3, 0.3).equals(&n));
+
+        let empty = NormalMap::new(Canvas::new(0, 0));
+        assert!(empty.perturb(n, t, 0.3, 0.3).equals(&n));
+    }
+
+    #[test]
+    fn normal_map_uses_tangent_frame() {
+        // a texel pointing fully along +x in tangent space
+        let mut c = Canvas::new(1, 1);
+        c.write_pixel(0, 0, Color::new(1.0, 0.5, 0.5));
+        let map = NormalMap::new(c);
+        let n = Tuple::new_vector(0.0, 1.0, 0.0);
+        let t = Tuple::new_vector(0.0, 0.0, 1.0);
+        let result = map.perturb(n, t, 0.5, 0.5);
+        assert!(result.equals(&t));
+        assert!(math_utils::f64_equals(result.magnitude(), 1.0));
+        assert!(map.perturb(n, n, 0.5, 0.5).equals(&n));
+    }
+
+    #[test]
+    fn perturbations_look_up_their_own_coordinates() {
+        // left half of the map tilts towards +x in tangent space, right half is flat
+        let mut c = Canvas::new(2, 1);
+        c.write_pixel(0, 0, Color::new(1.0, 0.5, 1.0));
+        c.write_pixel(1, 0, Color::new(0.5, 0.5, 1.0));
+        let map: &'static NormalMap = Box::leak(Box::new(NormalMap::new(c)));
+        let mapped = NormalPerturbation::Map {
+            map,
+            mapping: UvMapping::Planar,
+        };
+        let up = Tuple::new_vector(0.0, 1.0, 0.0);
+        let tilted = mapped.apply(up, Tuple::new_point(0.2, 0.0, 0.5));
+        assert!(tilted.equals(&Tuple::new_vector(1.0, 1.0, 0.0).normalize()));
+        assert!(mapped
+            .apply(up, Tuple::new_point(0.8, 0.0, 0.5))
+            .equals(&up));
+
+        let bumped = NormalPerturbation::Bump {
+            height: |p| p.x,
+            scale: 1.0,
+        };
+        let expected = Tuple::new_vector(-1.0, 1.0, 0.0).normalize();
+        assert!(bumped
+            .apply(up, Tuple::new_point(0.0, 0.0, 0.0))
+            .equals(&expected));
+    }
+}
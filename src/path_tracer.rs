@@ -0,0 +1,1592 @@
+use std::f64::consts::PI;
+
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::dielectric::Dielectric;
+use crate::environment::EnvironmentMap;
+use crate::light::{AreaLight, Light};
+use crate::material::Material;
+use crate::math_utils::EPSILON;
+use crate::media::{Fog, HenyeyGreenstein, HomogeneousMedium};
+use crate::microfacet::PbrMaterial;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::sampling::{self, SamplePattern, Supersampler};
+use crate::spectrum;
+use crate::tuple::{Color, Tuple};
+
+#[derive(Debug, Copy, Clone)]
+pub enum SurfaceMaterial {
+    // treated as Lambertian, with `color` as the albedo
+    Phong(Material),
+    Pbr(PbrMaterial),
+    // smooth glass; only reflects or refracts in one exact direction, so eval, pdf and
+    // sample treat it as black and the path tracer handles it separately
+    Dielectric(Dielectric),
+    // Invisible boundary that rays pass straight through, e.g. the surface of a volume
+    // of smoke. Only useful together with SurfaceHit::medium.
+    Interface,
+}
+
+impl SurfaceMaterial {
+    pub fn emissive(&self) -> Color {
+        match self {
+            SurfaceMaterial::Phong(m) => m.emissive,
+            SurfaceMaterial::Pbr(m) => m.emissive,
+            SurfaceMaterial::Dielectric(_) | SurfaceMaterial::Interface => Color::black(),
+        }
+    }
+
+    // the normal to shade `point` with, after any bump or normal map
+    pub fn shading_normal(&self, normal: Tuple, point: Tuple) -> Tuple {
+        let perturbation = match self {
+            SurfaceMaterial::Phong(m) => m.normal_perturbation,
+            SurfaceMaterial::Pbr(m) => m.normal_perturbation,
+            SurfaceMaterial::Dielectric(_) | SurfaceMaterial::Interface => None,
+        };
+        perturbation.map_or(normal, |p| p.apply(normal, point))
+    }
+
+    // BRDF value; all vectors point away from the surface
+    pub fn eval(&self, normal: Tuple, wo: Tuple, wi: Tuple) -> Color {
+        match self {
+            SurfaceMaterial::Phong(m) => {
+                if normal.dot(wi) <= 0.0 || normal.dot(wo) <= 0.0 {
+                    Color::black()
+                } else {
+                    m.color.scale(1.0 / PI)
+                }
+            }
+            SurfaceMaterial::Pbr(m) => m.eval(normal, wo, wi),
+            SurfaceMaterial::Dielectric(_) | SurfaceMaterial::Interface => Color::black(),
+        }
+    }
+
+    pub fn pdf(&self, normal: Tuple, wo: Tuple, wi: Tuple) -> f64 {
+        match self {
+            SurfaceMaterial::Phong(_) => (normal.dot(wi) / PI).max(0.0),
+            SurfaceMaterial::Pbr(m) => m.pdf(normal, wo, wi),
+            SurfaceMaterial::Dielectric(_) | SurfaceMaterial::Interface => 0.0,
+        }
+    }
+
+    pub fn sample(&self, normal: Tuple, wo: Tuple, rng: &mut Rng) -> Option<(Tuple, f64)> {
+        match self {
+            SurfaceMaterial::Phong(_) => {
+                let wi = sampling::cosine_hemisphere(normal, rng.next_f64(), rng.next_f64());
+                let pdf = self.pdf(normal, wo, wi);
+                if pdf <= 0.0 {
+                    None
+                } else {
+                    Some((wi, pdf))
+                }
+            }
+            SurfaceMaterial::Pbr(m) => m.sample(normal, wo, rng),
+            SurfaceMaterial::Dielectric(_) | SurfaceMaterial::Interface => None,
+        }
+    }
+}
+
+// What the path tracer needs to know about the closest hit along a ray.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceHit {
+    pub t: f64,
+    pub point: Tuple,
+    pub normal: Tuple,
+    pub material: SurfaceMaterial,
+    // medium filling the shape's interior, entered by rays crossing against the normal
+    pub medium: Option<HomogeneousMedium>,
+}
+
+impl SurfaceHit {
+    // medium a ray travelling along `direction` is in after crossing this surface;
+    // volumes don't nest, so leaving one means being back in empty space
+    fn medium_after(&self, direction: Tuple) -> Option<HomogeneousMedium> {
+        if self.normal.dot(direction) < 0.0 {
+            self.medium
+        } else {
+            None
+        }
+    }
+}
+
+// A point on one of the scene's emissive surfaces, picked for direct lighting.
+#[derive(Debug, Copy, Clone)]
+pub struct EmitterSample {
+    pub point: Tuple,
+    pub normal: Tuple,
+    // emitted radiance, the same from both sides like SurfaceMaterial::emissive
+    pub radiance: Color,
+    // density of picking this point, per unit area
+    pub pdf: f64,
+}
+
+// Where a path changes direction: off a surface, or inside a medium.
+#[derive(Debug, Copy, Clone)]
+enum Vertex {
+    Surface {
+        point: Tuple,
+        normal: Tuple,
+        material: SurfaceMaterial,
+    },
+    Medium {
+        point: Tuple,
+        phase: HenyeyGreenstein,
+    },
+}
+
+impl Vertex {
+    fn point(&self) -> Tuple {
+        match self {
+            Vertex::Surface { point, .. } | Vertex::Medium { point, .. } => *point,
+        }
+    }
+
+    // start of rays leaving the vertex, lifted off surfaces to avoid acne
+    fn origin(&self) -> Tuple {
+        match self {
+            Vertex::Surface { point, normal, .. } => point.add(normal.multiply(EPSILON)),
+            Vertex::Medium { point, .. } => *point,
+        }
+    }
+
+    // BSDF times cosine for surfaces, the phase function in media
+    fn eval(&self, wo: Tuple, wi: Tuple) -> Color {
+        match self {
+            Vertex::Surface {
+                normal, material, ..
+            } => material
+                .eval(*normal, wo, wi)
+                .scale(normal.dot(wi).max(0.0)),
+            Vertex::Medium { phase, .. } => {
+                let p = phase.eval(wo.negate().dot(wi));
+                Color::new(p, p, p)
+            }
+        }
+    }
+
+    fn pdf(&self, wo: Tuple, wi: Tuple) -> f64 {
+        match self {
+            Vertex::Surface {
+                normal, material, ..
+            } => material.pdf(*normal, wo, wi),
+            Vertex::Medium { phase, .. } => phase.eval(wo.negate().dot(wi)),
+        }
+    }
+}
+
+// Anything the path tracer can render. A world of shapes only has to find the closest
+// hit; rays that miss pick up the background.
+pub trait Scene {
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceHit>;
+
+    fn background(&self, _ray: &Ray) -> Color {
+        Color::black()
+    }
+
+    // Image based lighting for rays that miss. When present it replaces `background`
+    // and is importance sampled like the other lights.
+    fn environment(&self) -> Option<&EnvironmentMap> {
+        None
+    }
+
+    // Lights sampled directly at every bounce. Area lights among them are also visible
+    // to rays, emitting their intensity as radiance.
+    fn lights(&self) -> &[Light] {
+        &[]
+    }
+
+    // A point on the emissive surfaces `intersect` returns, sampled directly at every
+    // bounce alongside the lights. Scenes without any, or that leave them to BSDF
+    // sampling, return None.
+    fn sample_emitter(&self, _rng: &mut Rng) -> Option<EmitterSample> {
+        None
+    }
+
+    // Density per unit area with which sample_emitter picks the point of `hit`, so that
+    // emitters found by BSDF sampling can be weighted against it; 0 if it never does.
+    fn emitter_pdf(&self, _hit: &SurfaceHit) -> f64 {
+        0.0
+    }
+}
+
+// interface crossings a ray may make between two scattering events before the path
+// (or shadow ray) is given up on
+const MAX_CROSSINGS: usize = 64;
+
+// Unidirectional Monte Carlo path tracer. Phong materials bounce diffusely with
+// cosine-weighted sampling and Material::color as the albedo; PBR materials importance
+// sample their GGX BRDF. Light comes from the scene's lights, emissive materials and
+// the background.
+#[derive(Debug, Copy, Clone)]
+pub struct PathTracer {
+    // rounds of the sampler's offsets traced through every pixel
+    pub samples_per_pixel: usize,
+    // Where paths start within each pixel and the filter that weighs them together,
+    // as for Camera::render. Its seed and adaptive threshold aren't used: the path
+    // tracer's own seed drives everything, and every pixel is noisy enough to refine.
+    pub sampler: Supersampler,
+    pub max_depth: usize,
+    // bounces before Russian roulette may terminate a path
+    pub roulette_depth: usize,
+    // Sample the scene's lights, emitters and environment at every bounce and combine
+    // with BSDF sampling using the power heuristic. Off means lights are only found by
+    // BSDF sampling (and point, spot and directional lights are never found at all).
+    pub next_event_estimation: bool,
+    // exponential fog over the first Fog::max_distance of every segment of a path and
+    // every shadow ray, including rays that escape
+    pub fog: Option<Fog>,
+    // Give every camera ray a random wavelength and weight its result by the CIE
+    // response, so dielectrics with dispersion split white light into colours. Needs
+    // more samples than RGB mode to converge.
+    pub spectral: bool,
+    // Camera rays that escape see nothing, and the canvas alpha records the fraction
+    // of samples that hit something, so renders can be composited over a plate.
+    // Light reflected or refracted from the background still reaches the image.
+    pub transparent_background: bool,
+    pub seed: u64,
+}
+
+impl PathTracer {
+    pub fn new(samples_per_pixel: usize) -> Self {
+        PathTracer {
+            samples_per_pixel,
+            // one path anywhere in the pixel, averaged plainly
+            sampler: Supersampler {
+                pattern: SamplePattern::Jittered,
+                ..Supersampler::new(1)
+            },
+            max_depth: 16,
+            roulette_depth: 3,
+            next_event_estimation: true,
+            fog: None,
+            spectral: false,
+            transparent_background: false,
+            seed: 0,
+        }
+    }
+
+    // One path's estimate of the radiance arriving along `ray`. The camera is assumed
+    // to be outside every volume.
+    pub fn radiance<S: Scene>(&self, scene: &S, ray: Ray, rng: &mut Rng) -> Color {
+        self.trace(scene, ray, rng).0
+    }
+
+    // radiance, and whether the camera ray escaped without scattering off anything
+    fn trace<S: Scene>(&self, scene: &S, ray: Ray, rng: &mut Rng) -> (Color, bool) {
+        let mut radiance = Color::black();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = ray;
+        // pdf of the BSDF or phase sample that produced `ray`, and the vertex it was
+        // sampled at (which interface crossings leave behind); None for camera rays
+        let mut bsdf_pdf: Option<(f64, Tuple)> = None;
+        let mut medium: Option<HomogeneousMedium> = None;
+        let mut depth = 0;
+        let mut crossings = 0;
+
+        while depth < self.max_depth {
+            let hit = scene.intersect(&ray);
+            let hit_t = hit.map_or(f64::INFINITY, |h| h.t);
+            let light = closest_light(scene.lights(), &ray, hit_t);
+            let segment = light.map_or(hit_t, |(_, t)| t);
+
+            // Free-flight sampling: in a grey medium the transmittance cancels against
+            // the probability of getting this far, so passing through costs nothing.
+            let scatter_t = medium.map_or(f64::INFINITY, |m| m.sample_distance(rng));
+
+            if let Some(fog) = self.fog {
+                let transmittance = fog.transmittance(scatter_t.min(segment));
+                radiance = radiance.add(throughput.multiply(fog.color).scale(1.0 - transmittance));
+                throughput = throughput.scale(transmittance);
+            }
+
+            if let Some(m) = medium.filter(|_| scatter_t < segment) {
+                let point = ray.position(scatter_t);
+                let wo = ray.direction.negate();
+                let vertex = Vertex::Medium {
+                    point,
+                    phase: m.phase,
+                };
+                throughput = throughput.multiply(m.albedo());
+
+                if self.next_event_estimation {
+                    let direct = self.direct_lighting(scene, &vertex, &ray, medium, rng);
+                    radiance = radiance.add(throughput.multiply(direct));
+                }
+
+                // the phase function is sampled exactly, so the weight is one
+                let direction = m.phase.sample(ray.direction, rng);
+                if !self.survives(&mut throughput, depth, rng) {
+                    break;
+                }
+                bsdf_pdf = Some((vertex.pdf(wo, direction), point));
+                ray = Ray {
+                    origin: point,
+                    direction,
+                    ..ray
+                };
+                depth += 1;
+                crossings = 0;
+                continue;
+            }
+
+            // area lights in front of the closest surface end the path
+            if let Some((light, t)) = light {
+                let weight = match bsdf_pdf {
+                    Some((pdf, from)) if self.next_event_estimation => {
+                        let light_pdf = light.pdf_solid_angle(from, ray.position(t));
+                        sampling::power_heuristic(pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance = radiance.add(throughput.multiply(light.intensity).scale(weight));
+                break;
+            }
+
+            let hit = match hit {
+                Some(hit) => hit,
+                None if depth == 0 && self.transparent_background => {
+                    return (radiance, true);
+                }
+                None => {
+                    let background = match scene.environment() {
+                        Some(environment) => {
+                            let weight = match bsdf_pdf {
+                                Some((pdf, _)) if self.next_event_estimation => {
+                                    let env_pdf = environment.pdf(ray.direction);
+                                    sampling::power_heuristic(pdf, env_pdf)
+                                }
+                                _ => 1.0,
+                            };
+                            environment.lookup(ray.direction).scale(weight)
+                        }
+                        None => scene.background(&ray),
+                    };
+                    radiance = radiance.add(throughput.multiply(background));
+                    return (radiance, depth == 0);
+                }
+            };
+
+            if let SurfaceMaterial::Interface = hit.material {
+                crossings += 1;
+                if crossings > MAX_CROSSINGS {
+                    break;
+                }
+                medium = hit.medium_after(ray.direction);
+                let origin = hit.point.add(ray.direction.multiply(EPSILON));
+                ray = Ray { origin, ..ray };
+                continue;
+            }
+
+            if let SurfaceMaterial::Dielectric(glass) = hit.material {
+                let (direction, weight) =
+                    glass.scatter(ray.direction, hit.normal, ray.wavelength, rng);
+                throughput = throughput.multiply(weight);
+                if !self.survives(&mut throughput, depth, rng) {
+                    break;
+                }
+                // a delta direction: lights it happens to hit get full weight
+                bsdf_pdf = None;
+                medium = hit.medium_after(direction);
+                let side = if direction.dot(hit.normal) > 0.0 {
+                    hit.normal
+                } else {
+                    hit.normal.negate()
+                };
+                ray = Ray {
+                    origin: hit.point.add(side.multiply(EPSILON)),
+                    direction,
+                    ..ray
+                };
+                depth += 1;
+                crossings = 0;
+                continue;
+            }
+
+            let emissive = hit.material.emissive();
+            if emissive.r() > 0.0 || emissive.g() > 0.0 || emissive.b() > 0.0 {
+                let weight = match bsdf_pdf {
+                    Some((pdf, from)) if self.next_event_estimation => {
+                        let light_pdf = emitter_pdf_solid_angle(
+                            from,
+                            hit.point,
+                            hit.normal,
+                            scene.emitter_pdf(&hit),
+                        );
+                        sampling::power_heuristic(pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance = radiance.add(throughput.multiply(emissive).scale(weight));
+            }
+
+            // shade the side the ray arrived from
+            let normal = if hit.normal.dot(ray.direction) > 0.0 {
+                hit.normal.negate()
+            } else {
+                hit.normal
+            };
+            let normal = hit.material.shading_normal(normal, hit.point);
+            let wo = ray.direction.negate();
+            let vertex = Vertex::Surface {
+                point: hit.point,
+                normal,
+                material: hit.material,
+            };
+
+            if self.next_event_estimation {
+                let direct = self.direct_lighting(scene, &vertex, &ray, medium, rng);
+                radiance = radiance.add(throughput.multiply(direct));
+            }
+
+            let (direction, pdf) = match hit.material.sample(normal, wo, rng) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = throughput.multiply(vertex.eval(wo, direction).scale(1.0 / pdf));
+            if !self.survives(&mut throughput, depth, rng) {
+                break;
+            }
+
+            bsdf_pdf = Some((pdf, hit.point));
+            ray = Ray {
+                origin: vertex.origin(),
+                direction,
+                ..ray
+            };
+            depth += 1;
+            crossings = 0;
+        }
+
+        (radiance, false)
+    }
+
+    // Russian roulette once the path is deep enough; survivors are reweighted so the
+    // estimate stays unbiased
+    fn survives(&self, throughput: &mut Color, depth: usize, rng: &mut Rng) -> bool {
+        if throughput.r() <= 0.0 && throughput.g() <= 0.0 && throughput.b() <= 0.0 {
+            return false;
+        }
+        if depth + 1 >= self.roulette_depth {
+            let p = throughput
+                .r()
+                .max(throughput.g())
+                .max(throughput.b())
+                .clamp(0.05, 0.95);
+            if rng.next_f64() >= p {
+                return false;
+            }
+            *throughput = throughput.scale(1.0 / p);
+        }
+        true
+    }
+
+    // One sample of every light in the scene, plus its emitters and environment if it
+    // has them, at the vertex `ray` arrived at. Shadow rays keep the ray's time and
+    // wavelength.
+    fn direct_lighting<S: Scene>(
+        &self,
+        scene: &S,
+        vertex: &Vertex,
+        ray: &Ray,
+        medium: Option<HomogeneousMedium>,
+        rng: &mut Rng,
+    ) -> Color {
+        let mut direct = Color::black();
+        for light in scene.lights() {
+            direct = direct.add(self.direct_light(scene, light, vertex, ray, medium, rng));
+        }
+        if let Some(emitter) = scene.sample_emitter(rng) {
+            direct = direct.add(self.direct_emitter(scene, &emitter, vertex, ray, medium));
+        }
+        if let Some(environment) = scene.environment() {
+            direct =
+                direct.add(self.direct_environment(scene, environment, vertex, ray, medium, rng));
+        }
+        direct
+    }
+
+    // Like direct_light, for a direction drawn from the environment map. Always
+    // MIS-weighted, since BSDF samples that escape find the environment too.
+    fn direct_environment<S: Scene>(
+        &self,
+        scene: &S,
+        environment: &EnvironmentMap,
+        vertex: &Vertex,
+        ray: &Ray,
+        medium: Option<HomogeneousMedium>,
+        rng: &mut Rng,
+    ) -> Color {
+        let wo = ray.direction.negate();
+        let (wi, incoming, env_pdf) = match environment.sample(rng) {
+            Some(sample) => sample,
+            None => return Color::black(),
+        };
+        let f = vertex.eval(wo, wi);
+        if f.r() <= 0.0 && f.g() <= 0.0 && f.b() <= 0.0 {
+            return Color::black();
+        }
+        let weight = sampling::power_heuristic(env_pdf, vertex.pdf(wo, wi));
+        let shadow = Ray {
+            origin: vertex.origin(),
+            direction: wi,
+            ..*ray
+        };
+        let transmittance = self.transmittance(scene, shadow, f64::INFINITY, medium);
+        f.multiply(incoming).scale(transmittance * weight / env_pdf)
+    }
+
+    // Like direct_light, for a point on an emissive surface. The shadow ray stops just
+    // short of it, since the emitter is itself one of the scene's surfaces.
+    fn direct_emitter<S: Scene>(
+        &self,
+        scene: &S,
+        emitter: &EmitterSample,
+        vertex: &Vertex,
+        ray: &Ray,
+        medium: Option<HomogeneousMedium>,
+    ) -> Color {
+        let wo = ray.direction.negate();
+        let origin = vertex.origin();
+        let light_pdf =
+            emitter_pdf_solid_angle(vertex.point(), emitter.point, emitter.normal, emitter.pdf);
+        if light_pdf <= 0.0 {
+            return Color::black();
+        }
+        let v = emitter.point.minus(origin);
+        let wi = v.normalize();
+        let f = vertex.eval(wo, wi);
+        if f.r() <= 0.0 && f.g() <= 0.0 && f.b() <= 0.0 {
+            return Color::black();
+        }
+        let weight = sampling::power_heuristic(light_pdf, vertex.pdf(wo, wi));
+        let shadow = Ray {
+            origin,
+            direction: wi,
+            ..*ray
+        };
+        let transmittance = self.transmittance(scene, shadow, v.magnitude() - EPSILON, medium);
+        f.multiply(emitter.radiance)
+            .scale(transmittance * weight / light_pdf)
+    }
+
+    // Light arriving at a vertex from one sample of `light`, already multiplied by the
+    // BSDF and cosine (or phase function) and by the transmittance on the way. Area
+    // lights are MIS-weighted against BSDF sampling; lights without extent can only be
+    // reached this way and get full weight.
+    fn direct_light<S: Scene>(
+        &self,
+        scene: &S,
+        light: &Light,
+        vertex: &Vertex,
+        ray: &Ray,
+        medium: Option<HomogeneousMedium>,
+        rng: &mut Rng,
+    ) -> Color {
+        let wo = ray.direction.negate();
+        let point = vertex.point();
+        let origin = vertex.origin();
+        let (wi, distance, incoming, weight) = match light {
+            Light::Area(area) => {
+                let (light_point, _, _) = area.sample_surface(rng);
+                let light_pdf = area.pdf_solid_angle(point, light_point);
+                if light_pdf <= 0.0 {
+                    return Color::black();
+                }
+                let v = light_point.minus(origin);
+                let wi = v.normalize();
+                (
+                    wi,
+                    v.magnitude(),
+                    area.intensity.scale(1.0 / light_pdf),
+                    sampling::power_heuristic(light_pdf, vertex.pdf(wo, wi)),
+                )
+            }
+            _ => (
+                light.direction_from(point),
+                light.distance_from(point),
+                light.intensity_at(point),
+                1.0,
+            ),
+        };
+
+        let f = vertex.eval(wo, wi);
+        if f.r() <= 0.0 && f.g() <= 0.0 && f.b() <= 0.0 {
+            return Color::black();
+        }
+        let shadow = Ray {
+            origin,
+            direction: wi,
+            ..*ray
+        };
+        let transmittance = self.transmittance(scene, shadow, distance - EPSILON, medium);
+        f.multiply(incoming).scale(transmittance * weight)
+    }
+
+    // Fraction of light that makes it along the shadow ray for `distance`: zero if a
+    // surface is in the way, attenuated by any volumes crossed and by the fog otherwise.
+    // Like rays that escape, lights at an infinite distance are dimmed by the fog's
+    // whole depth.
+    fn transmittance<S: Scene>(
+        &self,
+        scene: &S,
+        shadow: Ray,
+        distance: f64,
+        medium: Option<HomogeneousMedium>,
+    ) -> f64 {
+        let direction = shadow.direction;
+        let mut transmittance = self.fog.map_or(1.0, |fog| fog.transmittance(distance));
+        let mut shadow = shadow;
+        let mut remaining = distance;
+        let mut medium = medium;
+        for _ in 0..MAX_CROSSINGS {
+            let hit = match scene.intersect(&shadow) {
+                Some(hit) if hit.t < remaining => hit,
+                _ => {
+                    if let Some(m) = medium {
+                        transmittance *= m.transmittance(remaining);
+                    }
+                    return transmittance;
+                }
+            };
+            if !matches!(hit.material, SurfaceMaterial::Interface) {
+                return 0.0;
+            }
+            if let Some(m) = medium {
+                transmittance *= m.transmittance(hit.t);
+            }
+            medium = hit.medium_after(direction);
+            shadow.origin = hit.point.add(direction.multiply(EPSILON));
+            remaining -= hit.t + EPSILON;
+        }
+        0.0
+    }
+
+    pub fn render<S: Scene>(&self, camera: &Camera, scene: &S) -> Canvas {
+        let mut canvas = Canvas::new(camera.hsize, camera.vsize);
+        let mut rng = Rng::new(self.seed);
+        let samples = self.samples_per_pixel.max(1);
+        for y in 0..camera.vsize {
+            for x in 0..camera.hsize {
+                let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let mut sum = Color::black();
+                let mut covered = 0.0;
+                let mut total_weight = 0.0;
+                for _ in 0..samples {
+                    for (dx, dy) in self.sampler.offsets(&mut rng) {
+                        let w = self.sampler.filter.weight(dx, dy);
+                        if w == 0.0 {
+                            continue;
+                        }
+                        total_weight += w;
+                        let mut ray = match camera.ray_for_point(cx + dx, cy + dy, &mut rng) {
+                            Some(ray) => ray,
+                            None => continue,
+                        };
+                        let (radiance, escaped) = if self.spectral {
+                            let wavelength = spectrum::sample_wavelength(rng.next_f64());
+                            ray.wavelength = Some(wavelength);
+                            let (radiance, escaped) = self.trace(scene, ray, &mut rng);
+                            let weight = spectrum::wavelength_weight(wavelength);
+                            (radiance.multiply(weight), escaped)
+                        } else {
+                            self.trace(scene, ray, &mut rng)
+                        };
+                        sum = sum.add(radiance.scale(w));
+                        if !escaped {
+                            covered += w;
+                        }
+                    }
+                }
+                if total_weight == 0.0 {
+                    continue;
+                }
+                canvas.write_pixel(x, y, sum.scale(1.0 / total_weight));
+                if self.transparent_background {
+                    // negative filter lobes can push the coverage just past [0, 1]
+                    canvas.write_alpha(x, y, (covered / total_weight).clamp(0.0, 1.0));
+                }
+            }
+        }
+        canvas
+    }
+}
+
+// An emitter's per-area density seen as a density per unit solid angle from `from`.
+// Emitters shine from both faces, so only edge-on points have no density.
+fn emitter_pdf_solid_angle(from: Tuple, point: Tuple, normal: Tuple, area_pdf: f64) -> f64 {
+    let v = point.minus(from);
+    let distance_squared = v.dot(v);
+    let cos_light = normal.dot(v.normalize()).abs();
+    if area_pdf <= 0.0 || cos_light <= 0.0 {
+        return 0.0;
+    }
+    area_pdf * distance_squared / cos_light
+}
+
+fn closest_light<'a>(lights: &'a [Light], ray: &Ray, max_t: f64) -> Option<(&'a AreaLight, f64)> {
+    let mut closest: Option<(&AreaLight, f64)> = None;
+    for light in lights {
+        if let Light::Area(area) = light {
+            if let Some(t) = area.intersect(ray) {
+                if t < max_t && closest.is_none_or(|(_, best)| t < best) {
+                    closest = Some((area, t));
+                }
+            }
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dielectric::Dispersion;
+    use crate::light::{DirectionalLight, PointLight};
+    use crate::math_utils;
+    use crate::normal_map::NormalPerturbation;
+    use crate::sampling::Filter;
+
+    // the inside of a sphere around the origin, every point the same material
+    struct Furnace {
+        radius: f64,
+        material: SurfaceMaterial,
+    }
+
+    impl Scene for Furnace {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let o = ray.origin;
+            let o = Tuple::new_vector(o.x, o.y, o.z);
+            let a = ray.direction.dot(ray.direction);
+            let b = 2.0 * o.dot(ray.direction);
+            let c = o.dot(o) - self.radius * self.radius;
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 {
+                return None;
+            }
+            let t = (-b + disc.sqrt()) / (2.0 * a);
+            if t <= 0.0 {
+                return None;
+            }
+            let point = ray.position(t);
+            Some(SurfaceHit {
+                t,
+                point,
+                normal: Tuple::new_vector(point.x, point.y, point.z).normalize(),
+                material: self.material,
+                medium: None,
+            })
+        }
+    }
+
+    struct Empty;
+
+    impl Scene for Empty {
+        fn intersect(&self, _ray: &Ray) -> Option<SurfaceHit> {
+            None
+        }
+
+        fn background(&self, ray: &Ray) -> Color {
+            Color::new(0.5, 0.5, ray.direction.y.abs())
+        }
+    }
+
+    fn furnace(albedo: f64) -> Furnace {
+        let mut material = Material::new();
+        material.color = Color::new(albedo, albedo, albedo);
+        material.emissive = Color::new(1.0, 1.0, 1.0);
+        Furnace {
+            radius: 1.0,
+            material: SurfaceMaterial::Phong(material),
+        }
+    }
+
+    fn ray() -> Ray {
+        Ray::new(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_vector(0.0, 0.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn misses_see_background() {
+        let pt = PathTracer::new(1);
+        let up = Ray::new(
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+        );
+        let c = pt.radiance(&Empty, up, &mut Rng::new(0));
+        assert!(c.equals(&Color::new(0.5, 0.5, 1.0)));
+    }
+
+    #[test]
+    fn fixed_depth_sums_bounces() {
+        // without roulette every path bounces max_depth times: sum of albedo^k
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 5;
+        pt.roulette_depth = 100;
+        let c = pt.radiance(&furnace(0.5), ray(), &mut Rng::new(0));
+        let expected = 1.0 + 0.5 + 0.25 + 0.125 + 0.0625;
+        assert!(math_utils::f64_equals(c.r(), expected));
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // emission E inside a closed furnace of albedo a converges to E / (1 - a)
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 200;
+        pt.roulette_depth = 1;
+        let scene = furnace(0.5);
+        let mut rng = Rng::new(99);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += pt.radiance(&scene, ray(), &mut rng).r();
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 2.0).abs() < 0.05, "mean was {}", mean);
+    }
+
+    #[test]
+    fn render_averages_samples() {
+        let camera = Camera::new(3, 2, PI / 2.0);
+        let canvas = PathTracer::new(4).render(&camera, &furnace(0.0));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!(canvas.pixel_at(x, y).equals(&Color::new(1.0, 1.0, 1.0)));
+            }
+        }
+    }
+
+    // an emitter filling the half of the view with positive x, in front of a sky
+    struct HalfWall;
+
+    impl Scene for HalfWall {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            if ray.direction.x <= 0.0 {
+                return None;
+            }
+            let mut material = Material::new();
+            material.color = Color::black();
+            material.emissive = Color::new(1.0, 1.0, 1.0);
+            Some(SurfaceHit {
+                t: 1.0,
+                point: ray.position(1.0),
+                normal: ray.direction.negate(),
+                material: SurfaceMaterial::Phong(material),
+                medium: None,
+            })
+        }
+
+        fn background(&self, _ray: &Ray) -> Color {
+            Color::new(0.0, 0.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn transparent_background_records_coverage() {
+        let camera = Camera::new(4, 1, PI / 2.0);
+        let mut pt = PathTracer::new(4);
+        let canvas = pt.render(&camera, &HalfWall);
+        assert!(canvas.is_opaque());
+
+        pt.transparent_background = true;
+        let canvas = pt.render(&camera, &HalfWall);
+        let mut covered = 0;
+        for x in 0..4 {
+            let alpha = canvas.alpha_at(x, 0);
+            assert!(alpha == 0.0 || alpha == 1.0, "{}", alpha);
+            covered += alpha as usize;
+            // premultiplied: the sky never shows through
+            let white = Color::new(1.0, 1.0, 1.0);
+            assert!(canvas.pixel_at(x, 0).equals(&white.scale(alpha)));
+        }
+        assert_eq!(covered, 2);
+    }
+
+    #[test]
+    fn render_reconstructs_with_the_sampler_filter() {
+        // the wall's edge runs between the middle two pixels
+        let camera = Camera::new(4, 1, PI / 2.0);
+        let mut pt = PathTracer::new(1);
+        pt.sampler = Supersampler::new(4);
+        let sharp = pt.render(&camera, &HalfWall);
+        let red: Vec<f64> = (0..4).map(|x| sharp.pixel_at(x, 0).r()).collect();
+        assert_eq!(red, [1.0, 1.0, 0.0, 0.0]);
+
+        // a tent reaches a pixel into the neighbours, just as it does in Camera::render
+        pt.sampler.filter = Filter::Tent;
+        let soft = pt.render(&camera, &HalfWall);
+        let red: Vec<f64> = (0..4).map(|x| soft.pixel_at(x, 0).r()).collect();
+        assert_eq!((red[0], red[3]), (1.0, 0.0));
+        assert!(red[1] > 0.5 && red[1] < 1.0, "{:?}", red);
+        assert!(math_utils::f64_equals(red[1] + red[2], 1.0), "{:?}", red);
+    }
+
+    #[test]
+    fn pbr_furnace() {
+        // A white metal reflects most of what reaches it. A black one (F0 = 0) still has
+        // Schlick's grazing reflectance, but from the centre every ray meets the wall
+        // head on, so only the few sampled directions far from the normal add anything.
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 3;
+        pt.roulette_depth = 100;
+
+        let mut black = PbrMaterial::new(Color::black(), 1.0, 0.5);
+        black.emissive = Color::new(1.0, 1.0, 1.0);
+        let scene = Furnace {
+            radius: 1.0,
+            material: SurfaceMaterial::Pbr(black),
+        };
+        let mut rng = Rng::new(3);
+        let n = 2000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let v = pt.radiance(&scene, ray(), &mut rng).r();
+            // the emission seen directly, plus whatever the grazing reflections add
+            assert!(v >= 1.0, "{}", v);
+            sum += v;
+        }
+        let mean = sum / n as f64;
+        assert!(mean < 1.01, "mean was {}", mean);
+
+        let mut white = PbrMaterial::new(Color::new(1.0, 1.0, 1.0), 1.0, 0.5);
+        white.emissive = Color::new(1.0, 1.0, 1.0);
+        let scene = Furnace {
+            radius: 1.0,
+            material: SurfaceMaterial::Pbr(white),
+        };
+        let mut rng = Rng::new(4);
+        let n = 2000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += pt.radiance(&scene, ray(), &mut rng).r();
+        }
+        let mean = sum / n as f64;
+        // three bounces of an albedo just under one
+        assert!(mean > 2.5 && mean <= 3.01, "mean was {}", mean);
+    }
+
+    // diffuse floor at z = 0 with lights above it
+    struct Floor {
+        material: SurfaceMaterial,
+        lights: Vec<Light>,
+    }
+
+    impl Scene for Floor {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            if ray.direction.z.abs() < f64::EPSILON {
+                return None;
+            }
+            let t = -ray.origin.z / ray.direction.z;
+            if t <= 0.0 {
+                return None;
+            }
+            Some(SurfaceHit {
+                t,
+                point: ray.position(t),
+                normal: Tuple::new_vector(0.0, 0.0, 1.0),
+                material: self.material,
+                medium: None,
+            })
+        }
+
+        fn lights(&self) -> &[Light] {
+            &self.lights
+        }
+    }
+
+    fn floor(light: Light) -> Floor {
+        let mut material = Material::new();
+        material.color = Color::new(0.5, 0.5, 0.5);
+        Floor {
+            material: SurfaceMaterial::Phong(material),
+            lights: vec![light],
+        }
+    }
+
+    fn down() -> Ray {
+        Ray::new(
+            Tuple::new_point(0.0, 0.0, 1.0),
+            Tuple::new_vector(0.0, 0.0, -1.0),
+        )
+    }
+
+    #[test]
+    fn point_lights_are_sampled_directly() {
+        let scene = floor(Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, 2.0),
+            Color::new(1.0, 1.0, 1.0),
+        )));
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 1;
+        let c = pt.radiance(&scene, down(), &mut Rng::new(0));
+        assert!(math_utils::f64_equals(c.r(), 0.5 / PI));
+
+        pt.next_event_estimation = false;
+        let c = pt.radiance(&scene, down(), &mut Rng::new(0));
+        assert!(c.equals(&Color::black()));
+    }
+
+    #[test]
+    fn materials_shade_with_their_perturbed_normal() {
+        let mut scene = floor(Light::Point(PointLight::new(
+            Tuple::new_point(0.0, 0.0, 2.0),
+            Color::new(1.0, 1.0, 1.0),
+        )));
+        let mut material = Material::new();
+        material.color = Color::new(0.5, 0.5, 0.5);
+        material.normal_perturbation = Some(NormalPerturbation::Bump {
+            height: |p| p.x,
+            scale: 1.0,
+        });
+        scene.material = SurfaceMaterial::Phong(material);
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 1;
+        // the light overhead now arrives at 45 degrees to the tilted normal
+        let c = pt.radiance(&scene, down(), &mut Rng::new(0));
+        assert!(math_utils::f64_equals(c.r(), 0.5 / PI / 2.0_f64.sqrt()));
+    }
+
+    // the floor, with a blocker over it that is only there late in the shutter interval
+    // or for rays carrying a wavelength
+    struct Shutter {
+        floor: Floor,
+    }
+
+    impl Scene for Shutter {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let hit = self.floor.intersect(ray);
+            if ray.time < 0.5 && ray.wavelength.is_none() {
+                return hit;
+            }
+            let t = (1.5 - ray.origin.z) / ray.direction.z;
+            if t <= 0.0 || hit.is_some_and(|h| h.t < t) {
+                return hit;
+            }
+            Some(SurfaceHit {
+                t,
+                point: ray.position(t),
+                normal: Tuple::new_vector(0.0, 0.0, -1.0),
+                material: self.floor.material,
+                medium: None,
+            })
+        }
+
+        fn lights(&self) -> &[Light] {
+            self.floor.lights()
+        }
+    }
+
+    #[test]
+    fn shadow_rays_keep_time_and_wavelength() {
+        let scene = Shutter {
+            floor: floor(Light::Point(PointLight::new(
+                Tuple::new_point(0.0, 0.0, 2.0),
+                Color::new(1.0, 1.0, 1.0),
+            ))),
+        };
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 1;
+        let early = pt.radiance(&scene, down(), &mut Rng::new(0));
+        assert!(math_utils::f64_equals(early.r(), 0.5 / PI));
+
+        let late = Ray::with_time(down().origin, down().direction, 0.75);
+        let c = pt.radiance(&scene, late, &mut Rng::new(0));
+        assert!(c.equals(&Color::black()));
+
+        let mut spectral = down();
+        spectral.wavelength = Some(550.0);
+        let c = pt.radiance(&scene, spectral, &mut Rng::new(0));
+        assert!(c.equals(&Color::black()));
+    }
+
+    // a unit square light two units above the floor, facing down
+    fn square_light(le: f64) -> Light {
+        Light::Area(AreaLight::new_rect(
+            Tuple::new_point(-0.5, -0.5, 2.0),
+            Tuple::new_vector(1.0, 0.0, 0.0),
+            1,
+            Tuple::new_vector(0.0, 1.0, 0.0),
+            1,
+            Color::new(le, le, le),
+        ))
+    }
+
+    // light reflected straight up by the floor under square_light:
+    // albedo / pi * Le * integral of cos * cos / d^2 over the light
+    fn square_light_reference(le: f64) -> f64 {
+        let steps = 400;
+        let cell = 1.0 / steps as f64;
+        let mut integral = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let x = -0.5 + (i as f64 + 0.5) * cell;
+                let y = -0.5 + (j as f64 + 0.5) * cell;
+                let d2 = x * x + y * y + 4.0;
+                integral += 4.0 / (d2 * d2) * cell * cell;
+            }
+        }
+        0.5 / PI * le * integral
+    }
+
+    // mean and variance of the radiance looking down at the floor, one bounce deep
+    fn estimate_floor<S: Scene>(scene: &S, nee: bool) -> (f64, f64) {
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 2;
+        pt.next_event_estimation = nee;
+        let mut rng = Rng::new(17);
+        let n = 20000;
+        let mut sum = 0.0;
+        let mut sum_sq = 0.0;
+        for _ in 0..n {
+            let v = pt.radiance(scene, down(), &mut rng).r();
+            sum += v;
+            sum_sq += v * v;
+        }
+        let mean = sum / n as f64;
+        (mean, sum_sq / n as f64 - mean * mean)
+    }
+
+    #[test]
+    fn mis_matches_bsdf_sampling_with_less_noise() {
+        let scene = floor(square_light(10.0));
+        let expected = square_light_reference(10.0);
+
+        let (mis_mean, mis_var) = estimate_floor(&scene, true);
+        let (bsdf_mean, bsdf_var) = estimate_floor(&scene, false);
+        assert!(
+            (mis_mean - expected).abs() / expected < 0.02,
+            "{} vs {}",
+            mis_mean,
+            expected
+        );
+        assert!(
+            (bsdf_mean - expected).abs() / expected < 0.1,
+            "{} vs {}",
+            bsdf_mean,
+            expected
+        );
+        assert!(mis_var * 10.0 < bsdf_var, "{} vs {}", mis_var, bsdf_var);
+    }
+
+    // the floor under a glowing surface where square_light would be, which the scene
+    // samples as an emitter
+    struct Panel {
+        floor: Floor,
+        radiance: Color,
+    }
+
+    impl Scene for Panel {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let hit = self.floor.intersect(ray);
+            let t = (2.0 - ray.origin.z) / ray.direction.z;
+            if t <= 0.0 || !t.is_finite() || hit.is_some_and(|h| h.t < t) {
+                return hit;
+            }
+            let point = ray.position(t);
+            if point.x.abs() > 0.5 || point.y.abs() > 0.5 {
+                return hit;
+            }
+            let mut material = Material::new();
+            material.color = Color::black();
+            material.emissive = self.radiance;
+            Some(SurfaceHit {
+                t,
+                point,
+                normal: Tuple::new_vector(0.0, 0.0, -1.0),
+                material: SurfaceMaterial::Phong(material),
+                medium: None,
+            })
+        }
+
+        fn sample_emitter(&self, rng: &mut Rng) -> Option<EmitterSample> {
+            let (x, y) = (rng.next_f64() - 0.5, rng.next_f64() - 0.5);
+            Some(EmitterSample {
+                point: Tuple::new_point(x, y, 2.0),
+                normal: Tuple::new_vector(0.0, 0.0, -1.0),
+                radiance: self.radiance,
+                pdf: 1.0,
+            })
+        }
+
+        fn emitter_pdf(&self, hit: &SurfaceHit) -> f64 {
+            if hit.point.z > 1.0 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn emissive_surfaces_are_sampled_like_lights() {
+        let mut scene = floor(square_light(0.0));
+        scene.lights.clear();
+        let scene = Panel {
+            floor: scene,
+            radiance: Color::new(10.0, 10.0, 10.0),
+        };
+        let expected = square_light_reference(10.0);
+
+        let (nee_mean, nee_var) = estimate_floor(&scene, true);
+        let (bsdf_mean, bsdf_var) = estimate_floor(&scene, false);
+        assert!(
+            (nee_mean - expected).abs() / expected < 0.02,
+            "{} vs {}",
+            nee_mean,
+            expected
+        );
+        assert!(
+            (bsdf_mean - expected).abs() / expected < 0.1,
+            "{} vs {}",
+            bsdf_mean,
+            expected
+        );
+        assert!(nee_var * 10.0 < bsdf_var, "{} vs {}", nee_var, bsdf_var);
+    }
+
+    // the floor with an empty interface at z = 1, between it and its lights
+    struct Veil {
+        floor: Floor,
+    }
+
+    impl Scene for Veil {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let hit = self.floor.intersect(ray);
+            let t = (1.0 - ray.origin.z) / ray.direction.z;
+            if t <= 0.0 || !t.is_finite() || hit.is_some_and(|h| h.t < t) {
+                return hit;
+            }
+            Some(SurfaceHit {
+                t,
+                point: ray.position(t),
+                normal: Tuple::new_vector(0.0, 0.0, 1.0),
+                material: SurfaceMaterial::Interface,
+                medium: None,
+            })
+        }
+
+        fn lights(&self) -> &[Light] {
+            self.floor.lights()
+        }
+    }
+
+    // a glowing floor at z = 0 under forty empty interfaces, one at each whole z, and a
+    // white sky
+    struct Layers {
+        floor: Floor,
+    }
+
+    impl Scene for Layers {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let hit = self.floor.intersect(ray);
+            let z = ray.origin.z;
+            let next = if ray.direction.z > 0.0 {
+                z.floor() + 1.0
+            } else {
+                z.ceil() - 1.0
+            };
+            let t = (next - z) / ray.direction.z;
+            if !(1.0..=40.0).contains(&next) || hit.is_some_and(|h| h.t < t) {
+                return hit;
+            }
+            Some(SurfaceHit {
+                t,
+                point: ray.position(t),
+                normal: Tuple::new_vector(0.0, 0.0, 1.0),
+                material: SurfaceMaterial::Interface,
+                medium: None,
+            })
+        }
+
+        fn background(&self, _ray: &Ray) -> Color {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn crossing_limit_applies_between_scattering_events() {
+        // forty crossings down to the floor and forty back up to the sky: more than
+        // MAX_CROSSINGS for the whole path, but not for either of its two rays
+        let mut material = Material::new();
+        material.color = Color::new(0.5, 0.5, 0.5);
+        material.emissive = Color::new(1.0, 1.0, 1.0);
+        let scene = Layers {
+            floor: Floor {
+                material: SurfaceMaterial::Phong(material),
+                lights: vec![],
+            },
+        };
+        let mut pt = PathTracer::new(1);
+        pt.roulette_depth = 100;
+        let start = Ray::new(
+            Tuple::new_point(0.0, 0.0, 40.5),
+            Tuple::new_vector(0.0, 0.0, -1.0),
+        );
+        let c = pt.radiance(&scene, start, &mut Rng::new(0));
+        assert!(c.equals(&Color::new(1.5, 1.5, 1.5)), "{:?}", c);
+    }
+
+    #[test]
+    fn mis_weights_lights_seen_through_an_interface() {
+        // the MIS weight of a light found by a BSDF sample is measured from the floor,
+        // not from where the ray crossed the interface on the way
+        let scene = Veil {
+            floor: floor(square_light(10.0)),
+        };
+        let expected = square_light_reference(10.0);
+        let (mis_mean, _) = estimate_floor(&scene, true);
+        let (bsdf_mean, _) = estimate_floor(&scene, false);
+        assert!(
+            (mis_mean - expected).abs() / expected < 0.02,
+            "{} vs {}",
+            mis_mean,
+            expected
+        );
+        assert!(
+            (bsdf_mean - mis_mean).abs() / expected < 0.1,
+            "{} vs {}",
+            bsdf_mean,
+            mis_mean
+        );
+    }
+
+    // a unit sphere of some medium at the origin under a white sky
+    struct Volume {
+        medium: HomogeneousMedium,
+    }
+
+    impl Scene for Volume {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let o = ray.origin;
+            let o = Tuple::new_vector(o.x, o.y, o.z);
+            let b = o.dot(ray.direction);
+            let c = o.dot(o) - 1.0;
+            let disc = b * b - c;
+            if disc < 0.0 {
+                return None;
+            }
+            let t = [-b - disc.sqrt(), -b + disc.sqrt()]
+                .into_iter()
+                .find(|&t| t > 0.0)?;
+            let point = ray.position(t);
+            Some(SurfaceHit {
+                t,
+                point,
+                normal: Tuple::new_vector(point.x, point.y, point.z),
+                material: SurfaceMaterial::Interface,
+                medium: Some(self.medium),
+            })
+        }
+
+        fn background(&self, _ray: &Ray) -> Color {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    fn through_volume() -> Ray {
+        Ray::new(
+            Tuple::new_point(0.0, 0.0, -5.0),
+            Tuple::new_vector(0.0, 0.0, 1.0),
+        )
+    }
+
+    fn mean_radiance<S: Scene>(pt: &PathTracer, scene: &S, ray: Ray, n: usize) -> f64 {
+        let mut rng = Rng::new(41);
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += pt.radiance(scene, ray, &mut rng).r();
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn absorbing_volume_follows_beer_lambert() {
+        let scene = Volume {
+            medium: HomogeneousMedium::new(0.5, 0.0, 0.0),
+        };
+        let pt = PathTracer::new(1);
+        // two units of medium along the diameter
+        let expected = (-1.0_f64).exp();
+        let mean = mean_radiance(&pt, &scene, through_volume(), 20000);
+        assert!((mean - expected).abs() < 0.02, "{} vs {}", mean, expected);
+
+        let t = pt.transmittance(&scene, through_volume(), 10.0, None);
+        assert!(math_utils::f64_equals(t, expected));
+    }
+
+    #[test]
+    fn scattering_volume_conserves_energy() {
+        // nothing is absorbed, so every path eventually escapes to the white sky
+        let scene = Volume {
+            medium: HomogeneousMedium::new(0.0, 2.0, 0.6),
+        };
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 200;
+        let mean = mean_radiance(&pt, &scene, through_volume(), 20000);
+        assert!((mean - 1.0).abs() < 0.03, "{}", mean);
+    }
+
+    #[test]
+    fn fog_covers_distant_and_missed_rays() {
+        let mut pt = PathTracer::new(1);
+        let fog = Fog::new(Color::new(0.2, 0.2, 0.2), 0.1, 10.0);
+        pt.fog = Some(fog);
+        let mut rng = Rng::new(0);
+        let c = pt.radiance(&Empty, ray(), &mut rng);
+        let background = Empty.background(&ray());
+        assert!(c.equals(&fog.apply(background, f64::INFINITY)));
+        assert!(!c.equals(&fog.color));
+    }
+
+    #[test]
+    fn fog_dims_sampled_and_found_lights_alike() {
+        let fog = Fog::new(Color::new(0.1, 0.1, 0.1), 0.2, 5.0);
+        let scene = floor(square_light(10.0));
+        let estimate = |nee: bool| {
+            let mut pt = PathTracer::new(1);
+            pt.max_depth = 2;
+            pt.next_event_estimation = nee;
+            pt.fog = Some(fog);
+            mean_radiance(&pt, &scene, down(), 20000)
+        };
+        let (with, without) = (estimate(true), estimate(false));
+        assert!(
+            (with - without).abs() / with < 0.05,
+            "{} vs {}",
+            with,
+            without
+        );
+        // the light is at least two units away, so it is dimmed by at least this much
+        let clear = square_light_reference(10.0);
+        assert!(with < 0.1 + clear * fog.transmittance(2.0), "{}", with);
+
+        // a sun is infinitely far away, but only the fog's depth stands in its way
+        let scene = floor(Light::Directional(DirectionalLight::new(
+            Tuple::new_vector(0.0, 0.0, -1.0),
+            Color::new(1.0, 1.0, 1.0),
+        )));
+        let mut pt = PathTracer::new(1);
+        pt.fog = Some(fog);
+        let vertex = Vertex::Surface {
+            point: Tuple::new_point(0.0, 0.0, 0.0),
+            normal: Tuple::new_vector(0.0, 0.0, 1.0),
+            material: scene.material,
+        };
+        let direct = pt.direct_lighting(&scene, &vertex, &down(), None, &mut Rng::new(0));
+        let dimmed = 0.5 / PI * (-0.2_f64 * 5.0).exp();
+        assert!(math_utils::f64_equals(direct.r(), dimmed), "{:?}", direct);
+        pt.fog = None;
+        let direct = pt.direct_lighting(&scene, &vertex, &down(), None, &mut Rng::new(0));
+        assert!(math_utils::f64_equals(direct.r(), 0.5 / PI));
+    }
+
+    // the diffuse floor under an environment map
+    struct Sky {
+        floor: Floor,
+        environment: EnvironmentMap,
+    }
+
+    impl Scene for Sky {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            self.floor.intersect(ray)
+        }
+
+        fn environment(&self) -> Option<&EnvironmentMap> {
+            Some(&self.environment)
+        }
+    }
+
+    #[test]
+    fn environment_lights_misses_and_surfaces() {
+        // white above the horizon (the map's top half is +y; the floor faces +z, so
+        // make the whole map white and let the floor block the lower half)
+        let mut canvas = Canvas::new(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                canvas.write_pixel(x, y, Color::new(1.0, 1.0, 1.0));
+            }
+        }
+        let mut material = Material::new();
+        material.color = Color::new(0.5, 0.5, 0.5);
+        let scene = Sky {
+            floor: Floor {
+                material: SurfaceMaterial::Phong(material),
+                lights: vec![],
+            },
+            environment: EnvironmentMap::new(canvas),
+        };
+
+        let up = Ray::new(
+            Tuple::new_point(0.0, 0.0, 1.0),
+            Tuple::new_vector(0.0, 0.0, 1.0),
+        );
+        let c = PathTracer::new(1).radiance(&scene, up, &mut Rng::new(0));
+        assert!(c.equals(&Color::new(1.0, 1.0, 1.0)));
+
+        // a diffuse surface under a uniform sky reflects its albedo
+        for nee in [true, false] {
+            let mut pt = PathTracer::new(1);
+            pt.max_depth = 2;
+            pt.next_event_estimation = nee;
+            let mut rng = Rng::new(9);
+            let n = 20000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += pt.radiance(&scene, down(), &mut rng).r();
+            }
+            let mean = sum / n as f64;
+            assert!((mean - 0.5).abs() < 0.02, "nee {}: {}", nee, mean);
+        }
+    }
+
+    // a solid glass ball under a white sky
+    struct GlassBall {
+        glass: Dielectric,
+    }
+
+    impl Scene for GlassBall {
+        fn intersect(&self, ray: &Ray) -> Option<SurfaceHit> {
+            let mut hit = Volume {
+                medium: HomogeneousMedium::new(0.0, 0.0, 0.0),
+            }
+            .intersect(ray)?;
+            hit.material = SurfaceMaterial::Dielectric(self.glass);
+            hit.medium = None;
+            Some(hit)
+        }
+
+        fn background(&self, _ray: &Ray) -> Color {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn clear_glass_loses_no_light() {
+        let scene = GlassBall {
+            glass: Dielectric::new(Dispersion::bk7()),
+        };
+        let mut pt = PathTracer::new(1);
+        pt.max_depth = 100;
+        let mean = mean_radiance(&pt, &scene, through_volume(), 5000);
+        assert!((mean - 1.0).abs() < 0.02, "{}", mean);
+    }
+
+    #[test]
+    fn spectral_render_of_white_stays_white() {
+        let scene = GlassBall {
+            glass: Dielectric::new(Dispersion::diamond()),
+        };
+        let mut camera = Camera::new(1, 1, 0.1);
+        camera.set_transform(crate::matrix::Matrix::view_transform(
+            Tuple::new_point(0.0, 0.0, -5.0),
+            Tuple::new_point(0.0, 0.0, 0.0),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+        ));
+        let mut pt = PathTracer::new(4000);
+        pt.spectral = true;
+        let c = pt.render(&camera, &scene).pixel_at(0, 0);
+        assert!(c.distance(&Color::new(1.0, 1.0, 1.0)) < 0.1, "{:?}", c);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::canvas::Canvas;
+use crate::tuple::Color;
+
+// Portable Float Map: a text header followed by raw 32-bit floats, with the rows stored
+// bottom to top. A negative scale in the header means little-endian samples. Colour
+// ("PF") and greyscale ("Pf") files can be read; colour files are written.
+
+pub fn write_pfm<W: Write>(canvas: &Canvas, writer: W) -> io::Result<()> {
+    let mut out = BufWriter::new(writer);
+    write!(out, "PF\n{} {}\n-1.0\n", canvas.width(), canvas.height())?;
+    for row in canvas.rows().rev() {
+        for pixel in row {
+            for channel in [pixel.r(), pixel.g(), pixel.b()] {
+                out.write_all(&(channel as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+pub fn read_pfm(path: &str) -> io::Result<Canvas> {
+    decode_pfm(&std::fs::read(path)?)
+}
+
+pub fn decode_pfm(bytes: &[u8]) -> io::Result<Canvas> {
+    let mut pos = 0;
+    let channels = match read_token(bytes, &mut pos)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let width = read_token(bytes, &mut pos)?;
+    let height = read_token(bytes, &mut pos)?;
+    let (width, height) = match (width.parse::<usize>(), height.parse::<usize>()) {
+        (Ok(w), Ok(h)) => (w, h),
+        _ => return Err(invalid(&format!("bad size {} {}", width, height))),
+    };
+    let scale = read_token(bytes, &mut pos)?;
+    // the magnitude of the scale is a hint nobody agrees on, so only its sign is used
+    let little_endian = match scale.parse::<f64>() {
+        Ok(s) if s != 0.0 => s < 0.0,
+        _ => return Err(invalid(&format!("bad scale {}", scale))),
+    };
+    // exactly one whitespace character separates the header from the samples
+    pos += 1;
+
+    // the samples are read before the canvas is allocated, so a size the file can't
+    // hold fails here rather than asking for a huge canvas
+    let data = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .and_then(|len| bytes.get(pos..)?.get(..len))
+        .ok_or_else(|| invalid("truncated pixel data"))?;
+    let samples: Vec<f64> = data
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b) as f64
+            } else {
+                f32::from_be_bytes(b) as f64
+            }
+        })
+        .collect();
+
+    let mut canvas = Canvas::new(width, height);
+    for (i, pixel) in samples.chunks_exact(channels).enumerate() {
+        let (x, y) = (i % width, height - 1 - i / width);
+        let color = match pixel {
+            [v] => Color::new(*v, *v, *v),
+            _ => Color::new(pixel[0], pixel[1], pixel[2]),
+        };
+        canvas.write_pixel(x, y, color);
+    }
+    Ok(canvas)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_token(bytes: &[u8], pos: &mut usize) -> io::Result<String> {
+    while bytes.get(*pos).is_some_and(|b| b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    if start == *pos || *pos == bytes.len() {
+        return Err(invalid("unexpected end of header"));
+    }
+    Ok(String::from_utf8_lossy(&bytes[start..*pos]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(canvas: &Canvas) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_pfm(canvas, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn writing_rows_bottom_to_top() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.write_pixel(0, 0, Color::new(1.5, -2.0, 100.0));
+        canvas.write_pixel(1, 1, Color::new(0.25, 0.0, 0.0));
+        let bytes = encode(&canvas);
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(bytes[..header.len()], header[..]);
+        let samples: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(
+            samples,
+            [0.0, 0.0, 0.0, 0.25, 0.0, 0.0, 1.5, -2.0, 100.0, 0.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn round_trip_keeps_values_above_one() {
+        let mut canvas = Canvas::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                canvas.write_pixel(x, y, Color::new(x as f64 * 4.5, y as f64 - 0.5, 1e6));
+            }
+        }
+        let decoded = decode_pfm(&encode(&canvas)).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!(decoded.pixel_at(x, y).equals(&canvas.pixel_at(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn reading_big_endian_greyscale() {
+        let mut bytes = b"Pf 2  1\n1.0\n".to_vec();
+        bytes.extend(0.5f32.to_be_bytes());
+        bytes.extend(8.0f32.to_be_bytes());
+        let canvas = decode_pfm(&bytes).unwrap();
+        assert!(canvas.pixel_at(0, 0).equals(&Color::new(0.5, 0.5, 0.5)));
+        assert!(canvas.pixel_at(1, 0).equals(&Color::new(8.0, 8.0, 8.0)));
+    }
+
+    #[test]
+    fn rejecting_bad_files() {
+        assert!(decode_pfm(b"P6\n1 1\n255\n\0\0\0").is_err());
+        assert!(decode_pfm(b"PF\n1 x\n-1.0\n").is_err());
+        assert!(decode_pfm(b"PF\n1 1\n0\n").is_err());
+        match decode_pfm(b"PF\n1 1\n-1.0\n\0\0\0\0") {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("truncated file decoded"),
+        }
+        let huge = format!("PF\n{} {}\n-1.0\n", usize::MAX / 2, 3);
+        assert!(decode_pfm(huge.as_bytes()).is_err());
+        assert!(decode_pfm(b"PF\n1000000 1000000\n-1.0\n\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::canvas::Canvas;
+use crate::tonemap::DisplayTransform;
+use crate::zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// IDAT payloads are split so no single chunk gets unreasonably large
+const MAX_IDAT: usize = 1 << 20;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// CRC-32 as used by PNG (and zip)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xffff_ffffu32;
+    for &byte in data {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    c ^ 0xffff_ffff
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PngDepth {
+    Eight,
+    Sixteen,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PngOptions {
+    pub depth: PngDepth,
+    // write an RGBA image with the canvas alpha rather than RGB
+    pub alpha: bool,
+    pub transform: DisplayTransform,
+}
+
+impl PngOptions {
+    // 8-bit RGB with the same clipping as the PPM output
+    pub fn new() -> Self {
+        PngOptions {
+            depth: PngDepth::Eight,
+            alpha: false,
+            transform: DisplayTransform::linear(),
+        }
+    }
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc_input = kind.to_vec();
+    crc_input.extend_from_slice(data);
+    out.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Row filtered with PNG filter `kind` (0 none, 1 sub, 2 up, 3 average, 4 paeth);
+// `bpp` is the number of bytes per whole pixel.
+fn filter_row(kind: u8, row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(row.len() + 1);
+    out.push(kind);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+    out
+}
+
+// The usual heuristic: use whichever filter leaves the smallest sum of absolute
+// differences, treating bytes as signed.
+fn best_filtered_row(row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    (0..5)
+        .map(|kind| filter_row(kind, row, previous, bpp))
+        .min_by_key(|f| {
+            f[1..]
+                .iter()
+                .map(|&b| (b as i8).unsigned_abs() as u32)
+                .sum::<u32>()
+        })
+        .expect("there are always five candidates")
+}
+
+pub fn write_png<W: Write>(canvas: &Canvas, writer: W, options: &PngOptions) -> io::Result<()> {
+    let mut out = BufWriter::new(writer);
+    out.write_all(&SIGNATURE)?;
+
+    let (bit_depth, sample_bytes, maxval) = match options.depth {
+        PngDepth::Eight => (8u8, 1, 255u16),
+        PngDepth::Sixteen => (16u8, 2, 65535u16),
+    };
+    let (color_type, channels) = if options.alpha { (6u8, 4) } else { (2u8, 3) };
+    let mut header = Vec::with_capacity(13);
+    header.extend((canvas.width() as u32).to_be_bytes());
+    header.extend((canvas.height() as u32).to_be_bytes());
+    // depth, colour type, deflate compression, adaptive filtering, no interlace
+    header.extend([bit_depth, color_type, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header)?;
+    if options.transform.srgb {
+        // perceptual rendering intent
+        write_chunk(&mut out, b"sRGB", &[0])?;
+    }
+
+    let bpp = channels * sample_bytes;
+    let stride = canvas.width() * bpp;
+    let mut filtered = Vec::with_capacity((stride + 1) * canvas.height());
+    let mut previous = vec![0u8; stride];
+    let mut row = Vec::with_capacity(stride);
+    for y in 0..canvas.height() {
+        row.clear();
+        for x in 0..canvas.width() {
+            let mut samples;
+            if options.alpha {
+                // PNG wants straight colour, and alpha is never tone mapped
+                let rgba = canvas.rgba_at(x, y).unpremultiply();
+                samples = options.transform.quantize(rgba.color, maxval).to_vec();
+                samples.push((rgba.alpha.clamp(0.0, 1.0) * maxval as f64).round() as u16);
+            } else {
+                samples = options
+                    .transform
+                    .quantize(canvas.pixel_at(x, y), maxval)
+                    .to_vec();
+            }
+            for sample in samples {
+                match options.depth {
+                    PngDepth::Eight => row.push(sample as u8),
+                    PngDepth::Sixteen => row.extend(sample.to_be_bytes()),
+                }
+            }
+        }
+        filtered.extend(best_filtered_row(&row, &previous, bpp));
+        std::mem::swap(&mut previous, &mut row);
+    }
+
+    let compressed = zlib::compress(&filtered);
+    for chunk in compressed.chunks(MAX_IDAT) {
+        write_chunk(&mut out, b"IDAT", chunk)?;
+    }
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite::Rgba;
+    use crate::tuple::Color;
+
+    // (kind, data) of every chunk, checking lengths and CRCs on the way
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut pos = 8;
+        let mut result = Vec::new();
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let data = &png[pos + 8..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(&png[pos + 4..pos + 8 + len]), crc);
+            result.push((String::from_utf8(kind.to_vec()).unwrap(), data.to_vec()));
+            pos += 12 + len;
+        }
+        result
+    }
+
+    // undo the row filters, returning the raw rows
+    fn unfilter(data: &[u8], stride: usize, bpp: usize) -> Vec<Vec<u8>> {
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        let mut previous = vec![0u8; stride];
+        for line in data.chunks(stride + 1) {
+            let mut row = vec![0u8; stride];
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = previous[i];
+                let c = if i >= bpp { previous[i - bpp] } else { 0 };
+                let predicted = match line[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    f => panic!("bad filter {}", f),
+                };
+                row[i] = line[i + 1].wrapping_add(predicted);
+            }
+            previous = row.clone();
+            rows.push(row);
+        }
+        rows
+    }
+
+    fn gradient() -> Canvas {
+        let mut c = Canvas::new(7, 5);
+        for y in 0..5 {
+            for x in 0..7 {
+                c.write_pixel(
+                    x,
+                    y,
+                    Color::new(x as f64 / 6.0, y as f64 / 4.0, ((x * y) % 3) as f64 / 2.0),
+                );
+            }
+        }
+        c
+    }
+
+    fn encode(canvas: &Canvas, options: &PngOptions) -> Vec<u8> {
+        let mut png = Vec::new();
+        write_png(canvas, &mut png, options).unwrap();
+        png
+    }
+
+    #[test]
+    fn crc32_checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn eight_bit_rgb() {
+        let canvas = gradient();
+        let png = encode(&canvas, &PngOptions::new());
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 7, 0, 0, 0, 5, 8, 2, 0, 0, 0]);
+
+        let data = zlib::decompress(&chunks[1].1).unwrap();
+        let rows = unfilter(&data, 7 * 3, 3);
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..7 {
+                let (r, g, b) = canvas.pixel_at(x, y).ppm_str();
+                assert_eq!(row[x * 3..x * 3 + 3], [r as u8, g as u8, b as u8]);
+            }
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_rgba() {
+        let canvas = gradient();
+        let mut options = PngOptions::new();
+        options.depth = PngDepth::Sixteen;
+        options.alpha = true;
+        options.transform = DisplayTransform::new();
+        let png = encode(&canvas, &options);
+        let chunks = chunks(&png);
+        assert_eq!(chunks[0].1[8..10], [16, 6]);
+        assert_eq!(chunks[1], ("sRGB".to_string(), vec![0]));
+
+        let data = zlib::decompress(&chunks[2].1).unwrap();
+        let rows = unfilter(&data, 7 * 8, 8);
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..7 {
+                let expected = options.transform.quantize(canvas.pixel_at(x, y), 65535);
+                let sample =
+                    |i: usize| u16::from_be_bytes([row[x * 8 + i * 2], row[x * 8 + i * 2 + 1]]);
+                assert_eq!([sample(0), sample(1), sample(2)], expected);
+                assert_eq!(sample(3), 65535);
+            }
+        }
+    }
+
+    #[test]
+    fn filters_round_trip() {
+        let row: Vec<u8> = (0..24).map(|i| (i * 37 % 256) as u8).collect();
+        let previous: Vec<u8> = (0..24).map(|i| (i * 11 % 256) as u8).collect();
+        for kind in 0..5 {
+            let filtered = filter_row(kind, &row, &previous, 3);
+            let mut data = vec![0u8];
+            data.extend(&previous);
+            data.extend(filtered);
+            assert_eq!(unfilter(&data, 24, 3)[1], row);
+        }
+        // a flat row is best predicted from its left neighbour
+        let flat = vec![9u8; 12];
+        assert_eq!(best_filtered_row(&flat, &[0; 12], 3)[0], 1);
+    }
+
+    #[test]
+    fn alpha_is_written_straight() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_rgba(0, 0, Rgba::new(Color::new(0.5, 0.25, 0.0), 0.5));
+        canvas.write_rgba(1, 0, Rgba::transparent());
+        let mut options = PngOptions::new();
+        options.alpha = true;
+        let chunks = chunks(&encode(&canvas, &options));
+        let data = zlib::decompress(&chunks[1].1).unwrap();
+        let rows = unfilter(&data, 2 * 4, 4);
+        assert_eq!(rows[0], [255, 128, 0, 128, 0, 0, 0, 0]);
+    }
+}
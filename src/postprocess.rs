@@ -0,0 +1,340 @@
+use crate::canvas::Canvas;
+use crate::composite::Rgba;
+use crate::filter::Resample;
+use crate::rng::Rng;
+use crate::tuple::Color;
+
+// Photographic finishing applied to linear HDR renders before tone mapping. Effects
+// change the (premultiplied) colour and leave alpha alone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Effect {
+    // Light above `threshold` luminance glows into its surroundings. The glow is the
+    // average of `scales` blurs, each on a canvas half the size of the last, so every
+    // scale reaches twice as far; `radius` is the blur's sigma in pixels at full size.
+    Bloom {
+        threshold: f64,
+        intensity: f64,
+        radius: f64,
+        scales: usize,
+    },
+    // Darkening towards the edges: `strength` is how much light the corners lose, and
+    // higher `falloff` keeps more of the middle untouched.
+    Vignette {
+        strength: f64,
+        falloff: f64,
+    },
+    // A lens focusing red and blue at slightly different sizes, pushing them apart by
+    // `amount` pixels at the corners and not at all in the middle.
+    ChromaticAberration {
+        amount: f64,
+    },
+    // Monochrome noise proportional to brightness, so black stays black. The same seed
+    // always gives the same grain.
+    Grain {
+        amount: f64,
+        seed: u64,
+    },
+}
+
+impl Effect {
+    pub fn apply(&self, canvas: &Canvas) -> Canvas {
+        match *self {
+            Effect::Bloom {
+                threshold,
+                intensity,
+                radius,
+                scales,
+            } => bloom(canvas, threshold, intensity, radius, scales),
+            Effect::Vignette { strength, falloff } => {
+                let (cx, cy) = centre(canvas);
+                let reach = (cx * cx + cy * cy).sqrt().max(f64::EPSILON);
+                map_colors(canvas, |x, y, color| {
+                    let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                    let r = (dx * dx + dy * dy).sqrt() / reach;
+                    color.scale((1.0 - strength * r.powf(falloff)).max(0.0))
+                })
+            }
+            Effect::ChromaticAberration { amount } => {
+                let (cx, cy) = centre(canvas);
+                let k = amount / (cx * cx + cy * cy).sqrt().max(f64::EPSILON);
+                map_colors(canvas, |x, y, color| {
+                    let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                    // red sampled nearer the middle is magnified, blue further out shrunk
+                    let red = sample(canvas, cx + dx * (1.0 - k), cy + dy * (1.0 - k));
+                    let blue = sample(canvas, cx + dx * (1.0 + k), cy + dy * (1.0 + k));
+                    Color::new(red.r(), color.g(), blue.b())
+                })
+            }
+            Effect::Grain { amount, seed } => {
+                let mut rng = Rng::new(seed);
+                let mut result = canvas.clone();
+                for (x, y, color) in canvas.enumerate_pixels() {
+                    let noise = 2.0 * rng.next_f64() - 1.0;
+                    let rgba = Rgba::new(color.scale(1.0 + amount * noise), canvas.alpha_at(x, y));
+                    result.write_rgba(x, y, rgba);
+                }
+                result
+            }
+        }
+    }
+}
+
+// An ordered stack of effects, each applied to the output of the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcess {
+    pub effects: Vec<Effect>,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        PostProcess {
+            effects: Vec::new(),
+        }
+    }
+
+    pub fn apply(&self, canvas: &Canvas) -> Canvas {
+        self.effects
+            .iter()
+            .fold(canvas.clone(), |image, effect| effect.apply(&image))
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn centre(canvas: &Canvas) -> (f64, f64) {
+    (canvas.width() as f64 / 2.0, canvas.height() as f64 / 2.0)
+}
+
+fn map_colors<F>(canvas: &Canvas, f: F) -> Canvas
+where
+    F: Fn(usize, usize, Color) -> Color,
+{
+    let mut result = canvas.clone();
+    for (x, y, color) in canvas.enumerate_pixels() {
+        let rgba = Rgba::new(f(x, y, color), canvas.alpha_at(x, y));
+        result.write_rgba(x, y, rgba);
+    }
+    result
+}
+
+// bilinear lookup in pixel coordinates, where pixel centres sit at half units
+fn sample(canvas: &Canvas, x: f64, y: f64) -> Color {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let at = |x: f64, y: f64| {
+        let x = (x.max(0.0) as usize).min(canvas.width() - 1);
+        let y = (y.max(0.0) as usize).min(canvas.height() - 1);
+        canvas.pixel_at(x, y)
+    };
+    let top = at(x0, y0).scale(1.0 - tx).add(at(x0 + 1.0, y0).scale(tx));
+    let bottom = at(x0, y0 + 1.0)
+        .scale(1.0 - tx)
+        .add(at(x0 + 1.0, y0 + 1.0).scale(tx));
+    top.scale(1.0 - ty).add(bottom.scale(ty))
+}
+
+fn bloom(canvas: &Canvas, threshold: f64, intensity: f64, radius: f64, scales: usize) -> Canvas {
+    let (width, height) = (canvas.width(), canvas.height());
+    if width == 0 || height == 0 || scales == 0 {
+        return canvas.clone();
+    }
+    // only the light above the threshold glows, keeping each pixel's hue; black pixels
+    // have no hue to keep even when the threshold is negative
+    let mut level = Canvas::new(width, height);
+    for (x, y, color) in canvas.enumerate_pixels() {
+        let luminance = color.luminance();
+        if luminance > threshold && luminance > 0.0 {
+            level.write_pixel(x, y, color.scale((luminance - threshold) / luminance));
+        }
+    }
+
+    let mut glow = Canvas::new(width, height);
+    for scale in 0..scales {
+        if scale > 0 {
+            let (w, h) = (level.width(), level.height());
+            level = level.resize((w / 2).max(1), (h / 2).max(1), Resample::Bilinear);
+        }
+        let blurred = if radius > 0.0 {
+            level.gaussian_blur(radius)
+        } else {
+            level.clone()
+        };
+        let blurred = blurred.resize(width, height, Resample::Bilinear);
+        for (x, y, color) in blurred.enumerate_pixels() {
+            glow.write_pixel(x, y, glow.pixel_at(x, y).add(color));
+        }
+    }
+
+    let weight = intensity / scales as f64;
+    map_colors(canvas, |x, y, color| {
+        color.add(glow.pixel_at(x, y).scale(weight))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(v: f64) -> Color {
+        Color::new(v, v, v)
+    }
+
+    fn flat(width: usize, height: usize, color: Color) -> Canvas {
+        let mut c = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                c.write_pixel(x, y, color);
+            }
+        }
+        c
+    }
+
+    fn same(a: &Canvas, b: &Canvas) -> bool {
+        a.enumerate_pixels()
+            .all(|(x, y, p)| p.equals(&b.pixel_at(x, y)))
+    }
+
+    #[test]
+    fn an_empty_stack_changes_nothing() {
+        let c = flat(4, 3, Color::new(0.2, 3.0, 0.5));
+        assert!(same(&PostProcess::new().apply(&c), &c));
+    }
+
+    #[test]
+    fn bloom_spreads_only_bright_light() {
+        let bloom = Effect::Bloom {
+            threshold: 1.0,
+            intensity: 1.0,
+            radius: 1.0,
+            scales: 3,
+        };
+        let dim = flat(16, 16, grey(0.9));
+        assert!(same(&bloom.apply(&dim), &dim));
+
+        let mut c = Canvas::new(16, 16);
+        c.write_pixel(8, 8, Color::new(20.0, 0.0, 0.0));
+        let bloomed = bloom.apply(&c);
+        assert!(bloomed.pixel_at(8, 8).r() > 20.0);
+        assert!(bloomed.pixel_at(10, 8).r() > 0.0);
+        assert!(bloomed.pixel_at(8, 6).r() > bloomed.pixel_at(8, 2).r());
+        // the glow keeps the colour of its source
+        assert_eq!(bloomed.pixel_at(10, 8).g(), 0.0);
+        // the larger scales reach further than a single blur does
+        let single = Effect::Bloom {
+            threshold: 1.0,
+            intensity: 1.0,
+            radius: 1.0,
+            scales: 1,
+        };
+        assert_eq!(single.apply(&c).pixel_at(8, 1).r(), 0.0);
+        assert!(bloomed.pixel_at(8, 1).r() > 0.0);
+    }
+
+    #[test]
+    fn bloom_without_radius_or_threshold_stays_finite() {
+        let mut c = flat(4, 4, Color::black());
+        c.write_pixel(1, 1, grey(4.0));
+        let bloom = |threshold, radius| Effect::Bloom {
+            threshold,
+            intensity: 1.0,
+            radius,
+            scales: 1,
+        };
+        // no blur leaves the glow where its light is
+        let sharp = bloom(1.0, 0.0).apply(&c);
+        assert!(sharp.pixel_at(1, 1).equals(&grey(7.0)));
+        assert!(sharp.pixel_at(2, 1).equals(&Color::black()));
+        assert!(same(&bloom(1.0, -2.0).apply(&c), &sharp));
+        // with a negative threshold black pixels still don't divide by zero
+        let everything = bloom(-1.0, 1.0).apply(&c);
+        assert!(everything
+            .enumerate_pixels()
+            .all(|(_, _, p)| p.r().is_finite() && p.g().is_finite() && p.b().is_finite()));
+        assert!(everything.pixel_at(2, 1).r() > 0.0);
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners() {
+        let c = flat(10, 10, grey(1.0));
+        let v = Effect::Vignette {
+            strength: 0.5,
+            falloff: 2.0,
+        }
+        .apply(&c);
+        let (middle, edge, corner) = (
+            v.pixel_at(5, 5).r(),
+            v.pixel_at(0, 5).r(),
+            v.pixel_at(0, 0).r(),
+        );
+        assert!(middle > 0.99 && middle > edge && edge > corner);
+        assert!(corner > 0.5);
+        assert!(v.is_opaque());
+    }
+
+    #[test]
+    fn chromatic_aberration_splits_red_and_blue_away_from_the_middle() {
+        let mut c = Canvas::new(21, 21);
+        for y in 0..21 {
+            c.write_pixel(15, y, grey(1.0));
+        }
+        let split = Effect::ChromaticAberration { amount: 3.0 }.apply(&c);
+        // green is untouched
+        assert!(split.pixel_at(15, 10).g() == 1.0 && split.pixel_at(14, 10).g() == 0.0);
+        // out here red lands further from the middle than blue
+        assert!(split.pixel_at(16, 10).r() > 0.0 && split.pixel_at(16, 10).b() == 0.0);
+        assert!(split.pixel_at(14, 10).b() > 0.0 && split.pixel_at(14, 10).r() == 0.0);
+        // nothing moves at the very middle
+        let mut centre = Canvas::new(21, 21);
+        centre.write_pixel(10, 10, grey(1.0));
+        let split = Effect::ChromaticAberration { amount: 3.0 }.apply(&centre);
+        assert!(split.pixel_at(10, 10).equals(&grey(1.0)));
+    }
+
+    #[test]
+    fn grain_is_repeatable() {
+        let mut c = flat(8, 8, grey(0.5));
+        c.write_pixel(0, 0, Color::black());
+        let grain = |seed| Effect::Grain { amount: 0.1, seed }.apply(&c);
+        let (a, b, other) = (grain(3), grain(3), grain(4));
+        assert!(same(&a, &b));
+        assert!(!same(&a, &other));
+        assert!(a.pixel_at(0, 0).equals(&Color::black()));
+        for (_, _, p) in a.enumerate_pixels() {
+            assert!((0.0..=0.55).contains(&p.r()));
+            assert_eq!((p.r(), p.r()), (p.g(), p.b()));
+        }
+    }
+
+    #[test]
+    fn effects_keep_alpha_and_apply_in_order() {
+        let mut c = Canvas::new(9, 9);
+        c.write_rgba(4, 4, Rgba::new(grey(8.0), 0.5));
+        c.write_rgba(0, 0, Rgba::transparent());
+        let bloom = Effect::Bloom {
+            threshold: 1.0,
+            intensity: 0.5,
+            radius: 1.0,
+            scales: 2,
+        };
+        let vignette = Effect::Vignette {
+            strength: 1.0,
+            falloff: 1.0,
+        };
+        let first = PostProcess {
+            effects: vec![bloom, vignette],
+        }
+        .apply(&c);
+        let second = PostProcess {
+            effects: vec![vignette, bloom],
+        }
+        .apply(&c);
+        assert!(!same(&first, &second));
+        assert_eq!(first.alpha_at(4, 4), 0.5);
+        assert_eq!(first.alpha_at(0, 0), 0.0);
+    }
+}
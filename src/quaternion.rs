@@ -0,0 +1,186 @@
+use crate::matrix::Matrix;
+use crate::tuple::Tuple;
+
+// Unit quaternions, only used to represent and interpolate rotations.
+#[derive(Debug, Copy, Clone)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+        Quaternion { w, x, y, z }
+    }
+
+    pub fn identity() -> Self {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    // rotation of `angle` radians around `axis`, matching Matrix::rotation_x/y/z
+    pub fn from_axis_angle(axis: Tuple, angle: f64) -> Self {
+        let axis = axis.normalize();
+        let s = (angle / 2.0).sin();
+        Quaternion::new((angle / 2.0).cos(), axis.x * s, axis.y * s, axis.z * s)
+    }
+
+    // From the upper 3x3 of a matrix that is a pure rotation.
+    pub fn from_matrix(m: &Matrix) -> Self {
+        let v = |r: usize, c: usize| m.get_value(r, c);
+        let trace = v(0, 0) + v(1, 1) + v(2, 2);
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quaternion::new(
+                0.25 * s,
+                (v(2, 1) - v(1, 2)) / s,
+                (v(0, 2) - v(2, 0)) / s,
+                (v(1, 0) - v(0, 1)) / s,
+            )
+        } else if v(0, 0) > v(1, 1) && v(0, 0) > v(2, 2) {
+            let s = (1.0 + v(0, 0) - v(1, 1) - v(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                (v(2, 1) - v(1, 2)) / s,
+                0.25 * s,
+                (v(0, 1) + v(1, 0)) / s,
+                (v(0, 2) + v(2, 0)) / s,
+            )
+        } else if v(1, 1) > v(2, 2) {
+            let s = (1.0 + v(1, 1) - v(0, 0) - v(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                (v(0, 2) - v(2, 0)) / s,
+                (v(0, 1) + v(1, 0)) / s,
+                0.25 * s,
+                (v(1, 2) + v(2, 1)) / s,
+            )
+        } else {
+            let s = (1.0 + v(2, 2) - v(0, 0) - v(1, 1)).sqrt() * 2.0;
+            Quaternion::new(
+                (v(1, 0) - v(0, 1)) / s,
+                (v(0, 2) + v(2, 0)) / s,
+                (v(1, 2) + v(2, 1)) / s,
+                0.25 * s,
+            )
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, other: Self) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    // A zero quaternion has no rotation to keep, so it becomes the identity rather
+    // than NaN.
+    pub fn normalize(&self) -> Self {
+        let len = self.dot(*self).sqrt();
+        if len == 0.0 || !len.is_finite() {
+            return Quaternion::identity();
+        }
+        Quaternion::new(self.w / len, self.x / len, self.y / len, self.z / len)
+    }
+
+    // 4x4 rotation matrix
+    pub fn to_matrix(self) -> Matrix {
+        let Quaternion { w, x, y, z } = self;
+        Matrix::from_vec(&vec![
+            vec![
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            vec![
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            vec![
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            vec![0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // Spherical linear interpolation, always along the shorter arc.
+    pub fn slerp(&self, other: Self, t: f64) -> Self {
+        let mut other = other;
+        let mut cos_theta = self.dot(other);
+        if cos_theta < 0.0 {
+            other = Quaternion::new(-other.w, -other.x, -other.y, -other.z);
+            cos_theta = -cos_theta;
+        }
+
+        let (a, b) = if cos_theta > 0.9995 {
+            // nearly parallel: plain lerp is accurate and avoids dividing by ~0
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Quaternion::new(
+            a * self.w + b * other.w,
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+        )
+        .normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn axis_angle_matches_rotation_matrices() {
+        let angle = PI / 3.0;
+        let cases = [
+            (Tuple::new_vector(1.0, 0.0, 0.0), Matrix::rotation_x(angle)),
+            (Tuple::new_vector(0.0, 1.0, 0.0), Matrix::rotation_y(angle)),
+            (Tuple::new_vector(0.0, 0.0, 1.0), Matrix::rotation_z(angle)),
+        ];
+        for (axis, m) in cases {
+            let q = Quaternion::from_axis_angle(axis, angle);
+            assert!(q.to_matrix().equals(&m));
+        }
+    }
+
+    #[test]
+    fn round_trip_through_matrix() {
+        let m = Matrix::rotation_x(0.4)
+            .multiply_matrix(&Matrix::rotation_y(2.9))
+            .multiply_matrix(&Matrix::rotation_z(-1.3));
+        assert!(Quaternion::from_matrix(&m).to_matrix().equals(&m));
+        let m = Matrix::rotation_y(PI);
+        assert!(Quaternion::from_matrix(&m).to_matrix().equals(&m));
+    }
+
+    #[test]
+    fn slerp_halfway() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle(Tuple::new_vector(0.0, 1.0, 0.0), PI / 2.0);
+        let half = a.slerp(b, 0.5);
+        assert!(half.to_matrix().equals(&Matrix::rotation_y(PI / 4.0)));
+        assert!(a.slerp(b, 0.0).to_matrix().equals(&Matrix::identity()));
+        assert!(a.slerp(b, 1.0).to_matrix().equals(&b.to_matrix()));
+        assert!(math_utils::f64_equals(half.dot(half), 1.0));
+    }
+
+    #[test]
+    fn normalizing_zero_gives_the_identity() {
+        let zero = Quaternion::new(0.0, 0.0, 0.0, 0.0).normalize();
+        assert!(zero.to_matrix().equals(&Matrix::identity()));
+    }
+}
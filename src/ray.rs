@@ -0,0 +1,102 @@
+use crate::matrix::Matrix;
+use crate::tuple::Tuple;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Tuple,
+    pub direction: Tuple,
+    // moment within the shutter interval the ray was sent, for motion blur
+    pub time: f64,
+    // in nanometres, only set when rendering spectrally
+    pub wavelength: Option<f64>,
+}
+
+impl Ray {
+    pub fn new(origin: Tuple, direction: Tuple) -> Self {
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_time(origin: Tuple, direction: Tuple, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelength: None,
+        }
+    }
+
+    pub fn position(&self, t: f64) -> Tuple {
+        self.origin.add(self.direction.multiply(t))
+    }
+
+    pub fn transform(&self, m: &Matrix) -> Self {
+        Ray {
+            origin: m.multiply_tuple(&self.origin),
+            direction: m.multiply_tuple(&self.direction),
+            time: self.time,
+            wavelength: self.wavelength,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creating_ray() {
+        let origin = Tuple::new_point(1.0, 2.0, 3.0);
+        let direction = Tuple::new_vector(4.0, 5.0, 6.0);
+        let r = Ray::new(origin, direction);
+        assert!(r.origin.equals(&origin));
+        assert!(r.direction.equals(&direction));
+    }
+
+    #[test]
+    fn point_from_distance() {
+        let r = Ray::new(
+            Tuple::new_point(2.0, 3.0, 4.0),
+            Tuple::new_vector(1.0, 0.0, 0.0),
+        );
+        assert!(r.position(0.0).equals(&Tuple::new_point(2.0, 3.0, 4.0)));
+        assert!(r.position(1.0).equals(&Tuple::new_point(3.0, 3.0, 4.0)));
+        assert!(r.position(-1.0).equals(&Tuple::new_point(1.0, 3.0, 4.0)));
+        assert!(r.position(2.5).equals(&Tuple::new_point(4.5, 3.0, 4.0)));
+    }
+
+    #[test]
+    fn transforming_ray() {
+        let r = Ray::new(
+            Tuple::new_point(1.0, 2.0, 3.0),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+        );
+        let r2 = r.transform(&Matrix::translation(3.0, 4.0, 5.0));
+        assert!(r2.origin.equals(&Tuple::new_point(4.0, 6.0, 8.0)));
+        assert!(r2.direction.equals(&Tuple::new_vector(0.0, 1.0, 0.0)));
+
+        let r2 = r.transform(&Matrix::scaling(2.0, 3.0, 4.0));
+        assert!(r2.origin.equals(&Tuple::new_point(2.0, 6.0, 12.0)));
+        assert!(r2.direction.equals(&Tuple::new_vector(0.0, 3.0, 0.0)));
+
+        // transforming keeps the time
+        let r = Ray::with_time(
+            Tuple::new_point(1.0, 2.0, 3.0),
+            Tuple::new_vector(0.0, 1.0, 0.0),
+            0.25,
+        );
+        assert_eq!(r.transform(&Matrix::scaling(2.0, 3.0, 4.0)).time, 0.25);
+
+        // and the wavelength
+        let mut r = r;
+        r.wavelength = Some(500.0);
+        assert_eq!(
+            r.transform(&Matrix::translation(1.0, 0.0, 0.0)).wavelength,
+            Some(500.0)
+        );
+    }
+}
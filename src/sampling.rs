@@ -0,0 +1,414 @@
+use std::f64::consts::PI;
+
+use crate::canvas::Canvas;
+use crate::rng::Rng;
+use crate::tuple::{Color, Tuple};
+
+// Where the sub-pixel samples go inside the filter footprint.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplePattern {
+    // centres of an n x n grid
+    Regular,
+    // one random point inside every cell of the n x n grid
+    Jittered,
+}
+
+// Reconstruction filter. Weights are separable and given in pixel units measured from
+// the pixel centre.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian { alpha: f64 },
+    Mitchell { b: f64, c: f64 },
+}
+
+impl Filter {
+    pub fn gaussian() -> Self {
+        Filter::Gaussian { alpha: 2.0 }
+    }
+
+    pub fn mitchell() -> Self {
+        Filter::Mitchell {
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    // half-width of the footprint in pixels
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian { .. } => 1.5,
+            Filter::Mitchell { .. } => 2.0,
+        }
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let r = self.radius();
+        if x > r {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box => 1.0,
+            Filter::Tent => r - x,
+            Filter::Gaussian { alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * r * r).exp()).max(0.0)
+            }
+            Filter::Mitchell { b, c } => {
+                // Mitchell-Netravali is defined on [-2, 2]
+                let x2 = x * x;
+                let x3 = x2 * x;
+                let w = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                w / 6.0
+            }
+        }
+    }
+
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+}
+
+// Per-pixel supersampling. Shading is supplied as a closure taking continuous pixel
+// coordinates (x right, y down, pixel (i, j) covers [i, i+1) x [j, j+1)), so the same
+// sampler works for any camera model.
+#[derive(Debug, Copy, Clone)]
+pub struct Supersampler {
+    pub pattern: SamplePattern,
+    // samples per axis, so n * n samples per pixel
+    pub samples: usize,
+    pub filter: Filter,
+    // When set, the four pixel corners are shaded first and the pixel is only
+    // supersampled if any two corners differ by more than this (Color::distance).
+    pub adaptive_threshold: Option<f64>,
+    pub seed: u64,
+}
+
+impl Supersampler {
+    pub fn new(samples: usize) -> Self {
+        Supersampler {
+            pattern: SamplePattern::Regular,
+            samples,
+            filter: Filter::Box,
+            adaptive_threshold: None,
+            seed: 0,
+        }
+    }
+
+    // sample offsets from the pixel centre, covering the filter footprint
+    pub fn offsets(&self, rng: &mut Rng) -> Vec<(f64, f64)> {
+        let n = self.samples.max(1);
+        let r = self.filter.radius();
+        let cell = 2.0 * r / n as f64;
+        let mut result = Vec::with_capacity(n * n);
+        for j in 0..n {
+            for i in 0..n {
+                let (ju, jv) = match self.pattern {
+                    SamplePattern::Regular => (0.5, 0.5),
+                    SamplePattern::Jittered => (rng.next_f64(), rng.next_f64()),
+                };
+                result.push((-r + (i as f64 + ju) * cell, -r + (j as f64 + jv) * cell));
+            }
+        }
+        result
+    }
+
+    // filtered colour of pixel (x, y)
+    pub fn pixel_color<F>(&self, x: usize, y: usize, rng: &mut Rng, shade: &F) -> Color
+    where
+        F: Fn(f64, f64) -> Color,
+    {
+        let cx = x as f64 + 0.5;
+        let cy = y as f64 + 0.5;
+        let mut sum = Color::black();
+        let mut total_weight = 0.0;
+        for (dx, dy) in self.offsets(rng) {
+            let w = self.filter.weight(dx, dy);
+            if w == 0.0 {
+                continue;
+            }
+            sum = sum.add(shade(cx + dx, cy + dy).scale(w));
+            total_weight += w;
+        }
+        if total_weight == 0.0 {
+            shade(cx, cy)
+        } else {
+            sum.scale(1.0 / total_weight)
+        }
+    }
+
+    pub fn render<F>(&self, width: usize, height: usize, shade: F) -> Canvas
+    where
+        F: Fn(f64, f64) -> Color,
+    {
+        let mut canvas = Canvas::new(width, height);
+        let mut rng = Rng::new(self.seed);
+
+        // corners are shared between neighbouring pixels, so shade them once
+        let corners = self.adaptive_threshold.map(|_| {
+            let mut grid = Vec::with_capacity((width + 1) * (height + 1));
+            for y in 0..=height {
+                for x in 0..=width {
+                    grid.push(shade(x as f64, y as f64));
+                }
+            }
+            grid
+        });
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = match (&corners, self.adaptive_threshold) {
+                    (Some(grid), Some(threshold)) => {
+                        let c = [
+                            grid[y * (width + 1) + x],
+                            grid[y * (width + 1) + x + 1],
+                            grid[(y + 1) * (width + 1) + x],
+                            grid[(y + 1) * (width + 1) + x + 1],
+                        ];
+                        if needs_refinement(&c, threshold) {
+                            self.pixel_color(x, y, &mut rng, &shade)
+                        } else {
+                            c[0].add(c[1]).add(c[2]).add(c[3]).scale(0.25)
+                        }
+                    }
+                    _ => self.pixel_color(x, y, &mut rng, &shade),
+                };
+                canvas.write_pixel(x, y, color);
+            }
+        }
+        canvas
+    }
+}
+
+// Uniform point on the unit disk from two uniform numbers in [0, 1), using the
+// concentric mapping so that stratified inputs stay stratified on the disk.
+pub fn sample_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let a = 2.0 * u1 - 1.0;
+    let b = 2.0 * u2 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, (PI / 4.0) * (b / a))
+    } else {
+        (b, PI / 2.0 - (PI / 4.0) * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// Uniform point inside a regular polygon inscribed in the unit circle, used for
+// bladed-aperture bokeh. `u3` picks the wedge, `u1` and `u2` the point inside it.
+pub fn sample_polygon(sides: usize, rotation: f64, u1: f64, u2: f64, u3: f64) -> (f64, f64) {
+    let sides = sides.max(3);
+    let wedge = ((u3 * sides as f64) as usize).min(sides - 1);
+    let a0 = rotation + 2.0 * PI * wedge as f64 / sides as f64;
+    let a1 = rotation + 2.0 * PI * (wedge + 1) as f64 / sides as f64;
+    // uniform point in the triangle (centre, corner a0, corner a1)
+    let s = u1.sqrt();
+    let w0 = s * (1.0 - u2);
+    let w1 = s * u2;
+    (w0 * a0.cos() + w1 * a1.cos(), w0 * a0.sin() + w1 * a1.sin())
+}
+
+// Two unit vectors that together with `n` form an orthonormal basis.
+pub fn orthonormal_basis(n: Tuple) -> (Tuple, Tuple) {
+    let helper = if n.x.abs() > 0.9 {
+        Tuple::new_vector(0.0, 1.0, 0.0)
+    } else {
+        Tuple::new_vector(1.0, 0.0, 0.0)
+    };
+    let t = helper.cross(n).normalize();
+    let b = n.cross(t);
+    (t, b)
+}
+
+// Direction in the hemisphere around `normal` with pdf cos(theta) / pi.
+pub fn cosine_hemisphere(normal: Tuple, u1: f64, u2: f64) -> Tuple {
+    let (x, y) = sample_disk(u1, u2);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    let (t, b) = orthonormal_basis(normal);
+    t.multiply(x)
+        .add(b.multiply(y))
+        .add(normal.multiply(z))
+        .normalize()
+}
+
+// MIS weight for a sample drawn with pdf `a` when the other strategy has pdf `b`.
+pub fn power_heuristic(a: f64, b: f64) -> f64 {
+    let a2 = a * a;
+    let b2 = b * b;
+    if a2 + b2 == 0.0 {
+        0.0
+    } else {
+        a2 / (a2 + b2)
+    }
+}
+
+fn needs_refinement(corners: &[Color; 4], threshold: f64) -> bool {
+    for i in 0..corners.len() {
+        for j in i + 1..corners.len() {
+            if corners[i].distance(&corners[j]) > threshold {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    // white left of x = 2.5, black to the right
+    fn edge(x: f64, _y: f64) -> Color {
+        if x < 2.5 {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            Color::black()
+        }
+    }
+
+    #[test]
+    fn filter_weights() {
+        assert!(math_utils::f64_equals(Filter::Box.weight(0.4, -0.4), 1.0));
+        assert!(math_utils::f64_equals(Filter::Box.weight(0.6, 0.0), 0.0));
+        assert!(math_utils::f64_equals(Filter::Tent.weight(0.5, 0.0), 0.5));
+        assert!(math_utils::f64_equals(Filter::Tent.weight(0.5, 0.5), 0.25));
+        assert!(Filter::gaussian().weight(0.0, 0.0) > Filter::gaussian().weight(1.0, 0.0));
+        assert!(math_utils::f64_equals(
+            Filter::gaussian().weight(1.5, 0.0),
+            0.0
+        ));
+
+        let m = Filter::mitchell();
+        assert!(math_utils::f64_equals(m.weight_1d(0.0), 8.0 / 9.0));
+        assert!(math_utils::f64_equals(m.weight_1d(2.0), 0.0));
+        // negative lobe
+        assert!(m.weight_1d(1.5) < 0.0);
+    }
+
+    #[test]
+    fn disk_samples() {
+        assert_eq!(sample_disk(0.5, 0.5), (0.0, 0.0));
+        let (x, y) = sample_disk(1.0, 0.5);
+        assert!(math_utils::f64_equals(x, 1.0));
+        assert!(math_utils::f64_equals(y, 0.0));
+        let mut rng = Rng::new(2);
+        for _ in 0..200 {
+            let (x, y) = sample_disk(rng.next_f64(), rng.next_f64());
+            assert!(x * x + y * y <= 1.0 + math_utils::EPSILON);
+        }
+    }
+
+    #[test]
+    fn polygon_samples_stay_inside() {
+        // square aperture rotated 45 degrees has its edges at |x| + |y| <= 1
+        let mut rng = Rng::new(8);
+        for _ in 0..200 {
+            let (x, y) = sample_polygon(4, 0.0, rng.next_f64(), rng.next_f64(), rng.next_f64());
+            assert!(x.abs() + y.abs() <= 1.0 + math_utils::EPSILON);
+        }
+    }
+
+    #[test]
+    fn cosine_weighted_directions() {
+        let n = Tuple::new_vector(0.0, 0.0, 1.0).normalize();
+        let mut rng = Rng::new(12);
+        let count = 4000;
+        let mut mean_cos = 0.0;
+        for _ in 0..count {
+            let d = cosine_hemisphere(n, rng.next_f64(), rng.next_f64());
+            assert!(math_utils::f64_equals(d.magnitude(), 1.0));
+            assert!(d.dot(n) >= 0.0);
+            mean_cos += d.dot(n);
+        }
+        // E[cos] under a cosine-weighted pdf is 2/3
+        mean_cos /= count as f64;
+        assert!((mean_cos - 2.0 / 3.0).abs() < 0.02);
+
+        let n = Tuple::new_vector(1.0, 0.0, 0.0);
+        let (t, b) = orthonormal_basis(n);
+        assert!(math_utils::f64_equals(t.dot(n), 0.0));
+        assert!(math_utils::f64_equals(b.dot(n), 0.0));
+        assert!(math_utils::f64_equals(t.dot(b), 0.0));
+    }
+
+    #[test]
+    fn power_heuristic_weights() {
+        assert!(math_utils::f64_equals(power_heuristic(1.0, 1.0), 0.5));
+        assert!(math_utils::f64_equals(power_heuristic(3.0, 1.0), 0.9));
+        assert!(math_utils::f64_equals(
+            power_heuristic(3.0, 1.0) + power_heuristic(1.0, 3.0),
+            1.0
+        ));
+        assert!(math_utils::f64_equals(power_heuristic(0.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn regular_offsets() {
+        let s = Supersampler::new(2);
+        let offsets = s.offsets(&mut Rng::new(0));
+        assert_eq!(
+            offsets,
+            vec![(-0.25, -0.25), (0.25, -0.25), (-0.25, 0.25), (0.25, 0.25)]
+        );
+    }
+
+    #[test]
+    fn jittered_offsets_are_stratified() {
+        let mut s = Supersampler::new(4);
+        s.pattern = SamplePattern::Jittered;
+        let offsets = s.offsets(&mut Rng::new(5));
+        assert_eq!(offsets.len(), 16);
+        for (k, (dx, dy)) in offsets.iter().enumerate() {
+            let i = (k % 4) as f64;
+            let j = (k / 4) as f64;
+            assert!(*dx >= -0.5 + i * 0.25 && *dx < -0.5 + (i + 1.0) * 0.25);
+            assert!(*dy >= -0.5 + j * 0.25 && *dy < -0.5 + (j + 1.0) * 0.25);
+        }
+    }
+
+    #[test]
+    fn supersampling_softens_edges() {
+        // one sample at the pixel centre lands exactly on the edge
+        let single = Supersampler::new(1).render(5, 1, edge);
+        assert!(single.pixel_at(2, 0).equals(&Color::black()));
+
+        let s = Supersampler::new(4);
+        let c = s.render(5, 1, edge);
+        assert!(c.pixel_at(0, 0).equals(&Color::new(1.0, 1.0, 1.0)));
+        assert!(c.pixel_at(2, 0).equals(&Color::new(0.5, 0.5, 0.5)));
+        assert!(c.pixel_at(4, 0).equals(&Color::black()));
+    }
+
+    #[test]
+    fn adaptive_only_refines_edges() {
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let shade = |x: f64, y: f64| {
+            calls.set(calls.get() + 1);
+            edge(x, y)
+        };
+        let mut s = Supersampler::new(4);
+        s.adaptive_threshold = Some(0.1);
+        let c = s.render(5, 1, shade);
+        // 12 corners, then 16 samples for the single pixel straddling the edge
+        assert_eq!(calls.get(), 12 + 16);
+        assert!(c.pixel_at(2, 0).equals(&Color::new(0.5, 0.5, 0.5)));
+        assert!(c.pixel_at(1, 0).equals(&Color::new(1.0, 1.0, 1.0)));
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::OnceLock;
+
+use crate::colorspace::ColorSpace;
+use crate::tuple::Color;
+
+// Visible range sampled in spectral mode, in nanometres.
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 780.0;
+
+// Fraunhofer d line, used whenever a wavelength-dependent quantity is needed by a ray
+// that doesn't carry a wavelength.
+pub const REFERENCE_WAVELENGTH: f64 = 587.56;
+
+// piecewise gaussian with different widths either side of the peak
+fn lobe(x: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if x < mu { sigma_below } else { sigma_above };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 2-degree colour matching functions, using the multi-lobe fit of Wyman,
+// Sloan and Shirley (2013) rather than the tabulated data.
+pub fn cie_xyz(wavelength: f64) -> (f64, f64, f64) {
+    let l = wavelength;
+    let x = 1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// CIE XYZ to linear sRGB (D65 white), with the same matrix as the colour spaces use
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    ColorSpace::LinearSrgb.xyz_to_rgb((x, y, z))
+}
+
+// Uniform wavelength across the visible range for a random number in [0, 1).
+pub fn sample_wavelength(u: f64) -> f64 {
+    MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+}
+
+// RGB weight of a single uniformly sampled wavelength, scaled so that averaging over
+// the visible range gives white: an RGB result traced at that wavelength, multiplied
+// by this, is an unbiased one-sample estimate of the spectral render. Some components
+// are negative for saturated wavelengths outside the sRGB gamut.
+pub fn wavelength_weight(wavelength: f64) -> Color {
+    let (x, y, z) = cie_xyz(wavelength);
+    let rgb = xyz_to_rgb(x, y, z);
+    let white = white_balance();
+    Color::new(
+        rgb.r() / white.r(),
+        rgb.g() / white.g(),
+        rgb.b() / white.b(),
+    )
+}
+
+// mean RGB response over the sampled range, worked out once
+fn white_balance() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = 4000;
+        let mut sum = Color::black();
+        for i in 0..steps {
+            let (x, y, z) = cie_xyz(sample_wavelength((i as f64 + 0.5) / steps as f64));
+            sum = sum.add(xyz_to_rgb(x, y, z));
+        }
+        sum.scale(1.0 / steps as f64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luminance_peaks_near_555nm() {
+        let (_, y, _) = cie_xyz(555.0);
+        assert!((y - 1.0).abs() < 0.03);
+        assert!(cie_xyz(450.0).1 < 0.1);
+        assert!(cie_xyz(700.0).1 < 0.01);
+        // x has its secondary lobe in the blue
+        assert!(cie_xyz(440.0).0 > 0.3);
+    }
+
+    #[test]
+    fn xyz_white_point_maps_to_white() {
+        let c = xyz_to_rgb(0.9505, 1.0, 1.089);
+        assert!((c.r() - 1.0).abs() < 1e-3);
+        assert!((c.g() - 1.0).abs() < 1e-3);
+        assert!((c.b() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn weights_average_to_white() {
+        let steps = 1000;
+        let mut sum = Color::black();
+        for i in 0..steps {
+            let u = (i as f64 + 0.5) / steps as f64;
+            sum = sum.add(wavelength_weight(sample_wavelength(u)));
+        }
+        let mean = sum.scale(1.0 / steps as f64);
+        assert!(mean.distance(&Color::new(1.0, 1.0, 1.0)) < 1e-3);
+    }
+
+    #[test]
+    fn wavelengths_have_their_hue() {
+        let blue = wavelength_weight(450.0);
+        assert!(blue.b() > blue.r() && blue.b() > blue.g());
+        let green = wavelength_weight(530.0);
+        assert!(green.g() > green.r() && green.g() > green.b());
+        let red = wavelength_weight(640.0);
+        assert!(red.r() > red.g() && red.r() > red.b());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::tuple::Color;
+
+// smallest white point the operators use, so a white of 0 can't divide by zero
+const MIN_WHITE: f64 = 1e-3;
+
+// Operators that squeeze unbounded linear radiance into [0, 1] before display.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMap {
+    // hard clip at 1, which is what the plain PPM output has always done
+    Clamp,
+    // c / (1 + c): never quite reaches white
+    Reinhard,
+    // Reinhard with `white` (and anything brighter) mapped to 1; whites below MIN_WHITE
+    // are raised to it
+    ExtendedReinhard { white: f64 },
+    // Narkowicz's curve fit of the ACES filmic reference transform
+    AcesFilmic,
+    // Hable's filmic curve from Uncharted 2, normalised so `white` maps to 1; as above,
+    // at least MIN_WHITE
+    Uncharted2 { white: f64 },
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        let f = |c: f64| self.apply_channel(c.max(0.0)).clamp(0.0, 1.0);
+        Color::new(f(color.r()), f(color.g()), f(color.b()))
+    }
+
+    fn apply_channel(&self, c: f64) -> f64 {
+        match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (1.0 + c),
+            ToneMap::ExtendedReinhard { white } => {
+                let white = white.max(MIN_WHITE);
+                c * (1.0 + c / (white * white)) / (1.0 + c)
+            }
+            ToneMap::AcesFilmic => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+            ToneMap::Uncharted2 { white } => hable(c) / hable(white.max(MIN_WHITE)),
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+// sRGB transfer function: linear [0, 1] to the encoded value displays expect
+pub fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_decode(encoded: f64) -> f64 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Everything between a rendered colour and a display value: exposure in stops, a tone
+// map, then optionally the sRGB curve.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DisplayTransform {
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    pub srgb: bool,
+}
+
+impl DisplayTransform {
+    // what most viewers expect: Reinhard and sRGB encoding
+    pub fn new() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            tone_map: ToneMap::Reinhard,
+            srgb: true,
+        }
+    }
+
+    // clip and write linear values as they are, matching Canvas::to_ppm
+    pub fn linear() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            srgb: false,
+        }
+    }
+
+    // display value of every channel, in [0, 1]
+    pub fn apply(&self, color: Color) -> Color {
+        let mapped = self.tone_map.apply(color.scale(2f64.powf(self.exposure)));
+        if self.srgb {
+            Color::new(
+                srgb_encode(mapped.r()),
+                srgb_encode(mapped.g()),
+                srgb_encode(mapped.b()),
+            )
+        } else {
+            mapped
+        }
+    }
+
+    // integer samples in 0..=maxval, e.g. 255 for 8-bit or 65535 for 16-bit output
+    pub fn quantize(self, color: Color, maxval: u16) -> [u16; 3] {
+        let c = self.apply(color);
+        let q = |v: f64| (v * maxval as f64).round() as u16;
+        [q(c.r()), q(c.g()), q(c.b())]
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    const OPERATORS: [ToneMap; 5] = [
+        ToneMap::Clamp,
+        ToneMap::Reinhard,
+        ToneMap::ExtendedReinhard { white: 4.0 },
+        ToneMap::AcesFilmic,
+        ToneMap::Uncharted2 { white: 11.2 },
+    ];
+
+    fn grey(v: f64) -> Color {
+        Color::new(v, v, v)
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_bounded() {
+        for op in OPERATORS {
+            assert!(op.apply(grey(0.0)).equals(&grey(0.0)), "{:?}", op);
+            let mut last = 0.0;
+            for i in 1..200 {
+                let v = op.apply(grey(i as f64 * 0.1)).r();
+                assert!(v >= last && v <= 1.0, "{:?} at {}", op, i);
+                last = v;
+            }
+            // negative light is treated as black
+            assert!(op.apply(grey(-1.0)).equals(&grey(0.0)));
+        }
+    }
+
+    #[test]
+    fn operator_values() {
+        assert!(ToneMap::Reinhard.apply(grey(1.0)).equals(&grey(0.5)));
+        assert!(ToneMap::Clamp.apply(grey(3.0)).equals(&grey(1.0)));
+        let extended = ToneMap::ExtendedReinhard { white: 4.0 };
+        assert!(extended.apply(grey(4.0)).equals(&grey(1.0)));
+        assert!(extended.apply(grey(1.0)).r() > ToneMap::Reinhard.apply(grey(1.0)).r());
+        let uncharted = ToneMap::Uncharted2 { white: 11.2 };
+        assert!(uncharted.apply(grey(11.2)).equals(&grey(1.0)));
+        // ACES saturates a little below 16
+        assert!(ToneMap::AcesFilmic.apply(grey(16.0)).equals(&grey(1.0)));
+        assert!((ToneMap::AcesFilmic.apply(grey(0.18)).r() - 0.267).abs() < 1e-3);
+    }
+
+    #[test]
+    fn degenerate_white_points_stay_finite() {
+        for white in [0.0, -2.0, f64::NAN] {
+            for op in [
+                ToneMap::ExtendedReinhard { white },
+                ToneMap::Uncharted2 { white },
+            ] {
+                assert!(op.apply(grey(0.0)).equals(&grey(0.0)), "{:?}", op);
+                // everything brighter than the tiny white point is white
+                assert!(op.apply(grey(0.5)).equals(&grey(1.0)), "{:?}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        assert!(math_utils::f64_equals(srgb_encode(0.0), 0.0));
+        assert!(math_utils::f64_equals(srgb_encode(1.0), 1.0));
+        // middle grey lands just above half way
+        assert!((srgb_encode(0.18) - 0.4614).abs() < 1e-4);
+        for i in 0..=100 {
+            let v = i as f64 / 100.0;
+            assert!(math_utils::f64_equals(srgb_decode(srgb_encode(v)), v));
+        }
+    }
+
+    #[test]
+    fn display_transforms() {
+        let linear = DisplayTransform::linear();
+        for c in [
+            grey(1.5),
+            Color::new(0.0, 0.5, -0.5),
+            Color::new(1.0, 0.8, 0.6),
+        ] {
+            let (r, g, b) = c.ppm_str();
+            assert_eq!(linear.quantize(c, 255), [r as u16, g as u16, b as u16]);
+        }
+
+        let mut t = DisplayTransform::new();
+        // Reinhard(1) = 0.5, which sRGB encodes to 188
+        assert_eq!(t.quantize(grey(1.0), 255), [188, 188, 188]);
+        assert_eq!(t.quantize(grey(1.0), 65535), [48_192, 48_192, 48_192]);
+        t.exposure = -1.0;
+        t.srgb = false;
+        // half the light: 0.5 / 1.5
+        assert_eq!(t.quantize(grey(1.0), 255), [85, 85, 85]);
+    }
+}
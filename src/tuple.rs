@@ -108,6 +108,11 @@ impl Tuple {
         )
     }
 
+    // reflect this vector around `normal`
+    pub fn reflect(&self, normal: Self) -> Self {
+        self.minus(normal.multiply(2.0 * self.dot(normal)))
+    }
+
     pub fn to_vec(&self) -> Vec<f64> {
         vec![self.x, self.y, self.z, self.w]
     }
@@ -162,6 +167,21 @@ impl Color {
         self.tuple.equals(&other.tuple)
     }
 
+    // euclidean distance between the two colours in rgb
+    pub fn distance(&self, other: &Self) -> f64 {
+        self.tuple.minus(other.tuple).magnitude()
+    }
+
+    // t = 0 gives self, t = 1 gives other
+    pub fn lerp(&self, other: Self, t: f64) -> Self {
+        self.add(other.minus(*self).scale(t))
+    }
+
+    // relative luminance of linear rgb with Rec. 709 primaries
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r() + 0.7152 * self.g() + 0.0722 * self.b()
+    }
+
     pub fn black() -> Self {
         Color::new(0.0, 0.0, 0.0)
     }
@@ -319,6 +339,18 @@ mod tests {
         assert!(b.cross(a).equals(&Tuple::new_vector(1.0, -2.0, 1.0)));
     }
 
+    #[test]
+    fn reflecting_vectors() {
+        let v = Tuple::new_vector(1.0, -1.0, 0.0);
+        let n = Tuple::new_vector(0.0, 1.0, 0.0);
+        assert!(v.reflect(n).equals(&Tuple::new_vector(1.0, 1.0, 0.0)));
+
+        let v = Tuple::new_vector(0.0, -1.0, 0.0);
+        let s = 2.0_f64.sqrt() / 2.0;
+        let n = Tuple::new_vector(s, s, 0.0);
+        assert!(v.reflect(n).equals(&Tuple::new_vector(1.0, 0.0, 0.0)));
+    }
+
     #[test]
     fn color_operations() {
         let c1 = Color::new(0.9, 0.6, 0.75);
@@ -335,6 +367,17 @@ mod tests {
         let c1 = Color::new(1.0, 0.2, 0.4);
         let c2 = Color::new(0.9, 1.0, 0.1);
         assert!(c1.multiply(c2).equals(&Color::new(0.9, 0.2, 0.04)));
+
+        let c1 = Color::new(0.0, 0.5, 1.0);
+        let c2 = Color::new(1.0, 0.5, 0.0);
+        assert!(c1.lerp(c2, 0.25).equals(&Color::new(0.25, 0.5, 0.75)));
+
+        let c1 = Color::new(0.1, 0.2, 0.3);
+        let c2 = Color::new(0.1, 0.5, 0.7);
+        assert!(math_utils::f64_equals(c1.distance(&c2), 0.5));
+
+        assert!(math_utils::f64_equals(Color::new(1.0, 1.0, 1.0).luminance(), 1.0));
+        assert!(math_utils::f64_equals(Color::new(0.0, 1.0, 0.0).luminance(), 0.7152));
     }
 
     // #[test]
@@ -0,0 +1,104 @@
+use std::f64::consts::PI;
+
+use crate::tuple::Tuple;
+
+// Texture coordinates for the basic primitives. u and v are both in [0, 1].
+
+// Point on (or around) the unit sphere at the origin. u runs around the y axis,
+// v from the south pole (0) to the north pole (1).
+pub fn spherical_map(point: Tuple) -> (f64, f64) {
+    let theta = point.x.atan2(point.z);
+    let vec = Tuple::new_vector(point.x, point.y, point.z);
+    let radius = vec.magnitude();
+    let phi = (point.y / radius).acos();
+    let raw_u = theta / (2.0 * PI);
+    let u = 1.0 - (raw_u + 0.5);
+    let v = 1.0 - phi / PI;
+    (u, v)
+}
+
+// Direction of increasing u on the sphere, used as the tangent for normal mapping.
+pub fn spherical_tangent(point: Tuple) -> Tuple {
+    // u decreases with theta = atan2(x, z), so the tangent is -d/dtheta
+    let t = Tuple::new_vector(-point.z, 0.0, point.x);
+    if t.magnitude() == 0.0 {
+        // at the poles any horizontal direction will do
+        Tuple::new_vector(1.0, 0.0, 0.0)
+    } else {
+        t.normalize()
+    }
+}
+
+// The xz plane, tiled once per unit.
+pub fn planar_map(point: Tuple) -> (f64, f64) {
+    let u = point.x - point.x.floor();
+    let v = point.z - point.z.floor();
+    (u, v)
+}
+
+pub fn planar_tangent() -> Tuple {
+    Tuple::new_vector(1.0, 0.0, 0.0)
+}
+
+// One of the mappings above, chosen per material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UvMapping {
+    Spherical,
+    Planar,
+}
+
+impl UvMapping {
+    pub fn map(&self, point: Tuple) -> (f64, f64) {
+        match self {
+            UvMapping::Spherical => spherical_map(point),
+            UvMapping::Planar => planar_map(point),
+        }
+    }
+
+    pub fn tangent(&self, point: Tuple) -> Tuple {
+        match self {
+            UvMapping::Spherical => spherical_tangent(point),
+            UvMapping::Planar => planar_tangent(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math_utils;
+
+    #[test]
+    fn spherical_mapping() {
+        let cases = [
+            (Tuple::new_point(0.0, 0.0, -1.0), 0.0, 0.5),
+            (Tuple::new_point(1.0, 0.0, 0.0), 0.25, 0.5),
+            (Tuple::new_point(0.0, 0.0, 1.0), 0.5, 0.5),
+            (Tuple::new_point(-1.0, 0.0, 0.0), 0.75, 0.5),
+            (Tuple::new_point(0.0, 1.0, 0.0), 0.5, 1.0),
+            (Tuple::new_point(0.0, -1.0, 0.0), 0.5, 0.0),
+        ];
+        for (p, u, v) in cases {
+            let (pu, pv) = spherical_map(p);
+            assert!(math_utils::f64_equals(pu, u));
+            assert!(math_utils::f64_equals(pv, v));
+        }
+    }
+
+    #[test]
+    fn spherical_tangent_follows_u() {
+        let p = Tuple::new_point(0.0, 0.0, -1.0);
+        let t = spherical_tangent(p);
+        let (u0, _) = spherical_map(p);
+        let (u1, _) = spherical_map(p.add(t.multiply(0.001)));
+        assert!(u1 > u0);
+        assert!(math_utils::f64_equals(t.magnitude(), 1.0));
+    }
+
+    #[test]
+    fn planar_mapping() {
+        let (u, v) = planar_map(Tuple::new_point(-0.25, 3.0, 1.75));
+        assert!(math_utils::f64_equals(u, 0.75));
+        assert!(math_utils::f64_equals(v, 0.75));
+    }
+}
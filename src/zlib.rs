@@ -0,0 +1,501 @@
+use std::io;
+
+// Minimal zlib (RFC 1950) around DEFLATE (RFC 1951), shared by the PNG and EXR code.
+// Compression is LZ77 with hash chains and the fixed Huffman codes, which is simple and
+// does well on rendered images; decompression handles every block type.
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+// how many earlier positions are tried per match; more is slower but smaller
+const MAX_CHAIN: usize = 128;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(invalid("zlib stream too short"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(invalid("bad zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(invalid("preset dictionaries are not supported"));
+    }
+    let out = inflate(&data[2..])?;
+    let end = data.len();
+    let expected = u32::from_be_bytes([data[end - 4], data[end - 3], data[end - 2], data[end - 1]]);
+    if adler32(&out) != expected {
+        return Err(invalid("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    // the low `count` bits of `value`, least significant first
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        for bit in (0..length).rev() {
+            self.write_bits((code >> bit) & 1, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+// fixed literal/length code from RFC 1951 section 3.2.6
+fn write_fixed_symbol(writer: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+// index of the last base not above `value`
+fn code_index(bases: &[u16], value: usize) -> usize {
+    bases.partition_point(|&b| b as usize <= value) - 1
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let h = ((data[i] as usize) << 10) ^ ((data[i + 1] as usize) << 5) ^ data[i + 2] as usize;
+    h & ((1 << HASH_BITS) - 1)
+}
+
+// remember position i in the hash chains
+fn insert(data: &[u8], head: &mut [usize], prev: &mut [usize], i: usize) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(data, i);
+        prev[i % WINDOW_SIZE] = head[h];
+        head[h] = i;
+    }
+}
+
+// Raw DEFLATE data as a single block using the fixed Huffman codes.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write_bits(1, 1); // final block
+    writer.write_bits(1, 2); // fixed codes
+
+    // most recent position for each hash, and the previous one with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            let l = code_index(&LENGTH_BASE, best_len);
+            write_fixed_symbol(&mut writer, 257 + l as u16);
+            writer.write_bits(
+                (best_len - LENGTH_BASE[l] as usize) as u32,
+                LENGTH_EXTRA[l] as u32,
+            );
+            let d = code_index(&DISTANCE_BASE, best_dist);
+            writer.write_code(d as u32, 5);
+            writer.write_bits(
+                (best_dist - DISTANCE_BASE[d] as usize) as u32,
+                DISTANCE_EXTRA[d] as u32,
+            );
+            for j in i..i + best_len {
+                insert(data, &mut head, &mut prev, j);
+            }
+            i += best_len;
+        } else {
+            write_fixed_symbol(&mut writer, data[i] as u16);
+            insert(data, &mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    write_fixed_symbol(&mut writer, 256);
+    writer.finish()
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("unexpected end of deflate data"))?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    // drop to the next byte boundary, for stored blocks
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman decoding table: how many codes there are of each length, and the
+// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad huffman code"))
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_table = Huffman::new(&lengths);
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_table.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(invalid("repeat with no previous length"));
+                }
+                (lengths[i - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(invalid("too many code lengths"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+// Raw DEFLATE data back to bytes.
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if len != !nlen & 0xffff {
+                    return Err(invalid("stored block length mismatch"));
+                }
+                reader.pos += 4;
+                let block = data
+                    .get(reader.pos..reader.pos + len)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                out.extend_from_slice(block);
+                reader.pos += len;
+            }
+            btype @ (1 | 2) => {
+                let (literals, distances) = if btype == 1 {
+                    fixed_tables()
+                } else {
+                    dynamic_tables(&mut reader)?
+                };
+                loop {
+                    let symbol = literals.decode(&mut reader)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let l = symbol - 257;
+                    if l >= LENGTH_BASE.len() {
+                        return Err(invalid("bad length code"));
+                    }
+                    let len =
+                        LENGTH_BASE[l] as usize + reader.bits(LENGTH_EXTRA[l] as u32)? as usize;
+                    let d = distances.decode(&mut reader)? as usize;
+                    if d >= DISTANCE_BASE.len() {
+                        return Err(invalid("bad distance code"));
+                    }
+                    let dist =
+                        DISTANCE_BASE[d] as usize + reader.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+                    if dist > out.len() {
+                        return Err(invalid("distance before start of data"));
+                    }
+                    let start = out.len() - dist;
+                    // byte by byte, since a match may overlap what it is copying
+                    for k in 0..len {
+                        out.push(out[start + k]);
+                    }
+                }
+            }
+            _ => return Err(invalid("reserved block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn adler32_checksum() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+        // long enough to need the modulo
+        assert_eq!(adler32(&vec![255u8; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut noise = Vec::new();
+        let mut x = 12345u32;
+        for _ in 0..70_000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            noise.push((x >> 16) as u8);
+        }
+        let cases: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            b"hello hello hello hello".to_vec(),
+            vec![7u8; 100_000],
+            (0..50_000).map(|i| (i % 251) as u8).collect(),
+            noise,
+        ];
+        for data in cases {
+            let compressed = compress(&data);
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        // runs compress well
+        assert!(compress(&vec![0u8; 100_000]).len() < 1000);
+    }
+
+    #[test]
+    fn inflates_other_encoders_output() {
+        // fixed codes
+        let fixed = from_hex("78dacb48cdc9c957c8402701680308b1");
+        assert_eq!(decompress(&fixed).unwrap(), b"hello hello hello hello");
+
+        // a stored block
+        let stored = from_hex("7801010c00f3ff73746f7265642064617461211f1f046d");
+        assert_eq!(decompress(&stored).unwrap(), b"stored data!");
+
+        // dynamic codes
+        let dynamic = from_hex(
+            "78da8554410e83300cfb0a5f6bb50a26950d699cfafa498b602676c2a512266d1cc7c95cd6b54c6d
+             2f538d8ed2b7e53801d001f06ffe3d6de7befc030d7149daf679f6f72b4df6687de7eba35d414c6b
+             27711a9e0ce5433808b192aacb6e285e0914012608547f1d2af7b9aad4d41800bbea7832b9437c2d
+             a5f547bd317cf7f1a7711e894be6d37bd4105f257c5f295f7d13d860f8ce005ce39a533f685a6a7e
+             a87941a1f205d64bcf867ee7d62cc4ead0137b0841009cb648cd7ea38d272139812dc1d6c9d8674b
+             c40f3045b1e2890db5b4b7aa203c68078a8518ac8faaefa66e0dc4a13c264f30992444e050d2d86d
+             6c5dd617e4b55750",
+        );
+        let text = decompress(&dynamic).unwrap();
+        assert_eq!(text.len(), 1685);
+        assert!(text.starts_with(b"gamma eta beta beta beta beta "));
+    }
+
+    #[test]
+    fn rejects_corrupt_streams() {
+        let mut data = compress(b"some data to corrupt");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decompress(&data).is_err());
+        assert!(decompress(&[0x78, 0x9c, 0xff]).is_err());
+        assert!(decompress(&[0x78, 0x9d, 0, 0, 0, 0, 0]).is_err());
+        // truncated
+        let data = compress(b"some data to truncate, some data to truncate");
+        assert!(decompress(&data[..data.len() / 2]).is_err());
+    }
+}